@@ -296,6 +296,15 @@ export function DataSegmentRef(props: {
   });
 }
 
+export function TagRef(props: {
+  index: number;
+}): Node {
+  return Reference({
+    text: `tag ${props.index}`,
+    // TODO: goto
+  });
+}
+
 export function Tip(props: {
   text: string;
   tooltip: string;
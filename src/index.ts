@@ -2,8 +2,8 @@ import { parse } from "./parse";
 import wasmUrl from "../wasm-tools/pkg/wasm_viewer_bg.wasm";
 import wasmInit, { BinaryError, Export, Import, IndirectNamingResultArray, Name } from "../wasm-tools/pkg";
 import { Module, Section, WASM_PAGE_SIZE, bytesToString, funcTypeToString, memoryTypeToString } from "./types";
-import { DataSegmentRef, E, ElementSegmentRef, F, FunctionRef, GlobalRef, ItemCount, Items, KindChip, MemoryRef, N, NameSection, RefTypeRef, Reference, ScrollPadder, TableRef, Tip, Toggle, TypeRef, ValTypeRef, WVNode, WasmError, addToggleEvents } from "./components";
-import { assertUnreachable } from "./util";
+import { DataSegmentRef, E, ElementSegmentRef, F, FunctionRef, GlobalRef, ItemCount, Items, KindChip, MemoryRef, N, NameSection, RefTypeRef, Reference, ScrollPadder, TableRef, TagRef, Tip, Toggle, TypeRef, ValTypeRef, WVNode, WasmError, addToggleEvents } from "./components";
+import { assertUnreachable, listItems } from "./util";
 import { activateTab, addTabToPane, newPane, newPaneContainer, newTab } from "./panes";
 import { GotoEntry, addGoto, clearGotos, goto, lookUpGoto as lookUpGotos } from "./goto";
 import {
@@ -86,7 +86,7 @@ async function loadModuleFromFile(wasmFile: File) {
   }
 
   try {
-    module = await parse(wasmFile.stream());
+    module = await parse(wasmFile);
   } catch (err) {
     if (err instanceof BinaryError) {
       throw new Error(`at offset ${err.offset}: ${err.message}`, { cause: err });
@@ -97,10 +97,6 @@ async function loadModuleFromFile(wasmFile: File) {
   // @ts-expect-error I am not allowed to debug my own code 🤡
   window.currentModule = module;
 
-  // TODO: Inspect each section for correct handling of indices.
-  // Functions, tables, memories, and globals can all be imported, and therefore can have their
-  // indices shifted.
-
   clearGotos();
 
   sections.innerHTML = "";
@@ -138,7 +134,7 @@ async function loadModuleFromFile(wasmFile: File) {
         if (section.names) {
           customItem.classList.remove("pa2");
           customItem.classList.add("ph2");
-          for (const name of section.names) {
+          for (const [, name] of listItems(section.names)) {
             if (name.is_error) {
               customItem.appendChild(p(`ERROR (offset ${name.offset}): ${name.message}`));
             } else {
@@ -201,6 +197,16 @@ async function loadModuleFromFile(wasmFile: File) {
                     ref: n => DataSegmentRef({ index: n.index }),
                   }));
                 } break;
+                case "field": {
+                  customItem.appendChild(indirectNameMap("Field", name.field));
+                } break;
+                case "tag": {
+                  customItem.appendChild(NameSection({
+                    title: "Tags",
+                    names: name.tag,
+                    ref: n => TagRef({ index: n.index }),
+                  }));
+                } break;
                 case "unknown": {
                   customItem.appendChild(p(`Unknown name type ${name.unknown.ty}: ${name.unknown.data.length} bytes`));
                 } break;
@@ -218,7 +224,7 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-type");
 
         const items: Node[] = [];
-        for (const [i, type] of section.types.entries()) {
+        for (const [i, type] of listItems(section.types)) {
           if (type.is_error) {
             items.push(WasmError(`ERROR (offset ${type.offset}): ${type.message}`));
           } else {
@@ -228,7 +234,7 @@ async function loadModuleFromFile(wasmFile: File) {
                 details = funcTypeToString(type.t.func);
               } break;
             }
-            const nextOffset = section.types[i + 1]?.offset ?? sectionEnd;
+            const nextOffset = section.types.get(i + 1)?.offset ?? sectionEnd;
             const item = E("div", ["item", "item-type", "pa2", "flex", "flex-column", "g2", "relative"], [
               E("div", ["b"], `Type ${i}`),
               E("div", [], details),
@@ -249,13 +255,13 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionContents.appendChild(Items(items));
       } break;
       case "Import": {
-        headerEl.appendChild(ItemCount(section.imports.imports.length));
+        headerEl.appendChild(ItemCount(section.imports.length));
         sectionEl.classList.add("section-import");
 
         const items: Node[] = [];
 
         const importModules: { name: string; imports: Import[] }[] = [];
-        for (const [i, imp] of section.imports.imports.entries()) {
+        for (const [i, imp] of listItems(section.imports)) {
           if (imp.is_error) {
             items.push(WasmError(`ERROR (offset ${imp.offset}): ${imp.message}`));
             continue;
@@ -270,7 +276,7 @@ async function loadModuleFromFile(wasmFile: File) {
             });
           }
 
-          const nextOffset = section.imports.imports[i + 1]?.offset ?? sectionEnd;
+          const nextOffset = section.imports.get(i + 1)?.offset ?? sectionEnd;
           addGoto({
             kind: "import",
             depth: 1,
@@ -341,14 +347,15 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-function");
 
         const items: Node[] = [];
-        if (module.imported.funcs.length > 0) {
-          items.push(E("div", ["i", "f--small"], `Functions 0 through ${module.imported.funcs.length - 1} are imported from the host.`));
+        const numImportedFuncs = module.index.num_imported("func");
+        if (numImportedFuncs > 0) {
+          items.push(E("div", ["i", "f--small"], `Functions 0 through ${numImportedFuncs - 1} are imported from the host.`));
         }
-        for (const [i, func] of section.functions.entries()) {
+        for (const [i, func] of listItems(section.functions)) {
           if (func.is_error) {
             items.push(WasmError(`ERROR (offset ${func.offset}): ${func.message}`));
           } else {
-            const funcIndex = module.imported.funcs.length + i;
+            const funcIndex = module.index.defined("func", i)!.index;
             const name = module.names.funcs[funcIndex];
             const item = E("div", ["item", "item-function", "relative", "pa2", "flex", "flex-column", "g2"], [
               E("div", ["b"], name ? `Function ${funcIndex}: ${name}` : `Function ${funcIndex}`),
@@ -358,7 +365,7 @@ async function loadModuleFromFile(wasmFile: File) {
             item.setAttribute("data-index", `${funcIndex}`);
             items.push(item);
 
-            const nextOffset = section.functions[i + 1]?.offset ?? sectionEnd;
+            const nextOffset = section.functions.get(i + 1)?.offset ?? sectionEnd;
             addGoto({
               kind: "function",
               depth: 1,
@@ -375,11 +382,11 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-table");
 
         const items: Node[] = [];
-        for (const [i, table] of section.tables.entries()) {
+        for (const [i, table] of listItems(section.tables)) {
           if (table.is_error) {
             items.push(WasmError(`ERROR (offset ${table.offset}): ${table.message}`));
           } else {
-            const tableIndex = module.imported.tables.length + i;
+            const tableIndex = module.index.defined("table", i)!.index;
             // TODO: table names?
 
             // TODO: Notice of table imports
@@ -396,7 +403,7 @@ async function loadModuleFromFile(wasmFile: File) {
             item.setAttribute("data-index", `${tableIndex}`);
             items.push(item);
 
-            const nextOffset = section.tables[i + 1]?.offset ?? sectionEnd;
+            const nextOffset = section.tables.get(i + 1)?.offset ?? sectionEnd;
             addGoto({
               kind: "table",
               depth: 1,
@@ -413,11 +420,11 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-memory");
 
         const items: Node[] = [];
-        for (const [i, mem] of section.mems.entries()) {
+        for (const [i, mem] of listItems(section.mems)) {
           if (mem.is_error) {
             items.push(WasmError(`ERROR (offset ${mem.offset}): ${mem.message}`));
           } else {
-            const memIndex = module.imported.memories.length + i;
+            const memIndex = module.index.defined("memory", i)!.index;
             // TODO: memory names?
 
             // TODO: notice of memory imports
@@ -455,7 +462,7 @@ async function loadModuleFromFile(wasmFile: File) {
             // TODO: list relevant data segments (WARNING! there can be a lot of them!)
             items.push(memEl);
 
-            const nextOffset = section.mems[i + 1]?.offset ?? sectionEnd;
+            const nextOffset = section.mems.get(i + 1)?.offset ?? sectionEnd;
             addGoto({
               kind: "memory",
               depth: 1,
@@ -472,11 +479,11 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-global");
 
         const items: Node[] = [];
-        for (const [i, global] of section.globals.entries()) {
+        for (const [i, global] of listItems(section.globals)) {
           if (global.is_error) {
             items.push(WasmError(`ERROR (offset ${global.offset}): ${global.message}`));
           } else {
-            const globalIndex = module.imported.globals.length + i;
+            const globalIndex = module.index.defined("global", i)!.index;
             // TODO: global names
             // TODO: global init expr
 
@@ -491,7 +498,7 @@ async function loadModuleFromFile(wasmFile: File) {
             item.setAttribute("data-index", `${globalIndex}`);
             items.push(item);
 
-            const nextOffset = section.globals[i + 1]?.offset ?? sectionEnd;
+            const nextOffset = section.globals.get(i + 1)?.offset ?? sectionEnd;
             addGoto({
               kind: "global",
               depth: 1,
@@ -508,7 +515,7 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-export");
 
         const goodExports: Export[] = [];
-        for (const exp of section.exports) {
+        for (const [, exp] of listItems(section.exports)) {
           if (exp.is_error) {
             sectionContents.appendChild(WasmError(`ERROR (offset ${exp.offset}): ${exp.message}`));
           } else {
@@ -564,7 +571,7 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-element");
 
         const items: Node[] = [];
-        for (const [i, element] of section.elements.entries()) {
+        for (const [i, element] of listItems(section.elements)) {
           if (element.is_error) {
             items.push(WasmError(`ERROR (offset ${element.offset}): ${element.message}`));
           } else {
@@ -611,11 +618,12 @@ async function loadModuleFromFile(wasmFile: File) {
 
         const items: Node[] = [];
 
-        if (module.imported.funcs.length > 0) {
-          items.push(E("div", ["i", "f--small"], `Functions 0 through ${module.imported.funcs.length - 1} are imported from the host.`));
+        const numImportedFuncs = module.index.num_imported("func");
+        if (numImportedFuncs > 0) {
+          items.push(E("div", ["i", "f--small"], `Functions 0 through ${numImportedFuncs - 1} are imported from the host.`));
         }
 
-        for (const [i, func] of section.funcs.entries()) {
+        for (const [i, func] of listItems(section.funcs)) {
           if (func.is_error) {
             items.push(WasmError(`ERROR (offset ${func.offset}): ${func.message}`));
           } else {
@@ -626,7 +634,7 @@ async function loadModuleFromFile(wasmFile: File) {
             //     sectionEl.appendChild(p(op.name));
             //   }
             // }
-            const funcIndex = module.imported.funcs.length + i;
+            const funcIndex = module.index.defined("func", i)!.index;
             const name = module.names.funcs[funcIndex];
             const funcTypeIndex = module.functionType(funcIndex);
             const item = E("div", ["item", "item-code", "relative", "pa2", "flex", "flex-column", "g1"], [
@@ -658,7 +666,7 @@ async function loadModuleFromFile(wasmFile: File) {
         sectionEl.classList.add("section-data");
 
         const items: Node[] = [];
-        for (const [i, data] of section.datas.entries()) {
+        for (const [i, data] of listItems(section.datas)) {
           if (data.is_error) {
             items.push(WasmError(`ERROR (offset ${data.offset}): ${data.message}`));
           } else {
@@ -1,24 +1,26 @@
 import { readVarU } from "./leb128";
 import { WasmReader } from "./reader";
 import { Module, Section, CustomSection, SectionCommon } from "./types";
+import { listItems } from "./util";
 import {
-  parse_code_section,
+  ModuleIndex,
+  parse_code_section_list,
   parse_custom_section,
-  parse_data_section,
-  parse_element_section,
-  parse_export_section,
+  parse_data_section_list,
+  parse_element_section_list,
+  parse_export_section_list,
   parse_function_body,
-  parse_function_section,
-  parse_global_section,
-  parse_import_section,
-  parse_memory_section,
-  parse_name_section,
-  parse_table_section,
-  parse_type_section,
+  parse_function_section_list,
+  parse_global_section_list,
+  parse_import_section_list,
+  parse_memory_section_list,
+  parse_name_section_list,
+  parse_table_section_list,
+  parse_type_section_list,
 } from "../wasm-tools/pkg/wasm_viewer";
 
-export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module> {
-  const reader = new WasmReader(stream);
+export async function parse(file: Blob): Promise<Module> {
+  const reader = new WasmReader(file.stream());
 
   async function expect(bytes: number[]) {
     for (const [i, expected] of bytes.entries()) {
@@ -37,6 +39,7 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
   // This means we don't care about section order, we are generous with opcodes, etc.
 
   const sections: Section[] = [];
+  const index = new ModuleIndex();
 
   // Parse sections
   while (true) {
@@ -55,6 +58,7 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
         const custom = parse_custom_section(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         const sec: SectionCommon & CustomSection = {
           type: "Custom",
@@ -64,7 +68,7 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         };
         if (sec.custom.name === "name") {
           console.log("Custom section is name section; parsing that too");
-          sec.names = parse_name_section(custom.data, 0); // TODO: wrong offset
+          sec.names = parse_name_section_list(custom.data, 0); // TODO: wrong offset
         }
 
         sections.push(sec);
@@ -74,7 +78,7 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Type section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const types = parse_type_section(bytes, contentOffset);
+        const types = parse_type_section_list(bytes, contentOffset);
 
         sections.push({
           type: "Type",
@@ -88,7 +92,8 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Import section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const imports = parse_import_section(bytes, contentOffset);
+        const imports = parse_import_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         sections.push({
           type: "Import",
@@ -102,7 +107,8 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Function section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const functions = parse_function_section(bytes, contentOffset);
+        const functions = parse_function_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         sections.push({
           type: "Function",
@@ -116,7 +122,8 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Table section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const tables = parse_table_section(bytes, contentOffset);
+        const tables = parse_table_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         sections.push({
           type: "Table",
@@ -130,7 +137,8 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Memory section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const mems = parse_memory_section(bytes, contentOffset);
+        const mems = parse_memory_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         sections.push({
           type: "Memory",
@@ -144,7 +152,8 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Global section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const globals = parse_global_section(bytes, contentOffset);
+        const globals = parse_global_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         sections.push({
           type: "Global",
@@ -158,7 +167,8 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Export section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const exports = parse_export_section(bytes, contentOffset);
+        const exports = parse_export_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
         sections.push({
           type: "Export",
@@ -185,7 +195,7 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Element section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const elements = parse_element_section(bytes, contentOffset);
+        const elements = parse_element_section_list(bytes, contentOffset);
 
         sections.push({
           type: "Element",
@@ -199,12 +209,14 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Code section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const funcs = parse_code_section(bytes, contentOffset);
+        const funcs = parse_code_section_list(bytes, contentOffset);
+        index.add_section(sectionID, bytes, contentOffset);
 
-        // TODO: Parse functions separately on a thread or whatever
+        // TODO: Decode the bodies in web workers, with partition_code_section and
+        // decode_code_range, merging their results by body_index
 
         // TODO: Offsets are bad here
-        for (const func of funcs) {
+        for (const [, func] of listItems(funcs)) {
           if (func.is_error) {
             continue;
           }
@@ -227,7 +239,7 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
         console.log("Data section");
         console.log("Getting this many bytes:", sectionContentsSize);
         const bytes = await reader.getNBytes(sectionContentsSize);
-        const datas = parse_data_section(bytes, contentOffset);
+        const datas = parse_data_section_list(bytes, contentOffset);
 
         sections.push({
           type: "Data",
@@ -251,8 +263,10 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
       } break;
       default: {
         console.log(`Unknown section type ${sectionID}`);
-        console.log("Advancing by", sectionContentsSize, "to skip over it");
-        await reader.advanceBy(sectionContentsSize);
+        console.log("Getting this many bytes:", sectionContentsSize);
+        const bytes = await reader.getNBytes(sectionContentsSize);
+        // The index still needs sections we don't otherwise show, like tags
+        index.add_section(sectionID, bytes, contentOffset);
       } break;
     }
 
@@ -262,5 +276,9 @@ export async function parse(stream: ReadableStream<Uint8Array>): Promise<Module>
     }
   }
 
-  return new Module(sections);
+  if (index.error) {
+    console.error("Failed to index part of the module:", index.error.message);
+  }
+
+  return new Module(sections, index);
 }
@@ -1,27 +1,28 @@
 import type {
-  BinaryError,
   CustomSection as WasmCustomSection,
-  Data,
-  Element,
-  Export,
+  DataResultList,
+  ElementResultList,
+  ExportResultList,
   Function,
-  Global,
-  ImportSection as WasmImportSection,
-  Memory,
+  FunctionBodyResultList,
+  FunctionResultList,
+  GlobalResultList,
+  ImportResultList,
+  MemoryResultList,
   MemoryType,
+  ModuleIndex,
+  NameResultList,
   RefType,
-  Table,
+  TableResultList,
   Type,
+  TypeResultList,
   ValType,
-  Name,
-  FunctionBody,
   FuncType,
   GlobalType,
-  TableType,
   NamingResultArray,
   IndirectNamingResultArray,
 } from "../wasm-tools/pkg/wasm_viewer";
-import { assertUnreachable } from "./util";
+import { assertUnreachable, listItems } from "./util";
 
 export interface SectionCommon {
   offset: number;
@@ -32,42 +33,42 @@ export interface CustomSection {
   type: "Custom";
   custom: WasmCustomSection;
 
-  names?: Array<Name | BinaryError>;
+  names?: NameResultList;
 }
 
 export interface TypeSection {
   type: "Type";
-  types: Array<Type | BinaryError>;
+  types: TypeResultList;
 }
 
 export interface ImportSection {
   type: "Import";
-  imports: WasmImportSection;
+  imports: ImportResultList;
 }
 
 export interface FunctionSection {
   type: "Function";
-  functions: Array<Function | BinaryError>;
+  functions: FunctionResultList;
 }
 
 export interface TableSection {
   type: "Table";
-  tables: Array<Table | BinaryError>;
+  tables: TableResultList;
 }
 
 export interface MemorySection {
   type: "Memory";
-  mems: Array<Memory | BinaryError>;
+  mems: MemoryResultList;
 }
 
 export interface GlobalSection {
   type: "Global";
-  globals: Array<Global | BinaryError>;
+  globals: GlobalResultList;
 }
 
 export interface ExportSection {
   type: "Export";
-  exports: Array<Export | BinaryError>;
+  exports: ExportResultList;
 }
 
 export interface StartSection {
@@ -77,17 +78,17 @@ export interface StartSection {
 
 export interface ElementSection {
   type: "Element";
-  elements: Array<Element | BinaryError>;
+  elements: ElementResultList;
 }
 
 export interface CodeSection {
   type: "Code";
-  funcs: Array<FunctionBody | BinaryError>;
+  funcs: FunctionBodyResultList;
 }
 
 export interface DataSection {
   type: "Data";
-  datas: Array<Data | BinaryError>;
+  datas: DataResultList;
 }
 
 export interface DataCountSection {
@@ -111,13 +112,6 @@ export type Section = SectionCommon & (
   | DataCountSection
 );
 
-export interface ImportedData {
-  funcs: Function["type_idx"][];
-  tables: TableType[];
-  memories: MemoryType[];
-  globals: GlobalType[];
-}
-
 export type NameMap = (string | undefined)[];
 export type IndirectNameMap = (NameMap | undefined)[];
 
@@ -132,14 +126,17 @@ export interface Names {
   globals: NameMap;
   elements: NameMap;
   datas: NameMap;
+  fields: IndirectNameMap;
+  tags: NameMap;
 }
 
 export class Module {
   sections: Section[];
-  imported: ImportedData;
+  /** Resolves indices that count imports to the imported or defined items. */
+  index: ModuleIndex;
   names: Names;
 
-  constructor(sections: Section[]) {
+  constructor(sections: Section[], index: ModuleIndex) {
     function nameMap(arr: NameMap, names: NamingResultArray) {
       for (const name of names) {
         if (name.is_error) {
@@ -168,12 +165,7 @@ export class Module {
     }
 
     this.sections = sections;
-    this.imported = {
-      funcs: [],
-      tables: [],
-      memories: [],
-      globals: [],
-    };
+    this.index = index;
     this.names = {
       module: undefined,
       funcs: [],
@@ -185,36 +177,19 @@ export class Module {
       globals: [],
       elements: [],
       datas: [],
+      fields: [],
+      tags: [],
     };
 
-    // Save imports and their names
-    for (const imp of this.section("Import")?.imports.imports ?? []) {
-      if (imp.is_error) {
-        continue;
-      }
-      switch (imp.ty.kind) {
-        case "func": {
-          this.imported.funcs.push(imp.ty.func);
-          this.names.funcs.push(imp.name);
-        } break;
-        case "table": {
-          this.imported.tables.push(imp.ty.table);
-        } break;
-        case "memory": {
-          this.imported.memories.push(imp.ty.memory);
-        } break;
-        case "global": {
-          this.imported.globals.push(imp.ty.global);
-        } break;
-      }
+    // Start with the names of imported functions, leaving a slot for every function
+    this.names.funcs.length = index.length("func");
+    for (let i = 0; i < index.num_imported("func"); i++) {
+      this.names.funcs[i] = index.get("func", i)?.import_name;
     }
-
-    // Initialize name arrays to the correct lengths after we know how many imports there were
-    this.names.funcs.length += this.section("Function")?.functions.length ?? 0;
     // TODO: All other name types
 
     // Get names from exports
-    for (const exp of this.section("Export")?.exports ?? []) {
+    for (const [, exp] of listItems(this.section("Export")?.exports)) {
       if (exp.is_error) {
         continue;
       }
@@ -231,7 +206,7 @@ export class Module {
     }
 
     // Finally, stomp on all existing names with the name section
-    for (const name of this.section("Custom")?.names ?? []) {
+    for (const [, name] of listItems(this.section("Custom")?.names)) {
       if (name.is_error) {
         continue;
       }
@@ -267,6 +242,12 @@ export class Module {
         case "data": {
           nameMap(this.names.datas, name.data);
         } break;
+        case "field": {
+          indirectNameMap(this.names.fields, name.field);
+        } break;
+        case "tag": {
+          nameMap(this.names.tags, name.tag);
+        } break;
         case "unknown": {
           // I sure do love being exhaustive
         } break;
@@ -290,7 +271,7 @@ export class Module {
       return undefined;
     }
 
-    const t = typeSection.types[index];
+    const t = typeSection.types.get(index);
     // eslint-disable-next-line @typescript-eslint/no-unnecessary-condition
     if (t && !t.is_error) {
       return t;
@@ -300,27 +281,20 @@ export class Module {
   }
 
   functionType(index: number): Function["type_idx"] | undefined {
-    if (index < this.imported.funcs.length) {
-      return this.imported.funcs[index];
-    }
-    const innerIndex = index - this.imported.funcs.length;
-    const func = this.section("Function")?.functions[innerIndex];
+    const func = this.index.get("func", index);
     if (!func) {
-      console.error(`No function with index ${index} (inner index ${innerIndex})`);
+      console.error(`No function with index ${index}`);
       return undefined;
     }
-    if (!func.is_error) {
-      return func.type_idx;
+    if (func.ty.kind === "func") {
+      return func.ty.func;
     }
   }
 
   globalType(index: number): GlobalType | undefined {
-    if (index < this.imported.globals.length) {
-      return this.imported.globals[index];
-    }
-    const global = this.section("Global")?.globals[index];
-    if (!global?.is_error) {
-      return global?.ty;
+    const global = this.index.get("global", index);
+    if (global?.ty.kind === "global") {
+      return global.ty.global;
     }
   }
 }
@@ -1,3 +1,22 @@
 export function assertUnreachable(_: never): never {
   throw new Error("reached unreachable code");
 }
+
+/** A list from the wasm module, which converts each item to JS as it is asked for. */
+export interface List<T> {
+  length: number;
+  get(index: number): T | undefined;
+}
+
+/** The items of a list with their indices, converting each one only when it is reached. */
+export function* listItems<T>(list: List<T> | undefined): Generator<[number, T]> {
+  if (!list) {
+    return;
+  }
+  for (let i = 0; i < list.length; i++) {
+    const item = list.get(i);
+    if (item !== undefined) {
+      yield [i, item];
+    }
+  }
+}
@@ -8,28 +8,136 @@ use proc_macro2::TokenStream as TokenStream2;
 // https://github.com/rustwasm/wasm-bindgen/blob/0753bec4c6f51d7e27b82c357e65cefab3c61dd3/crates/cli-support/src/js/binding.rs#L1275
 fn ident2ts(ty: String) -> String {
     match ty.as_str() {
-        "i32"
-        | "s8"
-        | "s16"
-        | "s32"
+        "i8"
+        | "i16"
+        | "i32"
+        | "isize"
         | "u8"
         | "u16"
         | "u32"
+        | "usize"
         | "f32"
         | "f64" => "number",
-        "i64" | "s64" | "u64" => "bigint",
+        "i64" | "u64" => "bigint",
         "String" => "string",
+        "bool" => "boolean",
         // TODO: Externref
-        "bool" => "bool",
-        // TODO: Vector
-        // TODO: Option
         // TODO: NamedExternref
         _ => ty.as_str(),
     }.to_string()
 }
 
+/// The typed array that wasm-bindgen converts a `Vec` of the given primitive
+/// to, if there is one.
+fn typed_array(element: &str) -> Option<&'static str> {
+    let array = match element {
+        "i8" => "Int8Array",
+        "i16" => "Int16Array",
+        "i32" => "Int32Array",
+        "i64" => "BigInt64Array",
+        "u8" => "Uint8Array",
+        "u16" => "Uint16Array",
+        "u32" => "Uint32Array",
+        "u64" => "BigUint64Array",
+        "f32" => "Float32Array",
+        "f64" => "Float64Array",
+        _ => return None,
+    };
+    Some(array)
+}
+
+/// The single generic argument of a type like `Option<T>` or `Vec<T>`.
+fn generic_arg(segment: &syn::PathSegment) -> Option<&syn::Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn type2ts(ty: &syn::Type) -> String {
+    let segment = match ty {
+        syn::Type::Path(p) if p.path.segments.len() == 1 => &p.path.segments[0],
+        syn::Type::Reference(r) => return type2ts(&r.elem),
+        _ => panic!("can't generate a TypeScript type for `{}`", ty.to_token_stream()),
+    };
+    let ident = segment.ident.to_string();
+    match (ident.as_str(), generic_arg(segment)) {
+        ("Option", Some(inner)) => format!("{} | undefined", type2ts(inner)),
+        ("Vec", Some(inner)) => {
+            if let Some(element) = array_element(ty) {
+                return format!("{}Array", element);
+            }
+            let element = type2ts(inner);
+            match typed_array(&element_name(inner)) {
+                Some(array) => array.to_string(),
+                None if element.contains('|') => format!("Array<({})>", element),
+                None => format!("Array<{}>", element),
+            }
+        }
+        ("str", None) => "string".to_string(),
+        ("Bytes", None) => "Uint8Array".to_string(),
+        _ => ident2ts(ident),
+    }
+}
+
+/// Turns a variant name like `ref_null` or `type_` into `RefNull` or `Type`.
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// The name of a type, without its generic arguments.
+fn element_name(ty: &syn::Type) -> String {
+    match ty {
+        syn::Type::Path(p) if p.path.segments.len() == 1 => p.path.segments[0].ident.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Returns `T` if `ty` is `Vec<T>` for a type `T` that is sent to JS through
+/// a `TArray`. Fields like this are kept as Rust vectors, so that they can be
+/// used (and serialized) natively, and are converted to JS arrays by a getter.
+fn array_element(ty: &syn::Type) -> Option<&Ident> {
+    let segment = match ty {
+        syn::Type::Path(p) if p.path.segments.len() == 1 => &p.path.segments[0],
+        _ => return None,
+    };
+    if segment.ident != "Vec" {
+        return None;
+    }
+    let element = match generic_arg(segment) {
+        Some(syn::Type::Path(p)) if p.path.segments.len() == 1 => &p.path.segments[0],
+        _ => return None,
+    };
+    if !element.arguments.is_empty() || element.ident != ident2ts(element.ident.to_string()) {
+        return None;
+    }
+    Some(&element.ident)
+}
+
+/// Whether the arguments to `wasmtools_enum` or `wasmtools_struct` ask for
+/// lazy lists of the type, with `#[wasmtools_struct(list)]`.
+fn wants_lists(args: TokenStream) -> bool {
+    match args.to_string().as_str() {
+        "" => false,
+        "list" => true,
+        args => panic!("unknown wasmtools arguments `{}`", args),
+    }
+}
+
 #[proc_macro_attribute]
-pub fn wasmtools_enum(_: TokenStream, input: TokenStream) -> TokenStream {
+pub fn wasmtools_enum(args: TokenStream, input: TokenStream) -> TokenStream {
+    let with_lists = wants_lists(args);
     let input = parse_macro_input!(input as DeriveInput);
     let name = &input.ident;
 
@@ -38,8 +146,12 @@ pub fn wasmtools_enum(_: TokenStream, input: TokenStream) -> TokenStream {
         _ => panic!("wasmtools_enum only works with enums"),
     };
 
+    let name_str = name.to_string();
     let mut struct_fields = quote! {};
     let mut constructors = quote! {};
+    let mut getters = quote! {};
+    let mut serialize_fields = quote! {};
+    let mut payload_structs = quote! {};
     let mut js_types: Vec<String> = vec![];
     for variant in enum_data.variants {
         let field_name = &variant.ident;
@@ -55,28 +167,35 @@ pub fn wasmtools_enum(_: TokenStream, input: TokenStream) -> TokenStream {
                         }
                     }
                 });
-                js_types.push(format!(
-                    "{{ kind: \"{}\" }}",
-                    variant.ident.to_string(),
-                ));
+                js_types.push(format!("{{ kind: \"{}\" }}", variant.ident));
             },
-            syn::Fields::Unnamed(variant_fields) => {
-                if variant_fields.unnamed.len() > 1 {
-                    panic!("wasmtools_enum does not support more than one field per variant");
-                }
-
+            syn::Fields::Unnamed(variant_fields) if variant_fields.unnamed.len() == 1 => {
                 let ty = &variant_fields.unnamed[0].ty;
-                let type_name = match ty {
-                    syn::Type::Path(p) => {
-                        &p.path.segments[0].ident
-                    },
-                    _ => panic!("wasmtools_enum does not support that kind of enum field type"),
-                };
                 let field_type = ty.to_token_stream();
-                let ts_field_type = ident2ts(type_name.to_string());
+                let ts_field_type = type2ts(ty);
 
-                struct_fields.extend(quote! {
-                    pub #field_name: Option<#field_type>,
+                if let Some(element) = array_element(ty) {
+                    let array_name = format_ident!("{}Array", element);
+                    let getter_name = format_ident!("js_{}", field_name);
+                    struct_fields.extend(quote! {
+                        #[wasm_bindgen(skip)]
+                        pub #field_name: Option<#field_type>,
+                    });
+                    getters.extend(quote! {
+                        #[wasm_bindgen(getter = #field_name, skip_typescript)]
+                        pub fn #getter_name(&self) -> Option<#array_name> {
+                            self.#field_name.clone().map(|v| v.into())
+                        }
+                    });
+                } else {
+                    struct_fields.extend(quote! {
+                        pub #field_name: Option<#field_type>,
+                    });
+                }
+                serialize_fields.extend(quote! {
+                    if let Some(value) = &self.#field_name {
+                        state.serialize_field(#field_name_str, value)?;
+                    }
                 });
                 constructors.extend(quote! {
                     pub fn #constructor_name(#field_name: #field_type) -> Self {
@@ -89,12 +208,68 @@ pub fn wasmtools_enum(_: TokenStream, input: TokenStream) -> TokenStream {
                 });
                 js_types.push(format!(
                     "{{ kind: \"{}\", {}: {} }}",
-                    variant.ident.to_string(),
-                    variant.ident.to_string(),
-                    ts_field_type,
+                    variant.ident, variant.ident, ts_field_type,
+                ));
+            },
+            syn::Fields::Unnamed(_) | syn::Fields::Named(_) => {
+                // The fields go in a struct of their own, e.g. `active { .. }`
+                // in `ElementKind` becomes `ElementKindActive`. Unnamed fields
+                // are called `_0`, `_1` and so on, which the TS says.
+                let payload_name = format_ident!("{}{}", name, upper_camel_case(&field_name_str));
+                let unnamed = matches!(variant.fields, syn::Fields::Unnamed(_));
+                let mut payload_fields = variant.fields.clone();
+                for (i, field) in payload_fields.iter_mut().enumerate() {
+                    if field.ident.is_none() {
+                        let doc = format!(" Field {} of the `{}` variant.", i, field_name_str);
+                        field.attrs.push(syn::parse_quote!(#[doc = #doc]));
+                        field.ident = Some(format_ident!("_{}", i));
+                    }
+                    field.vis = syn::parse_quote!(pub);
+                }
+                let params = payload_fields.iter().map(|f| {
+                    let (ident, ty) = (&f.ident, &f.ty);
+                    quote! { #ident: #ty }
+                });
+                let idents = payload_fields.iter().map(|f| &f.ident);
+                let payload_fields = payload_fields.iter();
+                let docs = variant.attrs.iter().filter(|a| a.path().is_ident("doc"));
+                payload_structs.extend(struct_impl(syn::parse_quote! {
+                    #(#docs)*
+                    pub struct #payload_name {
+                        #(#payload_fields,)*
+                    }
+                }));
+
+                struct_fields.extend(quote! {
+                    pub #field_name: Option<#payload_name>,
+                });
+                serialize_fields.extend(quote! {
+                    if let Some(value) = &self.#field_name {
+                        state.serialize_field(#field_name_str, value)?;
+                    }
+                });
+                constructors.extend(quote! {
+                    pub fn #constructor_name(#(#params),*) -> Self {
+                        #name {
+                            kind: #field_name_str.to_string(),
+                            #field_name: Some(#payload_name { #(#idents),* }),
+                            ..Default::default()
+                        }
+                    }
+                });
+                let doc = match unnamed {
+                    true => format!(
+                        "\n  /** `{}` holds the fields in order as `_0` to `_{}`. */\n  ",
+                        payload_name,
+                        variant.fields.len() - 1,
+                    ),
+                    false => String::new(),
+                };
+                js_types.push(format!(
+                    "{}{{ kind: \"{}\", {}: {} }}",
+                    doc, variant.ident, variant.ident, payload_name,
                 ));
             },
-            syn::Fields::Named(_) => panic!("wasmtools_enum does not work with named enum fields"),
         }
     }
     let output_struct = quote! {
@@ -109,6 +284,24 @@ pub fn wasmtools_enum(_: TokenStream, input: TokenStream) -> TokenStream {
         impl #name {
             #constructors
         }
+
+        #[wasm_bindgen]
+        impl #name {
+            #getters
+        }
+    };
+    // serialized as in the TS definition: the kind, plus the field for it
+    let impl_serialize = quote! {
+        impl serde::Serialize for #name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::SerializeStruct;
+                let mut state = serializer.serialize_struct(#name_str, 3)?;
+                state.serialize_field("is_error", &false)?;
+                state.serialize_field("kind", &self.kind)?;
+                #serialize_fields
+                state.end()
+            }
+        }
     };
     let ts_def = format!(r#"
 export type {} = {{ is_error: false }} & ({});
@@ -118,20 +311,91 @@ export type {} = {{ is_error: false }} & ({});
         const _: &'static str = #ts_def;
     };
     let arrays = arrays_and_results(name);
+    let lists = if with_lists { lists(name) } else { quote! {} };
 
     let output = quote! {
+        #lists
         #output_struct
         #constructor_impl
+        #impl_serialize
         #arrays
         #ts
+        #payload_structs
     };
     output.into()
 }
 
 #[proc_macro_attribute]
-pub fn wasmtools_struct(_: TokenStream, input: TokenStream) -> TokenStream {
+pub fn wasmtools_struct(args: TokenStream, input: TokenStream) -> TokenStream {
+    let with_lists = wants_lists(args);
     let input = parse_macro_input!(input as DeriveInput);
-    let name = &input.ident;
+    let lists = if with_lists { lists(&input.ident) } else { quote! {} };
+    let output = struct_impl(input);
+    quote! {
+        #lists
+        #output
+    }.into()
+}
+
+fn struct_impl(mut input: DeriveInput) -> TokenStream2 {
+    let name = input.ident.clone();
+    let name_str = name.to_string();
+
+    let fields = match &mut input.data {
+        syn::Data::Struct(syn::DataStruct { fields: syn::Fields::Named(fields), .. }) => &mut fields.named,
+        _ => panic!("wasmtools_struct only works with structs with named fields"),
+    };
+    let mut getters = quote! {};
+    let mut serialize_fields = quote! {};
+    for field in fields.iter_mut() {
+        let field_name = field.ident.clone().unwrap();
+        let field_name_str = field_name.to_string();
+        if let Some(element) = array_element(&field.ty) {
+            let array_name = format_ident!("{}Array", element);
+            let getter_name = format_ident!("js_{}", field_name);
+            let docs = field.attrs.iter().filter(|a| a.path().is_ident("doc"));
+            getters.extend(quote! {
+                #(#docs)*
+                #[wasm_bindgen(getter = #field_name)]
+                pub fn #getter_name(&self) -> #array_name {
+                    self.#field_name.clone().into()
+                }
+            });
+            field.attrs.push(syn::parse_quote!(#[wasm_bindgen(skip)]));
+        } else if element_name(&field.ty) == "Bytes" {
+            // Bytes shared with the rest of the module, which JS can either
+            // copy or look at directly
+            let getter_name = format_ident!("js_{}", field_name);
+            let view_name = format_ident!("{}_view", field_name);
+            let js_view_name = format_ident!("js_{}_view", field_name);
+            let view_doc = format!(
+                " A view of `{}` into the module's memory, which unlike `{}` doesn't copy \
+                the bytes. It is only valid until the memory grows, which can happen on any \
+                call into the module, and until this object is freed, which releases the \
+                bytes once nothing else read from the same section holds them.",
+                field_name, field_name,
+            );
+            let docs = field.attrs.iter().filter(|a| a.path().is_ident("doc"));
+            getters.extend(quote! {
+                #(#docs)*
+                #[wasm_bindgen(getter = #field_name)]
+                pub fn #getter_name(&self) -> Vec<u8> {
+                    self.#field_name.to_vec()
+                }
+
+                #[doc = #view_doc]
+                #[wasm_bindgen(js_name = #view_name)]
+                pub fn #js_view_name(&self) -> js_sys::Uint8Array {
+                    unsafe { js_sys::Uint8Array::view(&self.#field_name) }
+                }
+            });
+            field.attrs.push(syn::parse_quote!(#[wasm_bindgen(skip)]));
+        }
+        serialize_fields.extend(quote! {
+            state.serialize_field(#field_name_str, &self.#field_name)?;
+        });
+    }
+    let num_fields = fields.len() + 1;
 
     // error definition on the type (to enable discriminated union in typescript)
     let impl_error = quote! {
@@ -141,6 +405,19 @@ pub fn wasmtools_struct(_: TokenStream, input: TokenStream) -> TokenStream {
             pub fn get_error(&self) -> JsValue {
                 JsValue::FALSE
             }
+
+            #getters
+        }
+    };
+    let impl_serialize = quote! {
+        impl serde::Serialize for #name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::SerializeStruct;
+                let mut state = serializer.serialize_struct(#name_str, #num_fields)?;
+                state.serialize_field("is_error", &false)?;
+                #serialize_fields
+                state.end()
+            }
         }
     };
     let impl_error_ts_def = format!(r#"
@@ -153,7 +430,7 @@ export interface {} {{
         const _: &'static str = #impl_error_ts_def;
     };
 
-    let arrays = arrays_and_results(name);
+    let arrays = arrays_and_results(&name);
 
     let output = quote! {
         #[derive(Debug, Clone)]
@@ -161,19 +438,30 @@ export interface {} {{
         #input
         #impl_error
         #impl_error_ts
+        #impl_serialize
         #arrays
     };
-    output.into()
+    output
 }
 
 fn arrays_and_results(name: &Ident) -> TokenStream2 {
     // result type to encapsulate value + error + offset
     let result_name = format_ident!("{}Result", name);
     let result_type = quote! {
+        #[derive(Debug, Clone)]
         pub enum #result_name {
             Ok(#name),
             Err(BinaryError),
         }
+
+        impl serde::Serialize for #result_name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                match self {
+                    #result_name::Ok(ok) => ok.serialize(serializer),
+                    #result_name::Err(err) => err.serialize(serializer),
+                }
+            }
+        }
     };
 
     let array_name = format_ident!("{}Array", name);
@@ -226,3 +514,81 @@ export type {} = {};
         #impl_arrays_ts
     }
 }
+
+fn lists(name: &Ident) -> TokenStream2 {
+    // handles that keep the items in Rust, and only convert the ones that are
+    // asked for, for sections too big to turn into a JS array all at once
+    let result_name = format_ident!("{}Result", name);
+    let list_name = format_ident!("{}List", name);
+    let result_list_name = format_ident!("{}ResultList", name);
+    let list_doc = format!(" A list of `{}`s, which are only sent to JS as they are accessed.", name);
+    let result_list_doc = format!(" A list of `{}`s or errors, which are only sent to JS as they are accessed.", name);
+    let impl_lists = quote! {
+        #[doc = #list_doc]
+        #[wasm_bindgen]
+        pub struct #list_name {
+            items: Vec<#name>,
+        }
+
+        #[wasm_bindgen]
+        impl #list_name {
+            #[wasm_bindgen(getter)]
+            pub fn length(&self) -> usize {
+                self.items.len()
+            }
+
+            pub fn get(&self, index: usize) -> Option<#name> {
+                self.items.get(index).cloned()
+            }
+        }
+
+        impl From<Vec<#name>> for #list_name {
+            fn from(value: Vec<#name>) -> Self {
+                #list_name { items: value }
+            }
+        }
+
+        #[doc = #result_list_doc]
+        #[wasm_bindgen]
+        pub struct #result_list_name {
+            items: Vec<#result_name>,
+        }
+
+        #[wasm_bindgen]
+        impl #result_list_name {
+            #[wasm_bindgen(getter)]
+            pub fn length(&self) -> usize {
+                self.items.len()
+            }
+
+            #[wasm_bindgen(skip_typescript)]
+            pub fn get(&self, index: usize) -> JsValue {
+                match self.items.get(index) {
+                    Some(#result_name::Ok(ok)) => JsValue::from(ok.clone()),
+                    Some(#result_name::Err(err)) => JsValue::from(err.clone()),
+                    None => JsValue::UNDEFINED,
+                }
+            }
+        }
+
+        impl From<Vec<#result_name>> for #result_list_name {
+            fn from(value: Vec<#result_name>) -> Self {
+                #result_list_name { items: value }
+            }
+        }
+    };
+    let impl_lists_ts_def = format!(r#"
+export interface {} {{
+  get(index: number): {} | BinaryError | undefined;
+}}
+"#, result_list_name, name);
+    let impl_lists_ts = quote! {
+        #[wasm_bindgen(typescript_custom_section)]
+        const _: &'static str = #impl_lists_ts_def;
+    };
+
+    quote! {
+        #impl_lists
+        #impl_lists_ts
+    }
+}
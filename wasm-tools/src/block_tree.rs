@@ -0,0 +1,212 @@
+//! The nesting structure of a function body's blocks, and where each branch
+//! goes.
+
+use js_sys::Array;
+use macros::*;
+use wasm_bindgen::prelude::*;
+use wasmparser::{FunctionBody, Operator};
+
+use crate::types::{BinaryError, BlockType, U32Array};
+
+/// A structured control construct. The root of the tree is the function body
+/// itself, with the kind `func`.
+///
+/// `try_table` is not listed since the version of wasmparser we use does not
+/// decode it yet.
+#[wasmtools_struct]
+pub struct BlockNode {
+    pub id: u32,
+    /// `func`, `block`, `loop`, `if` or `try`.
+    pub kind: String,
+    /// The construct's block type, or `None` for the function body.
+    pub block_type: Option<BlockType>,
+    /// The offset of the instruction that opens the construct. For the
+    /// function body, the offset of its first instruction.
+    pub start: usize,
+    /// The offset of the `end` (or `delegate`) that closes the construct.
+    pub end: usize,
+    /// The offset of the `else` of an `if`, if it has one.
+    pub else_offset: Option<usize>,
+    pub parent: Option<u32>,
+    pub children: U32Array,
+}
+
+/// A branch instruction, with its label resolved to the construct it
+/// targets.
+#[wasmtools_struct]
+pub struct BranchTarget {
+    /// The offset of the branch instruction.
+    pub offset: usize,
+    /// The branch instruction, e.g. `br_if`.
+    pub instruction: String,
+    /// The label as written in the instruction, relative to the innermost
+    /// enclosing construct.
+    pub depth: u32,
+    /// The id of the targeted construct.
+    pub target: u32,
+    /// Where control goes if the branch is taken: the start of a `loop`, or
+    /// else just past the `end` of the targeted construct.
+    pub target_offset: usize,
+}
+
+#[wasm_bindgen]
+pub struct BlockTree {
+    nodes: Vec<BlockNode>,
+    branches: Vec<BranchTarget>,
+}
+
+struct Builder {
+    tree: BlockTree,
+    /// The constructs enclosing the current instruction, innermost last.
+    open: Vec<u32>,
+    /// Branches whose targets haven't reached their `end` yet, as indices
+    /// into `tree.branches`.
+    unresolved: Vec<usize>,
+}
+
+impl Builder {
+    fn open(&mut self, kind: &str, block_type: Option<BlockType>, offset: usize) {
+        let id = self.tree.nodes.len() as u32;
+        let parent = self.open.last().copied();
+        if let Some(parent) = parent {
+            self.tree.nodes[parent as usize].children.push(id);
+        }
+        self.tree.nodes.push(BlockNode {
+            id,
+            kind: kind.to_string(),
+            block_type,
+            start: offset,
+            end: offset,
+            else_offset: None,
+            parent,
+            children: Vec::new(),
+        });
+        self.open.push(id);
+    }
+
+    fn close(&mut self, offset: usize, after: usize) -> Result<(), BinaryError> {
+        let id = self.open.pop().ok_or_else(|| BinaryError {
+            message: "unmatched end".to_string(),
+            offset,
+        })?;
+        self.tree.nodes[id as usize].end = offset;
+        let branches = &mut self.tree.branches;
+        self.unresolved.retain(|&b| {
+            if branches[b].target != id {
+                return true;
+            }
+            branches[b].target_offset = after;
+            false
+        });
+        Ok(())
+    }
+
+    fn branch(&mut self, instruction: &str, depth: u32, offset: usize) -> Result<(), BinaryError> {
+        let i = self
+            .open
+            .len()
+            .checked_sub(depth as usize + 1)
+            .ok_or_else(|| BinaryError {
+                message: format!("branch depth {} is out of range", depth),
+                offset,
+            })?;
+        let target = self.open[i];
+        let node = &self.tree.nodes[target as usize];
+        if node.kind != "loop" {
+            self.unresolved.push(self.tree.branches.len());
+        }
+        self.tree.branches.push(BranchTarget {
+            offset,
+            instruction: instruction.to_string(),
+            depth,
+            target,
+            target_offset: node.start,
+        });
+        Ok(())
+    }
+}
+
+impl BlockTree {
+    pub fn build(body: &FunctionBody) -> Result<BlockTree, BinaryError> {
+        let mut builder = Builder {
+            tree: BlockTree {
+                nodes: Vec::new(),
+                branches: Vec::new(),
+            },
+            open: Vec::new(),
+            unresolved: Vec::new(),
+        };
+
+        let mut ops = body.get_operators_reader()?.into_iter_with_offsets();
+        let mut next = ops.next().transpose()?;
+        if let Some((_, offset)) = next {
+            builder.open("func", None, offset);
+        }
+        while let Some((op, offset)) = next {
+            next = ops.next().transpose()?;
+            let after = next.as_ref().map_or(body.range().end, |(_, o)| *o);
+            match op {
+                Operator::Block { blockty } => builder.open("block", Some(blockty.into()), offset),
+                Operator::Loop { blockty } => builder.open("loop", Some(blockty.into()), offset),
+                Operator::If { blockty } => builder.open("if", Some(blockty.into()), offset),
+                Operator::Try { blockty } => builder.open("try", Some(blockty.into()), offset),
+                Operator::Else => {
+                    if let Some(&id) = builder.open.last() {
+                        builder.tree.nodes[id as usize].else_offset = Some(offset);
+                    }
+                }
+                Operator::End | Operator::Delegate { .. } => builder.close(offset, after)?,
+                Operator::Br { relative_depth } => builder.branch("br", relative_depth, offset)?,
+                Operator::BrIf { relative_depth } => {
+                    builder.branch("br_if", relative_depth, offset)?
+                }
+                Operator::BrOnNull { relative_depth } => {
+                    builder.branch("br_on_null", relative_depth, offset)?
+                }
+                Operator::BrOnNonNull { relative_depth } => {
+                    builder.branch("br_on_non_null", relative_depth, offset)?
+                }
+                Operator::BrTable { targets } => {
+                    for depth in targets.targets() {
+                        builder.branch("br_table", depth?, offset)?;
+                    }
+                    builder.branch("br_table", targets.default(), offset)?;
+                }
+                _ => {}
+            }
+        }
+
+        if !builder.open.is_empty() {
+            return Err(BinaryError {
+                message: "function body is missing its final end".to_string(),
+                offset: body.range().end,
+            });
+        }
+        Ok(builder.tree)
+    }
+
+    /// Every construct in the function, in the order they open. The function
+    /// body itself is first.
+    pub fn nodes(&self) -> &[BlockNode] {
+        &self.nodes
+    }
+
+    /// Every branch in the function, in order. A `br_table` produces one
+    /// entry per label, with its default label last.
+    pub fn branches(&self) -> &[BranchTarget] {
+        &self.branches
+    }
+}
+
+#[wasm_bindgen]
+impl BlockTree {
+    #[wasm_bindgen(js_name = nodes)]
+    pub fn js_nodes(&self) -> BlockNodeArray {
+        self.nodes.clone().into()
+    }
+
+    #[wasm_bindgen(js_name = branches)]
+    pub fn js_branches(&self) -> BranchTargetArray {
+        self.branches.clone().into()
+    }
+}
@@ -0,0 +1,233 @@
+//! Instruction-by-instruction differences between two versions of a
+//! function.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+use crate::module::ParsedModule;
+use crate::refs::{IndexPolicy, Normalizer};
+use crate::types::{BinaryError, Operator};
+
+/// One step in the alignment of two operator sequences.
+#[wasmtools_struct]
+pub struct OperatorDiff {
+    /// `same`, `removed` (only in the old function) or `added` (only in the
+    /// new one).
+    pub change: String,
+    /// The operator in the old function, with its offset in the old binary.
+    pub old: Option<Operator>,
+    /// The operator in the new function, with its offset in the new binary.
+    pub new: Option<Operator>,
+    /// The operator as it was compared, with references to functions,
+    /// globals and types replaced by their names or structure.
+    pub normalized: String,
+}
+
+enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// How far `middle_snake` searches before giving up, which bounds the time
+/// taken to compare two large functions that are mostly different.
+const MAX_SEARCH: isize = 4096;
+
+/// Finds a shortest edit script between `a` and `b` with the linear space
+/// variant of Myers' algorithm, which is fast when the sequences are similar
+/// and needs memory proportional to their lengths rather than to the square
+/// of the number of edits. Parts that differ in more than about
+/// `2 * MAX_SEARCH` places are shown as removed and re-added rather than
+/// aligned.
+fn myers(a: &[u32], b: &[u32]) -> Vec<Edit> {
+    let max_d = (a.len() + b.len() + 1) / 2 + 1;
+    let mut vf = vec![0; 2 * max_d];
+    let mut vb = vec![0; 2 * max_d];
+    let mut edits = Vec::new();
+    conquer(a, 0, b, 0, &mut vf, &mut vb, &mut edits);
+    edits
+}
+
+fn common_prefix(a: &[u32], b: &[u32]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn common_suffix(a: &[u32], b: &[u32]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Appends the edits turning `a` into `b`, which start at `a_start` and
+/// `b_start` in the full sequences, by splitting both at the middle of a
+/// shortest edit script and recursing on either half.
+fn conquer(
+    a: &[u32],
+    a_start: usize,
+    b: &[u32],
+    b_start: usize,
+    vf: &mut [isize],
+    vb: &mut [isize],
+    edits: &mut Vec<Edit>,
+) {
+    let prefix = common_prefix(a, b);
+    edits.extend((0..prefix).map(|i| Edit::Same(a_start + i, b_start + i)));
+    let (a, a_start) = (&a[prefix..], a_start + prefix);
+    let (b, b_start) = (&b[prefix..], b_start + prefix);
+    let suffix = common_suffix(a, b);
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    match middle_snake(a, b, vf, vb) {
+        Some((x, y)) => {
+            conquer(&a[..x], a_start, &b[..y], b_start, vf, vb, edits);
+            conquer(&a[x..], a_start + x, &b[y..], b_start + y, vf, vb, edits);
+        }
+        None => {
+            edits.extend((0..a.len()).map(|i| Edit::Removed(a_start + i)));
+            edits.extend((0..b.len()).map(|j| Edit::Added(b_start + j)));
+        }
+    }
+
+    let (a_end, b_end) = (a_start + a.len(), b_start + b.len());
+    edits.extend((0..suffix).map(|i| Edit::Same(a_end + i, b_end + i)));
+}
+
+/// Searches forwards from the start and backwards from the end of `a` and
+/// `b` at once until the two searches meet, returning the point where they
+/// meet. Returns `None` if either sequence is empty, since there is nothing
+/// to split, or if the searches go on for longer than `MAX_SEARCH` steps.
+/// `vf` and `vb` must be at least as long as `myers` makes them.
+fn middle_snake(
+    a: &[u32],
+    b: &[u32],
+    vf: &mut [isize],
+    vb: &mut [isize],
+) -> Option<(usize, usize)> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta & 1 == 1;
+    // `vf[k]` is the furthest x reached on diagonal k = x - y going
+    // forwards, and `vb[k]` the furthest going backwards from the end.
+    let mid = (vf.len() / 2) as isize;
+    let at = |k: isize| (mid + k) as usize;
+    vf[at(1)] = 0;
+    vb[at(1)] = 0;
+
+    for d in 0..=((n + m + 1) / 2).min(MAX_SEARCH) {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vf[at(k - 1)] < vf[at(k + 1)]) {
+                vf[at(k + 1)]
+            } else {
+                vf[at(k - 1)] + 1
+            };
+            let y = x - k;
+            let (x0, y0) = (x, y);
+            if x < n && y < m {
+                x += common_prefix(&a[x as usize..], &b[y as usize..]) as isize;
+            }
+            vf[at(k)] = x;
+            if odd && (k - delta).abs() < d && vf[at(k)] + vb[at(delta - k)] >= n {
+                return Some((x0 as usize, y0 as usize));
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vb[at(k - 1)] < vb[at(k + 1)]) {
+                vb[at(k + 1)]
+            } else {
+                vb[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            if x < n && y < m {
+                let snake = common_suffix(&a[..(n - x) as usize], &b[..(m - y) as usize]);
+                x += snake as isize;
+                y += snake as isize;
+            }
+            vb[at(k)] = x;
+            if !odd && (k - delta).abs() <= d && vb[at(k)] + vf[at(delta - k)] >= n {
+                return Some(((n - x) as usize, (m - y) as usize));
+            }
+        }
+    }
+    // Without the limit, the searches always meet by the time each has
+    // covered half of the longest possible edit script.
+    None
+}
+
+fn read_ops(
+    module: &ParsedModule,
+    func_index: u32,
+) -> Result<Vec<(Operator, String)>, BinaryError> {
+    let body = module.func_body(func_index).ok_or_else(|| BinaryError {
+        message: format!("function {} has no body", func_index),
+        offset: 0,
+    })?;
+    let normalizer = Normalizer {
+        module,
+        indices: IndexPolicy::Names,
+    };
+    let mut ops = Vec::new();
+    for op in body.get_operators_reader()?.into_iter_with_offsets() {
+        let (op, offset) = op?;
+        let normalized = normalizer.operator(&op);
+        ops.push((Operator::from_wasm(op, offset), normalized));
+    }
+    Ok(ops)
+}
+
+/// Aligns the operators of a function in one module with those of a function
+/// in another, typically two builds of the same function.
+///
+/// Operators are compared with their immediates, except that functions and
+/// globals are compared by name and types by structure, so that operators
+/// aren't reported as changed just because an index shifted. Items without a
+/// name in the name section (or an import or export name, for functions)
+/// are still compared by index.
+pub fn diff_bodies(
+    old: &ParsedModule,
+    old_func: u32,
+    new: &ParsedModule,
+    new_func: u32,
+) -> Result<Vec<OperatorDiff>, BinaryError> {
+    let old_ops = read_ops(old, old_func)?;
+    let new_ops = read_ops(new, new_func)?;
+
+    // Compare small ids rather than strings.
+    let mut ids: HashMap<&str, u32> = HashMap::new();
+    let mut intern = |normalized| {
+        let next = ids.len() as u32;
+        *ids.entry(normalized).or_insert(next)
+    };
+    let a: Vec<u32> = old_ops.iter().map(|(_, n)| intern(n.as_str())).collect();
+    let b: Vec<u32> = new_ops.iter().map(|(_, n)| intern(n.as_str())).collect();
+
+    Ok(myers(&a, &b)
+        .into_iter()
+        .map(|edit| match edit {
+            Edit::Same(i, j) => OperatorDiff {
+                change: "same".to_string(),
+                old: Some(old_ops[i].0.clone()),
+                new: Some(new_ops[j].0.clone()),
+                normalized: new_ops[j].1.clone(),
+            },
+            Edit::Removed(i) => OperatorDiff {
+                change: "removed".to_string(),
+                old: Some(old_ops[i].0.clone()),
+                new: None,
+                normalized: old_ops[i].1.clone(),
+            },
+            Edit::Added(j) => OperatorDiff {
+                change: "added".to_string(),
+                old: None,
+                new: Some(new_ops[j].0.clone()),
+                normalized: new_ops[j].1.clone(),
+            },
+        })
+        .collect())
+}
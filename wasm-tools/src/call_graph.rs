@@ -0,0 +1,421 @@
+use js_sys::Array;
+use macros::*;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+use wasm_bindgen::prelude::*;
+use wasmparser::{ConstExpr, ElementItems, ExternalKind, FuncType, HeapType, Operator, TypeRef};
+
+use crate::json::to_json;
+use crate::module::ParsedModule;
+use crate::types::BinaryError;
+use crate::util::dot_string;
+
+/// The way in which one function refers to another.
+#[wasmtools_enum]
+pub enum CallKind {
+    /// A direct `call`.
+    call,
+    /// A direct tail call via `return_call`.
+    return_call,
+    /// A `call_indirect` or `return_call_indirect`.
+    call_indirect,
+    /// A `call_ref` or `return_call_ref`.
+    call_ref,
+    /// The function's reference is taken with `ref.func`.
+    ref_func,
+}
+
+/// An edge in the call graph. Indirect calls produce a single edge to the
+/// group of functions they may reach, rather than one edge per function.
+#[wasmtools_struct]
+pub struct CallEdge {
+    pub caller: u32,
+    /// The function called, or referenced by `ref.func`. Unset for indirect
+    /// calls.
+    pub callee: Option<u32>,
+    /// For indirect calls, the index of the `CallTargets` the call may reach.
+    pub targets: Option<u32>,
+    pub kind: CallKind,
+    /// The byte offset of the instruction that makes the call.
+    pub offset: usize,
+}
+
+/// The functions that indirect calls of one type may reach.
+#[wasmtools_struct]
+pub struct CallTargets {
+    /// The type index of the calls, or unset if they may call a function of
+    /// any type.
+    pub type_index: Option<u32>,
+    pub funcs: Vec<u32>,
+}
+
+#[wasm_bindgen]
+pub struct CallGraph {
+    edges: Vec<CallEdge>,
+    /// Indices into `edges` of the calls made by each function.
+    callees: Vec<Vec<usize>>,
+    /// Indices into `edges` of the direct calls made to each function.
+    callers: Vec<Vec<usize>>,
+    targets: Vec<CallTargets>,
+    /// Indices into `edges` of the indirect calls that reach each of
+    /// `targets`.
+    target_callers: Vec<Vec<usize>>,
+    /// Indices into `targets` of the groups each function is in.
+    target_groups: Vec<Vec<u32>>,
+    names: Vec<String>,
+    num_imported_funcs: u32,
+}
+
+/// Collects the functions referenced by `ref.func` in a constant expression.
+fn const_expr_funcs(expr: &ConstExpr, funcs: &mut BTreeSet<u32>) -> Result<(), BinaryError> {
+    for op in expr.get_operators_reader() {
+        if let Operator::RefFunc { function_index } = op? {
+            funcs.insert(function_index);
+        }
+    }
+    Ok(())
+}
+
+impl CallGraph {
+    /// Builds the call graph for every function in the module.
+    ///
+    /// Indirect calls are resolved conservatively: `call_indirect` and
+    /// `call_ref` may target any function whose reference escapes (through
+    /// element segments or `ref.func`) and whose type matches the call's
+    /// type. If a table is imported or exported, the host may also place
+    /// imported or exported functions in it, so those are included too.
+    /// Calls of the same type share a single `CallTargets`.
+    pub fn build(module: &ParsedModule) -> Result<CallGraph, BinaryError> {
+        let num_funcs = module.num_funcs() as usize;
+        let mut graph = CallGraph {
+            edges: Vec::new(),
+            callees: vec![Vec::new(); num_funcs],
+            callers: vec![Vec::new(); num_funcs],
+            targets: Vec::new(),
+            target_callers: Vec::new(),
+            target_groups: vec![Vec::new(); num_funcs],
+            names: module.func_display_names(),
+            num_imported_funcs: module.num_imported_funcs(),
+        };
+
+        let mut escaping = BTreeSet::new();
+        for element in module.elements.iter() {
+            match &element.value.items {
+                ElementItems::Functions(funcs) => {
+                    for f in funcs.clone() {
+                        escaping.insert(f?);
+                    }
+                }
+                ElementItems::Expressions(exprs) => {
+                    for expr in exprs.clone() {
+                        const_expr_funcs(&expr?, &mut escaping)?;
+                    }
+                }
+            }
+        }
+        for global in module.globals.iter() {
+            const_expr_funcs(&global.value.init_expr, &mut escaping)?;
+        }
+        let host_visible_tables = module
+            .imports
+            .iter()
+            .any(|i| matches!(i.value.ty, TypeRef::Table(_)))
+            || module
+                .exports
+                .iter()
+                .any(|e| e.value.kind == ExternalKind::Table);
+        if host_visible_tables {
+            escaping.extend(0..module.num_imported_funcs());
+            for export in module.exports.iter() {
+                if export.value.kind == ExternalKind::Func {
+                    escaping.insert(export.value.index);
+                }
+            }
+        }
+
+        // Direct calls and ref.func first, since ref.func in a body makes a
+        // function a potential indirect call target everywhere.
+        let mut indirect_calls = Vec::new();
+        for (i, body) in module.bodies.iter().enumerate() {
+            let caller = module.num_imported_funcs() + i as u32;
+            for op in body.get_operators_reader()?.into_iter_with_offsets() {
+                let (op, offset) = op?;
+                let (callee, kind) = match op {
+                    Operator::Call { function_index } => (function_index, CallKind::new_call()),
+                    Operator::ReturnCall { function_index } => {
+                        (function_index, CallKind::new_return_call())
+                    }
+                    Operator::RefFunc { function_index } => {
+                        escaping.insert(function_index);
+                        (function_index, CallKind::new_ref_func())
+                    }
+                    Operator::CallIndirect { type_index, .. }
+                    | Operator::ReturnCallIndirect { type_index, .. } => {
+                        indirect_calls.push((caller, Some(type_index), offset, false));
+                        continue;
+                    }
+                    Operator::CallRef { hty } | Operator::ReturnCallRef { hty } => {
+                        let type_index = match hty {
+                            HeapType::TypedFunc(t) => Some(t),
+                            _ => None,
+                        };
+                        indirect_calls.push((caller, type_index, offset, true));
+                        continue;
+                    }
+                    _ => continue,
+                };
+                graph.add_edge(CallEdge {
+                    caller,
+                    callee: Some(callee),
+                    targets: None,
+                    kind,
+                    offset,
+                });
+            }
+        }
+
+        // Types are compared by structure, so equal types at different
+        // indices share their targets.
+        let mut escaping_by_type: HashMap<&FuncType, Vec<u32>> = HashMap::new();
+        for &func in escaping.iter() {
+            if let Some(ty) = module.func_type(func) {
+                escaping_by_type.entry(ty).or_default().push(func);
+            }
+        }
+        let mut groups: HashMap<Option<&FuncType>, u32> = HashMap::new();
+        for (caller, type_index, offset, is_ref) in indirect_calls {
+            let call_type = type_index
+                .and_then(|t| module.types.get(t as usize))
+                .map(|t| &t.value);
+            let group = match groups.get(&call_type) {
+                Some(&group) => group,
+                None => {
+                    let funcs = match call_type {
+                        Some(ty) => escaping_by_type.get(ty).cloned().unwrap_or_default(),
+                        None => escaping.iter().copied().collect(),
+                    };
+                    let group = graph.add_targets(CallTargets {
+                        type_index: call_type.and(type_index),
+                        funcs,
+                    });
+                    groups.insert(call_type, group);
+                    group
+                }
+            };
+            graph.add_edge(CallEdge {
+                caller,
+                callee: None,
+                targets: Some(group),
+                kind: if is_ref {
+                    CallKind::new_call_ref()
+                } else {
+                    CallKind::new_call_indirect()
+                },
+                offset,
+            });
+        }
+
+        Ok(graph)
+    }
+
+    fn add_edge(&mut self, edge: CallEdge) {
+        let caller = edge.caller as usize;
+        let index = self.edges.len();
+        let callers = match (edge.callee, edge.targets) {
+            (Some(callee), _) => self.callers.get_mut(callee as usize),
+            (None, Some(group)) => self.target_callers.get_mut(group as usize),
+            (None, None) => None,
+        };
+        match callers {
+            Some(callers) if caller < self.callees.len() => callers.push(index),
+            // Invalid function index; the viewer reports these elsewhere.
+            _ => return,
+        }
+        self.callees[caller].push(index);
+        self.edges.push(edge);
+    }
+
+    fn add_targets(&mut self, targets: CallTargets) -> u32 {
+        let group = self.targets.len() as u32;
+        for &func in targets.funcs.iter() {
+            if let Some(groups) = self.target_groups.get_mut(func as usize) {
+                groups.push(group);
+            }
+        }
+        self.targets.push(targets);
+        self.target_callers.push(Vec::new());
+        group
+    }
+
+    pub fn edges(&self) -> &[CallEdge] {
+        &self.edges
+    }
+
+    /// The calls made by the given function.
+    pub fn callees_of(&self, func_idx: u32) -> impl Iterator<Item = &CallEdge> {
+        let edges = self.callees.get(func_idx as usize);
+        edges.into_iter().flatten().map(|&e| &self.edges[e])
+    }
+
+    /// The calls made to the given function, including the indirect calls
+    /// that may reach it.
+    pub fn callers_of(&self, func_idx: u32) -> impl Iterator<Item = &CallEdge> {
+        let direct = self.callers.get(func_idx as usize).into_iter().flatten();
+        let indirect = self
+            .target_groups
+            .get(func_idx as usize)
+            .into_iter()
+            .flatten()
+            .flat_map(|&group| self.target_callers[group as usize].iter());
+        direct.chain(indirect).map(|&e| &self.edges[e])
+    }
+
+    /// The functions that the indirect calls sharing the given group may
+    /// reach.
+    pub fn targets(&self, group: u32) -> Option<&CallTargets> {
+        self.targets.get(group as usize)
+    }
+
+    /// The functions reachable through calls from the given function,
+    /// including itself.
+    pub fn reachable_from(&self, func_idx: u32) -> BTreeSet<u32> {
+        let mut seen = BTreeSet::new();
+        let mut seen_targets = HashSet::new();
+        let mut stack = vec![func_idx];
+        while let Some(f) = stack.pop() {
+            if !seen.insert(f) {
+                continue;
+            }
+            for edge in self.callees_of(f) {
+                stack.extend(edge.callee);
+                if let Some(group) = edge.targets {
+                    if seen_targets.insert(group) {
+                        stack.extend(self.targets[group as usize].funcs.iter());
+                    }
+                }
+            }
+        }
+        seen
+    }
+}
+
+#[wasm_bindgen]
+impl CallGraph {
+    #[wasm_bindgen(js_name = callees)]
+    pub fn js_callees(&self, func_idx: u32) -> CallEdgeArray {
+        self.callees_of(func_idx)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    #[wasm_bindgen(js_name = callers)]
+    pub fn js_callers(&self, func_idx: u32) -> CallEdgeArray {
+        self.callers_of(func_idx)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    #[wasm_bindgen(js_name = targets)]
+    pub fn js_targets(&self, group: u32) -> Option<CallTargets> {
+        self.targets(group).cloned()
+    }
+
+    #[wasm_bindgen(js_name = reachable_from)]
+    pub fn js_reachable_from(&self, func_idx: u32) -> Vec<u32> {
+        self.reachable_from(func_idx).into_iter().collect()
+    }
+
+    /// Renders the graph in Graphviz DOT format. Multiple calls between the
+    /// same pair of functions are collapsed into a single edge, and indirect
+    /// calls go through a node for their `CallTargets`.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n  node [shape=box];\n");
+        for (i, name) in self.names.iter().enumerate() {
+            let style = if (i as u32) < self.num_imported_funcs {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            let _ = writeln!(out, "  f{} [label={}{}];", i, dot_string(name), style);
+        }
+
+        for (i, targets) in self.targets.iter().enumerate() {
+            let label = match targets.type_index {
+                Some(type_index) => format!("type {}", type_index),
+                None => "any type".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "  t{} [label={}, shape=ellipse, style=dashed];",
+                i,
+                dot_string(&label)
+            );
+            for func in targets.funcs.iter() {
+                let _ = writeln!(out, "  t{} -> f{} [style=dashed];", i, func);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for edge in self.edges.iter() {
+            let target = match (edge.callee, edge.targets) {
+                (Some(callee), _) => format!("f{}", callee),
+                (None, Some(group)) => format!("t{}", group),
+                (None, None) => continue,
+            };
+            if !seen.insert((edge.caller, target.clone(), edge.kind.kind.as_str())) {
+                continue;
+            }
+            let style = match edge.kind.kind.as_str() {
+                "return_call" => " [style=bold]",
+                "call_indirect" | "call_ref" => " [style=dashed]",
+                "ref_func" => " [style=dotted, arrowhead=odot]",
+                _ => "",
+            };
+            let _ = writeln!(out, "  f{} -> {}{};", edge.caller, target, style);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Renders the graph as JSON, in the form
+    /// `{ "functions": [{ "index", "name", "imported" }], "edges": [CallEdge], "targets": [CallTargets] }`,
+    /// with edges and targets in the same shape as their TypeScript
+    /// definitions.
+    pub fn to_json(&self) -> Result<String, BinaryError> {
+        let functions = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| FunctionJson {
+                index: i as u32,
+                name,
+                imported: (i as u32) < self.num_imported_funcs,
+            })
+            .collect();
+        to_json(
+            &CallGraphJson {
+                functions,
+                edges: &self.edges,
+                targets: &self.targets,
+            },
+            false,
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct FunctionJson<'g> {
+    index: u32,
+    name: &'g str,
+    imported: bool,
+}
+
+#[derive(Serialize)]
+struct CallGraphJson<'g> {
+    functions: Vec<FunctionJson<'g>>,
+    edges: &'g [CallEdge],
+    targets: &'g [CallTargets],
+}
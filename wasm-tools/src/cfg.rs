@@ -0,0 +1,426 @@
+//! Control flow graphs for individual function bodies.
+
+use js_sys::Array;
+use macros::*;
+use wasm_bindgen::prelude::*;
+use wasmparser::{FunctionBody, Operator as ParserOperator};
+
+use crate::types::{BinaryError, Operator, OperatorArray};
+
+/// The reason control can pass from one basic block to another.
+#[wasmtools_enum]
+pub enum CfgEdgeKind {
+    /// Control falls through to the next block, e.g. past the `end` of a
+    /// block that is also the target of a branch.
+    fallthrough,
+    /// An unconditional `br`.
+    br,
+    /// A conditional branch (`br_if`, `br_on_null` or `br_on_non_null`) is
+    /// taken.
+    br_if_taken,
+    /// A conditional branch is not taken, and control continues after it.
+    br_if_not_taken,
+    /// One of the labels of a `br_table`.
+    br_table,
+    /// The default label of a `br_table`.
+    br_table_default,
+    /// The condition of an `if` is true.
+    if_true,
+    /// The condition of an `if` is false, so control goes to the `else`
+    /// branch, or past the `end` if there is none.
+    if_false,
+    /// An exception thrown inside a `try` is caught by a `catch` or
+    /// `catch_all`.
+    catch,
+    /// The function returns to the exit block, either with `return`, a tail
+    /// call, a branch to the function's label, or by reaching its final
+    /// `end`.
+    exit,
+}
+
+/// A straight-line sequence of operators with a single entry point.
+#[wasmtools_struct]
+pub struct BasicBlock {
+    pub id: u32,
+    /// The offset of the block's first operator.
+    pub start: usize,
+    /// The offset just past the block's last operator.
+    pub end: usize,
+    /// The index of the block's first operator in the graph's operator list.
+    pub first_operator: usize,
+    pub num_operators: usize,
+}
+
+#[wasmtools_struct]
+pub struct CfgEdge {
+    pub from: u32,
+    pub to: u32,
+    pub kind: CfgEdgeKind,
+    /// The offset of the operator that transfers control.
+    pub offset: usize,
+}
+
+/// The control flow graph of a function body. The last block is a synthetic,
+/// empty exit block that every returning path leads to.
+#[wasm_bindgen]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+    edges: Vec<CfgEdge>,
+    operators: Vec<Operator>,
+}
+
+enum FrameKind {
+    Function,
+    Block,
+    /// A loop, whose label refers to the block starting at the `loop`.
+    Loop(u32),
+    /// An `if`, along with the block that ends in the `if` until an `else` is
+    /// reached.
+    If(Option<u32>),
+    /// A `try`, along with the block that starts the `try` body.
+    Try(u32),
+}
+
+struct Frame {
+    kind: FrameKind,
+    /// Branches to the end of this frame, which are resolved once the block
+    /// after the `end` exists.
+    pending: Vec<(u32, CfgEdgeKind, usize)>,
+}
+
+struct Builder {
+    graph: ControlFlowGraph,
+    frames: Vec<Frame>,
+    /// The block that operators are currently added to, or `None` if the
+    /// previous operator never falls through, so the next one starts a new
+    /// block.
+    current: Option<u32>,
+}
+
+impl Builder {
+    fn start_block(&mut self, offset: usize) -> u32 {
+        let id = self.graph.blocks.len() as u32;
+        self.graph.blocks.push(BasicBlock {
+            id,
+            start: offset,
+            end: offset,
+            first_operator: self.graph.operators.len(),
+            num_operators: 0,
+        });
+        self.current = Some(id);
+        id
+    }
+
+    /// Adds an operator to the current block, starting a new one if needed,
+    /// and returns the block it was added to.
+    fn push(&mut self, op: &ParserOperator, offset: usize, end: usize) -> u32 {
+        let id = match self.current {
+            Some(id) => id,
+            None => self.start_block(offset),
+        };
+        let block = &mut self.graph.blocks[id as usize];
+        block.end = end;
+        block.num_operators += 1;
+        self.graph
+            .operators
+            .push(Operator::from_wasm(op.clone(), offset));
+        id
+    }
+
+    fn edge(&mut self, from: u32, to: u32, kind: CfgEdgeKind, offset: usize) {
+        self.graph.edges.push(CfgEdge {
+            from,
+            to,
+            kind,
+            offset,
+        });
+    }
+
+    /// Records a branch to the label at `depth`.
+    fn branch(
+        &mut self,
+        from: u32,
+        depth: u32,
+        kind: CfgEdgeKind,
+        offset: usize,
+    ) -> Result<(), BinaryError> {
+        let i = self
+            .frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .ok_or_else(|| BinaryError {
+                message: format!("branch depth {} is out of range", depth),
+                offset,
+            })?;
+        match self.frames[i].kind {
+            FrameKind::Loop(header) => self.edge(from, header, kind, offset),
+            FrameKind::Function => {
+                let kind = CfgEdgeKind::new_exit();
+                self.frames[i].pending.push((from, kind, offset))
+            }
+            _ => self.frames[i].pending.push((from, kind, offset)),
+        }
+        Ok(())
+    }
+
+    /// Ends the current block and starts a new one at `offset` that the old
+    /// one falls through to. An empty current block is reused instead.
+    fn split(&mut self, offset: usize, kind: CfgEdgeKind, edge_offset: usize) -> u32 {
+        let prev = self.current;
+        if let Some(prev) = prev {
+            if self.graph.blocks[prev as usize].num_operators == 0 {
+                return prev;
+            }
+        }
+        let next = self.start_block(offset);
+        if let Some(prev) = prev {
+            self.edge(prev, next, kind, edge_offset);
+        }
+        next
+    }
+
+    fn frame(&mut self, offset: usize) -> Result<&mut Frame, BinaryError> {
+        self.frames.last_mut().ok_or_else(|| BinaryError {
+            message: "operator outside of any block".to_string(),
+            offset,
+        })
+    }
+
+    fn visit(&mut self, op: &ParserOperator, offset: usize, end: usize) -> Result<(), BinaryError> {
+        match *op {
+            ParserOperator::Loop { .. } => {
+                let header = self.split(offset, CfgEdgeKind::new_fallthrough(), offset);
+                self.push(op, offset, end);
+                self.frames.push(Frame {
+                    kind: FrameKind::Loop(header),
+                    pending: Vec::new(),
+                });
+            }
+            ParserOperator::Try { .. } => {
+                let body = self.split(offset, CfgEdgeKind::new_fallthrough(), offset);
+                self.push(op, offset, end);
+                self.frames.push(Frame {
+                    kind: FrameKind::Try(body),
+                    pending: Vec::new(),
+                });
+            }
+            ParserOperator::Block { .. } => {
+                self.push(op, offset, end);
+                self.frames.push(Frame {
+                    kind: FrameKind::Block,
+                    pending: Vec::new(),
+                });
+            }
+            ParserOperator::If { .. } => {
+                let cond = self.push(op, offset, end);
+                let then = self.start_block(end);
+                self.edge(cond, then, CfgEdgeKind::new_if_true(), offset);
+                self.frames.push(Frame {
+                    kind: FrameKind::If(Some(cond)),
+                    pending: Vec::new(),
+                });
+            }
+            ParserOperator::Else => {
+                let prev = self.current;
+                let frame = self.frame(offset)?;
+                if let Some(prev) = prev {
+                    let kind = CfgEdgeKind::new_fallthrough();
+                    frame.pending.push((prev, kind, offset));
+                }
+                let cond = match &mut frame.kind {
+                    FrameKind::If(cond) => cond.take(),
+                    _ => None,
+                };
+                let els = self.start_block(offset);
+                self.push(op, offset, end);
+                if let Some(cond) = cond {
+                    self.edge(cond, els, CfgEdgeKind::new_if_false(), offset);
+                }
+            }
+            ParserOperator::Catch { .. } | ParserOperator::CatchAll => {
+                let prev = self.current;
+                let frame = self.frame(offset)?;
+                if let Some(prev) = prev {
+                    let kind = CfgEdgeKind::new_fallthrough();
+                    frame.pending.push((prev, kind, offset));
+                }
+                let body = match frame.kind {
+                    FrameKind::Try(body) => Some(body),
+                    _ => None,
+                };
+                let handler = self.start_block(offset);
+                self.push(op, offset, end);
+                if let Some(body) = body {
+                    self.edge(body, handler, CfgEdgeKind::new_catch(), offset);
+                }
+            }
+            ParserOperator::End | ParserOperator::Delegate { .. } => {
+                let mut frame = self.frames.pop().ok_or_else(|| BinaryError {
+                    message: "unmatched end".to_string(),
+                    offset,
+                })?;
+                if let FrameKind::If(Some(cond)) = frame.kind {
+                    let kind = CfgEdgeKind::new_if_false();
+                    frame.pending.push((cond, kind, offset));
+                }
+
+                if let FrameKind::Function = frame.kind {
+                    let reachable = self.current.is_some();
+                    let from = self.push(op, offset, end);
+                    if reachable {
+                        frame.pending.push((from, CfgEdgeKind::new_exit(), offset));
+                    }
+                    let exit = self.start_block(end);
+                    for (from, kind, offset) in frame.pending {
+                        self.edge(from, exit, kind, offset);
+                    }
+                    self.current = None;
+                } else if frame.pending.is_empty() {
+                    // Nothing branches here, so the current block carries on
+                    // past the `end`.
+                    self.push(op, offset, end);
+                } else {
+                    let next = self.split(offset, CfgEdgeKind::new_fallthrough(), offset);
+                    self.push(op, offset, end);
+                    for (from, kind, offset) in frame.pending {
+                        self.edge(from, next, kind, offset);
+                    }
+                }
+            }
+            ParserOperator::Br { relative_depth } => {
+                let from = self.push(op, offset, end);
+                self.branch(from, relative_depth, CfgEdgeKind::new_br(), offset)?;
+                self.current = None;
+            }
+            ParserOperator::BrIf { relative_depth }
+            | ParserOperator::BrOnNull { relative_depth }
+            | ParserOperator::BrOnNonNull { relative_depth } => {
+                let from = self.push(op, offset, end);
+                self.branch(from, relative_depth, CfgEdgeKind::new_br_if_taken(), offset)?;
+                self.split(end, CfgEdgeKind::new_br_if_not_taken(), offset);
+            }
+            ParserOperator::BrTable { ref targets } => {
+                let from = self.push(op, offset, end);
+                for target in targets.targets() {
+                    self.branch(from, target?, CfgEdgeKind::new_br_table(), offset)?;
+                }
+                let default = targets.default();
+                self.branch(from, default, CfgEdgeKind::new_br_table_default(), offset)?;
+                self.current = None;
+            }
+            ParserOperator::Return
+            | ParserOperator::ReturnCall { .. }
+            | ParserOperator::ReturnCallIndirect { .. }
+            | ParserOperator::ReturnCallRef { .. } => {
+                let from = self.push(op, offset, end);
+                let function = self.frames.first_mut().ok_or_else(|| BinaryError {
+                    message: "operator outside of any block".to_string(),
+                    offset,
+                })?;
+                function
+                    .pending
+                    .push((from, CfgEdgeKind::new_exit(), offset));
+                self.current = None;
+            }
+            ParserOperator::Unreachable
+            | ParserOperator::Throw { .. }
+            | ParserOperator::Rethrow { .. } => {
+                self.push(op, offset, end);
+                self.current = None;
+            }
+            _ => {
+                self.push(op, offset, end);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ControlFlowGraph {
+    /// Splits a function body into basic blocks.
+    ///
+    /// A new block starts after every branch, at each `loop` and `try` (so
+    /// that branches and exceptions have somewhere to land), at each `else`,
+    /// `catch` and `catch_all`, and at an `end` that something branches to.
+    /// Code that can never run, such as the code after a `br`, gets blocks
+    /// with no incoming edges.
+    pub fn build(body: &FunctionBody) -> Result<ControlFlowGraph, BinaryError> {
+        let mut ops = Vec::new();
+        for op in body.get_operators_reader()?.into_iter_with_offsets() {
+            ops.push(op?);
+        }
+
+        let mut builder = Builder {
+            graph: ControlFlowGraph {
+                blocks: Vec::new(),
+                edges: Vec::new(),
+                operators: Vec::with_capacity(ops.len()),
+            },
+            frames: vec![Frame {
+                kind: FrameKind::Function,
+                pending: Vec::new(),
+            }],
+            current: None,
+        };
+        let body_end = body.range().end;
+        for (i, (op, offset)) in ops.iter().enumerate() {
+            let end = ops.get(i + 1).map_or(body_end, |(_, next)| *next);
+            builder.visit(op, *offset, end)?;
+        }
+        if !builder.frames.is_empty() {
+            return Err(BinaryError {
+                message: "function body is missing its final end".to_string(),
+                offset: body_end,
+            });
+        }
+        Ok(builder.graph)
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn edges(&self) -> &[CfgEdge] {
+        &self.edges
+    }
+
+    /// The operators in the given block.
+    pub fn block_operators(&self, block: u32) -> &[Operator] {
+        match self.blocks.get(block as usize) {
+            Some(b) => &self.operators[b.first_operator..][..b.num_operators],
+            None => &[],
+        }
+    }
+
+    pub fn successors(&self, block: u32) -> impl Iterator<Item = &CfgEdge> {
+        self.edges.iter().filter(move |e| e.from == block)
+    }
+
+    pub fn predecessors(&self, block: u32) -> impl Iterator<Item = &CfgEdge> {
+        self.edges.iter().filter(move |e| e.to == block)
+    }
+}
+
+#[wasm_bindgen]
+impl ControlFlowGraph {
+    #[wasm_bindgen(js_name = blocks)]
+    pub fn js_blocks(&self) -> BasicBlockArray {
+        self.blocks.clone().into()
+    }
+
+    #[wasm_bindgen(js_name = edges)]
+    pub fn js_edges(&self) -> CfgEdgeArray {
+        self.edges.clone().into()
+    }
+
+    /// Every operator in the function, in order. Each block's operators are a
+    /// contiguous run of this list.
+    pub fn operators(&self) -> OperatorArray {
+        self.operators.clone().into()
+    }
+
+    #[wasm_bindgen(js_name = block_operators)]
+    pub fn js_block_operators(&self, block: u32) -> OperatorArray {
+        self.block_operators(block).to_vec().into()
+    }
+}
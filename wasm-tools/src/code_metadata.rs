@@ -0,0 +1,69 @@
+//! Readers for the `metadata.code.*` family of custom sections, which attach
+//! opaque metadata to individual instructions. wasmparser doesn't know about
+//! these yet, so they're defined here in the same style.
+//!
+//! See https://github.com/WebAssembly/tool-conventions/blob/main/CodeMetadata.md
+//! and https://github.com/WebAssembly/branch-hinting.
+
+use wasmparser::{BinaryReader, FromReader, Result, SectionLimited};
+
+/// The prefix shared by the names of all code metadata custom sections.
+pub const CODE_METADATA_PREFIX: &str = "metadata.code.";
+
+/// A reader for a code metadata custom section, not including the section name.
+pub type CodeMetadataSectionReader<'a> = SectionLimited<'a, FunctionCodeMetadata<'a>>;
+
+/// The metadata for all annotated instructions in a single function.
+#[derive(Clone)]
+pub struct FunctionCodeMetadata<'a> {
+    /// The index of the function, including imported functions.
+    pub func_index: u32,
+    /// The metadata for each annotated instruction, in offset order.
+    pub metadata: SectionLimited<'a, CodeMetadata<'a>>,
+}
+
+impl<'a> FromReader<'a> for FunctionCodeMetadata<'a> {
+    fn from_reader(reader: &mut BinaryReader<'a>) -> Result<Self> {
+        let func_index = reader.read_var_u32()?;
+
+        // Skip over the entries to find where this function's metadata ends.
+        let mut entries = reader.clone();
+        let start = reader.original_position();
+        let count = reader.read_var_u32()?;
+        for _ in 0..count {
+            CodeMetadata::from_reader(reader)?;
+        }
+        let entries = entries.read_bytes(reader.original_position() - start)?;
+
+        Ok(FunctionCodeMetadata {
+            func_index,
+            metadata: SectionLimited::new(entries, start)?,
+        })
+    }
+}
+
+/// A single piece of code metadata.
+#[derive(Debug, Clone)]
+pub struct CodeMetadata<'a> {
+    /// The byte offset of the annotated instruction, relative to the start of
+    /// the function body (i.e. the start of its locals).
+    pub offset: u32,
+    /// The metadata payload. Its meaning depends on the kind of section.
+    pub data: &'a [u8],
+    /// The byte offset of this entry within the module.
+    pub entry_offset: usize,
+}
+
+impl<'a> FromReader<'a> for CodeMetadata<'a> {
+    fn from_reader(reader: &mut BinaryReader<'a>) -> Result<Self> {
+        let entry_offset = reader.original_position();
+        let offset = reader.read_var_u32()?;
+        let size = reader.read_var_u32()?;
+        let data = reader.read_bytes(size as usize)?;
+        Ok(CodeMetadata {
+            offset,
+            data,
+            entry_offset,
+        })
+    }
+}
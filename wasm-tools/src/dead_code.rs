@@ -0,0 +1,61 @@
+use js_sys::Array;
+use macros::*;
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+
+use crate::module::{ItemKind, ParsedModule};
+use crate::refs::{implicit_references, references, roots};
+use crate::types::BinaryError;
+
+/// An item that cannot be reached from any of the module's roots.
+#[wasmtools_struct]
+pub struct DeadItem {
+    /// The kind of item, e.g. `func` or `global`.
+    pub kind: String,
+    pub index: u32,
+    pub name: String,
+    pub imported: bool,
+    /// The number of bytes the item occupies in the binary.
+    pub size: usize,
+}
+
+/// Finds every item that is not reachable from the module's exports or start
+/// function.
+///
+/// Active element and data segments are considered live as long as the table
+/// or memory they initialize is live, since they are applied at instantiation.
+/// Passive segments must be used by an instruction, and declarative segments
+/// are live if any function they declare is live.
+pub fn find_dead_items(module: &ParsedModule) -> Result<Vec<DeadItem>, BinaryError> {
+    let implicit = implicit_references(module)?;
+    let mut worklist = roots(module);
+    let mut live = HashSet::new();
+    while let Some(item) = worklist.pop() {
+        if !live.insert(item) {
+            continue;
+        }
+        for r in references(module, item)? {
+            if r.target.kind != ItemKind::Local {
+                worklist.push(r.target);
+            }
+        }
+        if let Some(targets) = implicit.get(&item) {
+            worklist.extend(targets.iter().copied());
+        }
+    }
+
+    let mut dead = Vec::new();
+    for item in module.items() {
+        if live.contains(&item) {
+            continue;
+        }
+        dead.push(DeadItem {
+            kind: item.kind.name().to_string(),
+            index: item.index,
+            name: module.display_name(item),
+            imported: item.index < module.num_imported(item.kind),
+            size: module.item_size(item),
+        });
+    }
+    Ok(dead)
+}
@@ -0,0 +1,1597 @@
+//! Decompiles a function body into C-like pseudo-code, with nested
+//! expressions in place of stack operations and `if`/`while` statements in
+//! place of blocks and branches where the code allows it.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::HashMap;
+use std::mem;
+use wasm_bindgen::prelude::*;
+use wasmparser::{
+    BlockType, BrTable, FuncValidator, HeapType, Ieee32, Ieee64, MemArg, Operator, RefType,
+    TypeRef, ValType, ValidatorResources, V128,
+};
+
+use crate::module::{ItemKind, ItemRef, ParsedModule};
+use crate::names::op_name;
+use crate::refs::OperatorArg;
+use crate::stack_types::func_validator;
+use crate::types::BinaryError;
+
+/// A line of decompiled pseudo-code.
+#[wasmtools_struct]
+pub struct DecompiledLine {
+    /// The offset of the operator the line comes from. For a statement, this
+    /// is the operator that completes it, such as the `local.set` of an
+    /// assignment.
+    pub offset: usize,
+    /// How deeply the line is nested.
+    pub indent: u32,
+    pub text: String,
+}
+
+// Operator precedences, from loosest to tightest binding.
+const TERNARY: u8 = 1;
+const BIT_OR: u8 = 2;
+const BIT_XOR: u8 = 3;
+const BIT_AND: u8 = 4;
+const EQUALITY: u8 = 5;
+const RELATIONAL: u8 = 6;
+const SHIFT: u8 = 7;
+const ADDITIVE: u8 = 8;
+const MULTIPLICATIVE: u8 = 9;
+const UNARY: u8 = 10;
+const ATOM: u8 = 11;
+
+const NUMBER_TYPES: [&str; 4] = ["i32", "i64", "f32", "f64"];
+
+/// A value on the operand stack, as the expression that computes it.
+#[derive(Clone)]
+struct Expr {
+    text: String,
+    prec: u8,
+    ty: String,
+    /// The locals the expression reads, so that it can be kept from moving
+    /// past a write to one of them.
+    locals: Vec<u32>,
+    /// Whether the expression calls a function or reads state other than
+    /// locals, so that it can be kept from moving past other side effects.
+    effects: bool,
+    /// The operator that computes the value.
+    offset: usize,
+    /// The opposite condition, if it can be written more simply than by
+    /// putting `!` in front.
+    negation: Option<String>,
+}
+
+impl Expr {
+    fn var(name: String, ty: String, offset: usize) -> Expr {
+        Expr {
+            text: name,
+            prec: ATOM,
+            ty,
+            locals: Vec::new(),
+            effects: false,
+            offset,
+            negation: None,
+        }
+    }
+
+    /// Whether the expression can be evaluated at any point without changing
+    /// its value, as for constants and temporaries.
+    fn is_simple(&self) -> bool {
+        self.locals.is_empty() && !self.effects
+    }
+
+    /// The expression's text as an operand of an operator with the given
+    /// precedence.
+    fn operand(&self, prec: u8) -> String {
+        if self.prec < prec {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+fn join(values: &[Expr]) -> String {
+    let texts: Vec<&str> = values.iter().map(|v| v.text.as_str()).collect();
+    texts.join(", ")
+}
+
+/// The condition that is true when the given one is false.
+fn negate(cond: &Expr) -> String {
+    match &cond.negation {
+        Some(negation) => negation.clone(),
+        None => format!("!{}", cond.operand(UNARY)),
+    }
+}
+
+fn ref_type_name(ty: RefType) -> String {
+    match ty.heap_type() {
+        HeapType::Func => "funcref".to_string(),
+        HeapType::Extern => "externref".to_string(),
+        heap_type => format!("ref {}", format!("{:?}", heap_type).to_lowercase()),
+    }
+}
+
+fn type_name(ty: ValType) -> String {
+    match ty {
+        ValType::I32 => "i32".to_string(),
+        ValType::I64 => "i64".to_string(),
+        ValType::F32 => "f32".to_string(),
+        ValType::F64 => "f64".to_string(),
+        ValType::V128 => "v128".to_string(),
+        ValType::Ref(r) => ref_type_name(r),
+    }
+}
+
+/// Names items and locals for the pseudo-code, preferring names from the
+/// name section.
+struct Namer<'m, 'a> {
+    module: &'m ParsedModule<'a>,
+    locals: Vec<String>,
+}
+
+impl Namer<'_, '_> {
+    fn item(&self, item: ItemRef) -> String {
+        if item.kind == ItemKind::Local {
+            return self.local(item.index);
+        }
+        // Unnamed items are shown as e.g. `global3` rather than `global[3]`,
+        // which would read as an array access.
+        let name = self.module.display_name(item);
+        if name == format!("{}[{}]", item.kind.name(), item.index) {
+            format!("{}{}", item.kind.name(), item.index)
+        } else {
+            name
+        }
+    }
+
+    fn local(&self, index: u32) -> String {
+        match self.locals.get(index as usize) {
+            Some(name) => name.clone(),
+            None => format!("l{}", index),
+        }
+    }
+}
+
+/// An immediate argument of an operator that isn't given its own syntax.
+trait Immediate {
+    fn text(&self, name: &str, namer: &Namer) -> Option<String>;
+
+    fn memarg(&self) -> Option<MemArg> {
+        None
+    }
+}
+
+impl Immediate for u32 {
+    fn text(&self, name: &str, namer: &Namer) -> Option<String> {
+        match self.item_ref(name) {
+            // Most modules only have memory 0, so it goes without saying.
+            Some(item) if item.kind == ItemKind::Memory && item.index == 0 => None,
+            Some(item) => Some(namer.item(item)),
+            None => Some(self.to_string()),
+        }
+    }
+}
+
+impl Immediate for u8 {
+    fn text(&self, name: &str, _: &Namer) -> Option<String> {
+        // `mem_byte` and `table_byte` are reserved bytes, not arguments.
+        (!name.ends_with("_byte")).then(|| self.to_string())
+    }
+}
+
+impl Immediate for MemArg {
+    fn text(&self, _: &str, namer: &Namer) -> Option<String> {
+        (self.memory != 0).then(|| namer.item(ItemRef::new(ItemKind::Memory, self.memory)))
+    }
+
+    fn memarg(&self) -> Option<MemArg> {
+        Some(*self)
+    }
+}
+
+impl Immediate for Ieee32 {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        Some(format!("{:?}f", f32::from_bits(self.bits())))
+    }
+}
+
+impl Immediate for Ieee64 {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        Some(format!("{:?}", f64::from_bits(self.bits())))
+    }
+}
+
+impl Immediate for V128 {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        let hex: String = self
+            .bytes()
+            .iter()
+            .rev()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Some(format!("0x{}", hex))
+    }
+}
+
+impl Immediate for [u8; 16] {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        let lanes: Vec<String> = self.iter().map(|l| l.to_string()).collect();
+        Some(lanes.join(", "))
+    }
+}
+
+impl Immediate for HeapType {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        Some(format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Immediate for ValType {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        Some(type_name(*self))
+    }
+}
+
+impl Immediate for RefType {
+    fn text(&self, _: &str, _: &Namer) -> Option<String> {
+        Some(ref_type_name(*self))
+    }
+}
+
+macro_rules! plain_immediates {
+    ($($ty:ty)*) => ($(
+        impl Immediate for $ty {
+            fn text(&self, _: &str, _: &Namer) -> Option<String> {
+                Some(self.to_string())
+            }
+        }
+    )*);
+}
+plain_immediates!(i32 i64);
+
+macro_rules! control_immediates {
+    ($($ty:ty)*) => ($(
+        impl Immediate for $ty {
+            fn text(&self, _: &str, _: &Namer) -> Option<String> {
+                None
+            }
+        }
+    )*);
+}
+control_immediates!(BlockType BrTable<'_>);
+
+macro_rules! operator_info {
+    ($(@$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => (
+        /// The name of the visitor method for an operator, e.g.
+        /// `visit_i32_add`, along with its memory argument if it has one and
+        /// the text of its other immediates.
+        #[allow(unused_mut)]
+        fn operator_info(op: &Operator, namer: &Namer) -> (&'static str, Option<MemArg>, Vec<String>) {
+            match op {
+                $(
+                    Operator::$op $({ $($arg),* })? => {
+                        let mut memarg = None;
+                        let mut immediates = Vec::new();
+                        $($(
+                            memarg = memarg.or($arg.memarg());
+                            immediates.extend($arg.text(stringify!($arg), namer));
+                        )*)?
+                        (stringify!($visit), memarg, immediates)
+                    }
+                )*
+            }
+        }
+    );
+}
+wasmparser::for_each_operator!(operator_info);
+
+/// Whether an operator that isn't given its own syntax leaves no result, by
+/// its visitor name without the `visit_` prefix.
+fn is_void(name: &str) -> bool {
+    name == "nop"
+        || name.contains("store")
+        || name.ends_with("_set")
+        || [
+            "_fill",
+            "_copy",
+            "_init",
+            "data_drop",
+            "elem_drop",
+            "atomic_fence",
+        ]
+        .iter()
+        .any(|s| name.contains(s))
+}
+
+/// Whether an operator only computes a value from its operands.
+fn is_pure(name: &str) -> bool {
+    let numeric = [
+        "i32_", "i64_", "f32_", "f64_", "v128_", "i8x16_", "i16x8_", "i32x4_", "i64x2_", "f32x4_",
+        "f64x2_", "ref_",
+    ];
+    numeric.iter().any(|p| name.starts_with(p))
+        && !["load", "store", "atomic"].iter().any(|s| name.contains(s))
+}
+
+/// Shows a numeric operator with C operators and casts where they mean the
+/// same thing, or as a call to a function named after the operator. Integer
+/// comparisons also come with their negation.
+fn numeric(ty: &str, op: &str, args: &[Expr]) -> Option<(String, u8, Option<String>)> {
+    let unsigned = if ty == "i64" { "(u64)" } else { "(u32)" };
+    match args {
+        [a, b] => {
+            let (symbol, prec, is_unsigned) = match op {
+                "add" => ("+", ADDITIVE, false),
+                "sub" => ("-", ADDITIVE, false),
+                "mul" => ("*", MULTIPLICATIVE, false),
+                "div" | "div_s" => ("/", MULTIPLICATIVE, false),
+                "div_u" => ("/", MULTIPLICATIVE, true),
+                "rem_s" => ("%", MULTIPLICATIVE, false),
+                "rem_u" => ("%", MULTIPLICATIVE, true),
+                "and" => ("&", BIT_AND, false),
+                "or" => ("|", BIT_OR, false),
+                "xor" => ("^", BIT_XOR, false),
+                "shl" => ("<<", SHIFT, false),
+                "shr_s" => (">>", SHIFT, false),
+                "shr_u" => (">>", SHIFT, true),
+                "eq" => ("==", EQUALITY, false),
+                "ne" => ("!=", EQUALITY, false),
+                "lt" | "lt_s" => ("<", RELATIONAL, false),
+                "lt_u" => ("<", RELATIONAL, true),
+                "gt" | "gt_s" => (">", RELATIONAL, false),
+                "gt_u" => (">", RELATIONAL, true),
+                "le" | "le_s" => ("<=", RELATIONAL, false),
+                "le_u" => ("<=", RELATIONAL, true),
+                "ge" | "ge_s" => (">=", RELATIONAL, false),
+                "ge_u" => (">=", RELATIONAL, true),
+                "rotl" | "rotr" | "min" | "max" | "copysign" => {
+                    return Some((format!("{}({}, {})", op, a.text, b.text), ATOM, None));
+                }
+                _ => return None,
+            };
+            let left = if is_unsigned {
+                format!("{}{}", unsigned, a.operand(UNARY))
+            } else {
+                a.operand(prec)
+            };
+            // Only the left operand's signedness matters to a shift.
+            let right = if is_unsigned && op != "shr_u" {
+                format!("{}{}", unsigned, b.operand(UNARY))
+            } else {
+                b.operand(prec + 1)
+            };
+            // Float comparisons are all false for NaN, so only integer ones
+            // can be negated by flipping the operator.
+            let negation = match symbol {
+                "==" => Some("!="),
+                "!=" => Some("=="),
+                "<" => Some(">="),
+                ">=" => Some("<"),
+                ">" => Some("<="),
+                "<=" => Some(">"),
+                _ => None,
+            };
+            let negation = negation
+                .filter(|_| ty.starts_with('i'))
+                .map(|n| format!("{} {} {}", left, n, right));
+            Some((format!("{} {} {}", left, symbol, right), prec, negation))
+        }
+        [a] => {
+            let x = a.operand(UNARY);
+            let text = match op {
+                "eqz" => return Some((format!("!{}", x), UNARY, Some(a.text.clone()))),
+                "neg" if x.starts_with('-') => format!("-({})", a.text),
+                "neg" => format!("-{}", x),
+                "extend8_s" => format!("({})(i8){}", ty, x),
+                "extend16_s" => format!("({})(i16){}", ty, x),
+                "extend32_s" => format!("({})(i32){}", ty, x),
+                "wrap_i64" | "extend_i32_s" | "demote_f64" | "promote_f32" => {
+                    format!("({}){}", ty, x)
+                }
+                "extend_i32_u" => format!("({})(u32){}", ty, x),
+                "trunc_f32_s" | "trunc_f64_s" => format!("({}){}", ty, x),
+                "trunc_f32_u" | "trunc_f64_u" => format!("{}{}", unsigned, x),
+                "convert_i32_s" | "convert_i64_s" => format!("({}){}", ty, x),
+                "convert_i32_u" => format!("({})(u32){}", ty, x),
+                "convert_i64_u" => format!("({})(u64){}", ty, x),
+                "clz" | "ctz" | "popcnt" | "abs" | "ceil" | "floor" | "trunc" | "nearest"
+                | "sqrt" => return Some((format!("{}({})", op, a.text), ATOM, None)),
+                _ => return None,
+            };
+            Some((text, UNARY, None))
+        }
+        _ => None,
+    }
+}
+
+/// A memory access such as `mem32[p + 8]`. Loads of fewer bits than their
+/// type say whether they are sign or zero extended, as in `mem8u`.
+fn memory_access(namer: &Namer, ty: &str, op: &str, memarg: MemArg, addr: &Expr) -> String {
+    let bits = op.trim_start_matches("load").trim_start_matches("store");
+    let width = match bits {
+        "" if ty.starts_with('f') => ty.to_string(),
+        "" => ty[1..].to_string(),
+        bits => bits.replace('_', ""),
+    };
+    let memory = match memarg.memory {
+        0 => String::new(),
+        m => format!("{}.", namer.item(ItemRef::new(ItemKind::Memory, m))),
+    };
+    format!("{}mem{}[{}]", memory, width, address(addr, memarg.offset))
+}
+
+fn address(addr: &Expr, offset: u64) -> String {
+    if offset == 0 {
+        return addr.text.clone();
+    }
+    match addr.text.parse::<u64>() {
+        Ok(base) => (base + offset).to_string(),
+        Err(_) => format!("{} + {}", addr.operand(ADDITIVE), offset),
+    }
+}
+
+/// A statement of pseudo-code.
+enum Stmt {
+    Line {
+        offset: usize,
+        text: String,
+    },
+    /// A `break` out of a block or a `continue` of a loop. Its label is only
+    /// known once the target's other jumps have been simplified away or not.
+    Jump {
+        offset: usize,
+        /// Text before the jump, such as `case 2: `.
+        prefix: String,
+        cond: Option<Expr>,
+        target: usize,
+        keyword: &'static str,
+    },
+    /// A statement with nested statements, such as `if` or `while`. It is
+    /// labelled with the block it comes from if anything jumps to it.
+    Nested {
+        label: Option<usize>,
+        arms: Vec<Arm>,
+        end: usize,
+        /// Text after the closing brace, as in `} while (x);`.
+        tail: String,
+    },
+}
+
+/// One of the bracketed parts of a nested statement, such as the `else`
+/// part of an `if`.
+struct Arm {
+    offset: usize,
+    head: String,
+    body: Vec<Stmt>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Try,
+}
+
+/// A block, loop, if or try being decompiled, or the function itself.
+struct Frame {
+    /// The number of the block in the order blocks start in the body, or
+    /// `None` for the function.
+    id: Option<usize>,
+    kind: FrameKind,
+    /// The height of the operand stack below the block's parameters.
+    height: usize,
+    /// The block's parameters, for `else` to start from again.
+    params: Vec<Expr>,
+    results: Vec<String>,
+    /// The variables that a jump to the block assigns its values to.
+    branch_vars: Vec<String>,
+    /// The variables that the end of the block assigns its results to.
+    end_vars: Vec<String>,
+    cond: Option<Expr>,
+    arms: Vec<Arm>,
+    head: String,
+    head_offset: usize,
+    body: Vec<Stmt>,
+    /// Whether the code being decompiled is unreachable.
+    dead: bool,
+    /// Whether the end of any part of the block can be reached.
+    reached_end: bool,
+}
+
+impl Frame {
+    fn new(id: Option<usize>, kind: FrameKind, height: usize, offset: usize) -> Frame {
+        Frame {
+            id,
+            kind,
+            height,
+            params: Vec::new(),
+            results: Vec::new(),
+            branch_vars: Vec::new(),
+            end_vars: Vec::new(),
+            cond: None,
+            arms: Vec::new(),
+            head: String::new(),
+            head_offset: offset,
+            body: Vec::new(),
+            dead: false,
+            reached_end: false,
+        }
+    }
+
+    fn close_arm(&mut self) {
+        self.arms.push(Arm {
+            offset: self.head_offset,
+            head: mem::take(&mut self.head),
+            body: mem::take(&mut self.body),
+        });
+    }
+}
+
+/// Finds the blocks, loops and ifs that some branch targets, by their
+/// number in the order they start.
+fn branch_targets(ops: &[(Operator, usize)]) -> Vec<bool> {
+    fn target(frames: &[Option<usize>], targeted: &mut [bool], depth: u32) {
+        let index = frames.len().checked_sub(1 + depth as usize);
+        if let Some(Some(id)) = index.map(|i| frames[i]) {
+            targeted[id] = true;
+        }
+    }
+
+    let mut targeted = Vec::new();
+    let mut frames: Vec<Option<usize>> = vec![None];
+    for (op, _) in ops {
+        match op {
+            Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::Try { .. } => {
+                frames.push(Some(targeted.len()));
+                targeted.push(false);
+            }
+            Operator::End | Operator::Delegate { .. } => {
+                frames.pop();
+            }
+            Operator::Br { relative_depth }
+            | Operator::BrIf { relative_depth }
+            | Operator::BrOnNull { relative_depth }
+            | Operator::BrOnNonNull { relative_depth } => {
+                target(&frames, &mut targeted, *relative_depth)
+            }
+            Operator::BrTable { targets } => {
+                for depth in targets.targets().chain(Some(Ok(targets.default()))) {
+                    target(&frames, &mut targeted, depth.unwrap_or(0));
+                }
+            }
+            _ => {}
+        }
+    }
+    targeted
+}
+
+struct Decompiler<'m, 'a> {
+    module: &'m ParsedModule<'a>,
+    namer: Namer<'m, 'a>,
+    func: FuncValidator<ValidatorResources>,
+    stack: Vec<Expr>,
+    frames: Vec<Frame>,
+    /// Whether each block is the target of a branch.
+    targeted: Vec<bool>,
+    /// The number of jumps to each block that are left after simplifying.
+    jumps: Vec<u32>,
+    next_block: usize,
+    next_temp: u32,
+    /// How many blocks deep into unreachable code the decompiler is.
+    skipped: u32,
+}
+
+impl<'m, 'a> Decompiler<'m, 'a> {
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, stmt: Stmt) {
+        self.frame().body.push(stmt);
+    }
+
+    fn line(&mut self, offset: usize, text: String) {
+        self.emit(Stmt::Line { offset, text });
+    }
+
+    fn temp(&mut self) -> String {
+        self.next_temp += 1;
+        format!("t{}", self.next_temp - 1)
+    }
+
+    fn pop_n(&mut self, n: usize) -> Vec<Expr> {
+        let at = self.stack.len().saturating_sub(n);
+        self.stack.split_off(at)
+    }
+
+    fn pop(&mut self) -> Expr {
+        self.pop_n(1)
+            .pop()
+            .unwrap_or_else(|| Expr::var("?".to_string(), "?".to_string(), 0))
+    }
+
+    /// The types of the values an operator pushed, bottom first.
+    fn pushed_types(&self, n: usize) -> Vec<String> {
+        (0..n)
+            .rev()
+            .map(|depth| match self.func.get_operand_type(depth) {
+                Some(Some(ty)) => type_name(ty),
+                _ => "?".to_string(),
+            })
+            .collect()
+    }
+
+    fn push(&mut self, text: String, prec: u8, args: &[Expr], effects: bool, offset: usize) {
+        let mut locals: Vec<u32> = args.iter().flat_map(|a| a.locals.iter().copied()).collect();
+        locals.sort_unstable();
+        locals.dedup();
+        self.stack.push(Expr {
+            text,
+            prec,
+            ty: self.pushed_types(1).remove(0),
+            locals,
+            effects: effects || args.iter().any(|a| a.effects),
+            offset,
+            negation: None,
+        });
+    }
+
+    /// Assigns the values on the stack that match `pred` to temporaries, so
+    /// that they are computed before the statement that is about to be
+    /// emitted.
+    fn spill(&mut self, pred: impl Fn(usize, &Expr) -> bool) {
+        for i in 0..self.stack.len() {
+            let value = &self.stack[i];
+            if value.is_simple() || !pred(i, value) {
+                continue;
+            }
+            let value = value.clone();
+            let name = self.temp();
+            self.line(
+                value.offset,
+                format!("{} {} = {};", value.ty, name, value.text),
+            );
+            self.stack[i] = Expr::var(name, value.ty, value.offset);
+        }
+    }
+
+    fn spill_all(&mut self) {
+        self.spill(|_, _| true);
+    }
+
+    /// Emits a statement, first spilling the values it could change or be
+    /// reordered with.
+    fn statement(&mut self, offset: usize, text: String, effects: bool, writes: Option<u32>) {
+        self.spill(|_, v| {
+            (effects && v.effects) || writes.map_or(false, |l| v.locals.contains(&l))
+        });
+        self.line(offset, text);
+    }
+
+    /// Pushes the results of a call-like expression, emitting it as a
+    /// statement if it has no results or more than one.
+    fn results(&mut self, offset: usize, text: String, args: &[Expr], n: usize) {
+        match n {
+            0 => self.statement(offset, format!("{};", text), true, None),
+            1 => self.push(text, ATOM, args, true, offset),
+            _ => {
+                let types = self.pushed_types(n);
+                let names: Vec<String> = types.iter().map(|_| self.temp()).collect();
+                let decls: Vec<String> = types
+                    .iter()
+                    .zip(names.iter())
+                    .map(|(ty, name)| format!("{} {}", ty, name))
+                    .collect();
+                self.statement(
+                    offset,
+                    format!("{} = {};", decls.join(", "), text),
+                    true,
+                    None,
+                );
+                for (name, ty) in names.into_iter().zip(types) {
+                    self.stack.push(Expr::var(name, ty, offset));
+                }
+            }
+        }
+    }
+
+    /// Declares variables for the results of a block.
+    fn declare(&mut self, offset: usize, types: &[String]) -> Vec<String> {
+        let mut names = Vec::new();
+        for ty in types {
+            let name = self.temp();
+            self.line(offset, format!("{} {};", ty, name));
+            names.push(name);
+        }
+        names
+    }
+
+    fn block_type(&self, ty: BlockType) -> (usize, Vec<String>) {
+        match ty {
+            BlockType::Empty => (0, Vec::new()),
+            BlockType::Type(ty) => (0, vec![type_name(ty)]),
+            BlockType::FuncType(index) => match self.module.types.get(index as usize) {
+                Some(ty) => (
+                    ty.value.params().len(),
+                    ty.value.results().iter().map(|&t| type_name(t)).collect(),
+                ),
+                None => (0, Vec::new()),
+            },
+        }
+    }
+
+    fn tag_params(&self, tag_index: u32) -> usize {
+        let module = self.module;
+        let ty = match tag_index.checked_sub(module.num_imported_tags()) {
+            Some(defined) => module
+                .tags
+                .get(defined as usize)
+                .map(|t| t.value.func_type_idx),
+            None => module
+                .imports
+                .iter()
+                .filter_map(|i| match i.value.ty {
+                    TypeRef::Tag(t) => Some(t.func_type_idx),
+                    _ => None,
+                })
+                .nth(tag_index as usize),
+        };
+        ty.and_then(|t| module.types.get(t as usize))
+            .map_or(0, |t| t.value.params().len())
+    }
+
+    fn start_block(&mut self, kind: FrameKind, ty: BlockType, offset: usize, cond: Option<Expr>) {
+        let id = self.next_block;
+        self.next_block += 1;
+        let (num_params, results) = self.block_type(ty);
+        // A block or loop that nothing jumps to is the same as the code in
+        // it, so it is decompiled in place.
+        let kind = match kind {
+            FrameKind::Loop if !self.targeted[id] => FrameKind::Block,
+            kind => kind,
+        };
+        let inline = kind == FrameKind::Block && !self.targeted[id];
+        let height = self.stack.len() - num_params.min(self.stack.len());
+        match kind {
+            // The loop's parameters are assigned to variables below.
+            FrameKind::Loop => self.spill(|i, _| i < height),
+            _ if !inline => self.spill_all(),
+            _ => {}
+        }
+        let mut frame = Frame::new(Some(id), kind, height, offset);
+        match kind {
+            FrameKind::Loop => {
+                // The parameters become variables that `continue` assigns to.
+                for i in height..self.stack.len() {
+                    let value = self.stack[i].clone();
+                    let name = self.temp();
+                    self.line(offset, format!("{} {} = {};", value.ty, name, value.text));
+                    self.stack[i] = Expr::var(name.clone(), value.ty, offset);
+                    frame.branch_vars.push(name);
+                }
+                frame.end_vars = self.declare(offset, &results);
+                frame.head = "while (true)".to_string();
+            }
+            FrameKind::Block if inline => {}
+            _ => {
+                frame.end_vars = self.declare(offset, &results);
+                frame.branch_vars = frame.end_vars.clone();
+                frame.head = match (kind, &cond) {
+                    (FrameKind::If, Some(cond)) => format!("if ({})", cond.text),
+                    (FrameKind::Try, _) => "try".to_string(),
+                    _ => String::new(),
+                };
+            }
+        }
+        frame.params = self.stack[height..].to_vec();
+        frame.results = results;
+        frame.cond = cond;
+        self.frames.push(frame);
+    }
+
+    /// Finishes the current part of a block, assigning its results if its
+    /// end can be reached.
+    fn end_arm(&mut self) {
+        let frame = self.frames.last().unwrap();
+        let height = frame.height;
+        if frame.dead {
+            self.stack.truncate(height);
+            return;
+        }
+        let vars = frame.end_vars.clone();
+        if !vars.is_empty() {
+            let values = self.pop_n(vars.len());
+            for (var, value) in vars.iter().zip(values) {
+                self.line(value.offset, format!("{} = {};", var, value.text));
+            }
+        }
+        if self.frame().kind != FrameKind::Block {
+            self.stack.truncate(height);
+        }
+        self.frame().reached_end = true;
+    }
+
+    fn next_arm(&mut self, offset: usize, head: String, restore_params: bool) {
+        self.end_arm();
+        let frame = self.frame();
+        frame.close_arm();
+        frame.head = head;
+        frame.head_offset = offset;
+        frame.dead = false;
+        let (height, params) = (frame.height, frame.params.clone());
+        self.stack.truncate(height);
+        if restore_params {
+            self.stack.extend(params);
+        }
+    }
+
+    /// Turns `L: { while (true) { if (x) break L; ... } }` into
+    /// `while (!x) { ... }`.
+    fn simplify_while(&mut self, id: usize, body: &mut [Stmt]) {
+        let arm = match body.last_mut() {
+            Some(Stmt::Nested { arms, tail, .. }) if arms.len() == 1 && tail.is_empty() => {
+                &mut arms[0]
+            }
+            _ => return,
+        };
+        if arm.head != "while (true)" {
+            return;
+        }
+        let head = match arm.body.first() {
+            Some(Stmt::Jump {
+                target,
+                cond: Some(cond),
+                prefix,
+                ..
+            }) if *target == id && prefix.is_empty() => format!("while ({})", negate(cond)),
+            _ => return,
+        };
+        arm.body.remove(0);
+        arm.head = head;
+        self.jumps[id] -= 1;
+    }
+
+    /// Turns `L: { if (x) break L; ... }` into `if (!x) { ... }`.
+    fn simplify_guard(&mut self, id: usize, body: &mut Vec<Stmt>, end: usize) {
+        if self.jumps[id] != 1 {
+            return;
+        }
+        let (head, offset) = match body.first() {
+            Some(Stmt::Jump {
+                target,
+                cond: Some(cond),
+                prefix,
+                offset,
+                ..
+            }) if *target == id && prefix.is_empty() => (format!("if ({})", negate(cond)), *offset),
+            _ => return,
+        };
+        let rest = body.split_off(1);
+        *body = vec![Stmt::Nested {
+            label: None,
+            arms: vec![Arm {
+                offset,
+                head,
+                body: rest,
+            }],
+            end,
+            tail: String::new(),
+        }];
+        self.jumps[id] -= 1;
+    }
+
+    fn end_block(&mut self, offset: usize, tail: &str) {
+        self.end_arm();
+        let mut frame = self.frames.pop().unwrap();
+        let id = frame.id.unwrap();
+        // An `if` without an `else` passes its parameters through as its
+        // results when the condition is false.
+        let implicit_else = frame.kind == FrameKind::If && frame.arms.is_empty();
+        if implicit_else && !frame.end_vars.is_empty() {
+            frame.close_arm();
+            frame.head = "else".to_string();
+            for (var, value) in frame.end_vars.iter().zip(frame.params.iter()) {
+                frame.body.push(Stmt::Line {
+                    offset,
+                    text: format!("{} = {};", var, value.text),
+                });
+            }
+        }
+        frame.close_arm();
+        let reached = match frame.kind {
+            FrameKind::Loop => frame.reached_end,
+            _ => frame.reached_end || implicit_else || self.targeted[id],
+        };
+
+        let mut arms = frame.arms;
+        let mut tail = tail.to_string();
+        match frame.kind {
+            FrameKind::Block => {
+                let mut body = mem::take(&mut arms[0].body);
+                if self.jumps[id] > 0 && frame.end_vars.is_empty() {
+                    self.simplify_while(id, &mut body);
+                    self.simplify_guard(id, &mut body, offset);
+                }
+                if self.jumps[id] == 0 {
+                    self.frame().body.extend(body);
+                } else {
+                    arms[0].body = body;
+                    self.emit(Stmt::Nested {
+                        label: Some(id),
+                        arms,
+                        end: offset,
+                        tail,
+                    });
+                }
+            }
+            FrameKind::Loop => {
+                let last_jump = match arms[0].body.last() {
+                    Some(Stmt::Jump {
+                        target,
+                        cond,
+                        prefix,
+                        ..
+                    }) if *target == id && prefix.is_empty() => Some(cond.clone()),
+                    _ => None,
+                };
+                match last_jump {
+                    Some(cond) => {
+                        if let Some(cond) = cond {
+                            arms[0].head = "do".to_string();
+                            tail = format!(" while ({});", cond.text);
+                        }
+                        arms[0].body.pop();
+                        self.jumps[id] -= 1;
+                    }
+                    // Falling off the end of a loop leaves it.
+                    None if frame.reached_end => arms[0].body.push(Stmt::Line {
+                        offset,
+                        text: "break;".to_string(),
+                    }),
+                    None => {}
+                }
+                self.emit(Stmt::Nested {
+                    label: Some(id),
+                    arms,
+                    end: offset,
+                    tail,
+                });
+            }
+            FrameKind::If | FrameKind::Try => {
+                if frame.kind == FrameKind::If && arms.len() == 2 {
+                    if arms[0].body.is_empty() {
+                        let cond = frame.cond.as_ref().unwrap();
+                        arms[1].head = format!("if ({})", negate(cond));
+                        arms[1].offset = arms[0].offset;
+                        arms.remove(0);
+                    } else if arms[1].body.is_empty() {
+                        arms.pop();
+                    }
+                }
+                self.emit(Stmt::Nested {
+                    label: Some(id),
+                    arms,
+                    end: offset,
+                    tail,
+                });
+            }
+            FrameKind::Function => unreachable!(),
+        }
+
+        if !reached {
+            self.frame().dead = true;
+        } else if !frame.end_vars.is_empty() {
+            for (name, ty) in frame.end_vars.into_iter().zip(frame.results) {
+                self.stack.push(Expr::var(name, ty, offset));
+            }
+        }
+    }
+
+    /// Emits a jump to the block at the given depth, assigning the values it
+    /// passes. A jump out of the function is a `return`.
+    /// Emits `br_on_null` as a branch taken if the reference is null, leaving
+    /// it on the stack otherwise, or `br_on_non_null` as a branch taken if it
+    /// isn't, passing it to the target.
+    fn br_on_null(&mut self, offset: usize, depth: u32, on_null: bool) {
+        // The reference is both tested and passed on, so it and the values
+        // the branch passes must only be computed once.
+        let index = self.frames.len() - 1 - depth as usize;
+        let arity = match self.frames[index].kind {
+            FrameKind::Function => self.frames[index].results.len(),
+            _ => self.frames[index].branch_vars.len(),
+        };
+        let passed = match on_null {
+            true => self.stack.len().saturating_sub(arity + 1),
+            false => self.stack.len().saturating_sub(arity.max(1)),
+        };
+        self.spill(|i, v| v.effects || i >= passed);
+        let value = self.pop();
+        if !on_null {
+            self.stack.push(value.clone());
+        }
+        let is_null = format!("{} == null", value.text);
+        let not_null = format!("{} != null", value.text);
+        let (text, negation) = match on_null {
+            true => (is_null, not_null),
+            false => (not_null, is_null),
+        };
+        let cond = Expr {
+            text,
+            prec: EQUALITY,
+            ty: "i32".to_string(),
+            locals: value.locals.clone(),
+            effects: false,
+            offset,
+            negation: Some(negation),
+        };
+        self.branch(offset, depth, Some(cond), String::new());
+        match on_null {
+            true => {
+                let ty = self.pushed_types(1).remove(0);
+                self.stack.push(Expr { ty, ..value });
+            }
+            false => {
+                self.pop();
+            }
+        }
+    }
+
+    fn branch(&mut self, offset: usize, depth: u32, cond: Option<Expr>, prefix: String) {
+        let index = self.frames.len() - 1 - depth as usize;
+        let target = &self.frames[index];
+        let (kind, id) = (target.kind, target.id);
+        let vars = match kind {
+            FrameKind::Function => Vec::new(),
+            _ => target.branch_vars.clone(),
+        };
+        let arity = match kind {
+            FrameKind::Function => target.results.len(),
+            _ => vars.len(),
+        };
+        let values = self.stack[self.stack.len() - arity.min(self.stack.len())..].to_vec();
+        let cond_text = cond.as_ref().map(|c| format!("if ({}) ", c.text));
+        let id = match id {
+            Some(id) => id,
+            None => {
+                let text = match values.len() {
+                    0 => "return;".to_string(),
+                    _ => format!("return {};", join(&values)),
+                };
+                let text = format!("{}{}{}", prefix, cond_text.unwrap_or_default(), text);
+                self.line(offset, text);
+                return;
+            }
+        };
+        let keyword = if kind == FrameKind::Loop {
+            "continue"
+        } else {
+            "break"
+        };
+        self.jumps[id] += 1;
+        let assigns: Vec<String> = vars
+            .iter()
+            .zip(values.iter())
+            .map(|(var, value)| format!("{} = {}; ", var, value.text))
+            .collect();
+        match cond {
+            Some(cond) if !assigns.is_empty() => {
+                let mut body: Vec<Stmt> = assigns
+                    .into_iter()
+                    .map(|a| Stmt::Line {
+                        offset,
+                        text: a.trim_end().to_string(),
+                    })
+                    .collect();
+                body.push(Stmt::Jump {
+                    offset,
+                    prefix: String::new(),
+                    cond: None,
+                    target: id,
+                    keyword,
+                });
+                self.emit(Stmt::Nested {
+                    label: None,
+                    arms: vec![Arm {
+                        offset,
+                        head: format!("if ({})", cond.text),
+                        body,
+                    }],
+                    end: offset,
+                    tail: String::new(),
+                });
+            }
+            cond => self.emit(Stmt::Jump {
+                offset,
+                prefix: format!("{}{}", prefix, assigns.concat()),
+                cond,
+                target: id,
+                keyword,
+            }),
+        }
+    }
+
+    fn operator(&mut self, op: &Operator, offset: usize, before: usize) {
+        if self.frame().dead {
+            match op {
+                Operator::Block { .. }
+                | Operator::Loop { .. }
+                | Operator::If { .. }
+                | Operator::Try { .. } => {
+                    self.skipped += 1;
+                    self.next_block += 1;
+                    return;
+                }
+                Operator::End | Operator::Delegate { .. } if self.skipped > 0 => {
+                    self.skipped -= 1;
+                    return;
+                }
+                Operator::Else
+                | Operator::Catch { .. }
+                | Operator::CatchAll
+                | Operator::End
+                | Operator::Delegate { .. }
+                    if self.skipped == 0 => {}
+                _ => return,
+            }
+        }
+
+        let namer = &self.namer;
+        match *op {
+            Operator::Nop => {}
+            Operator::Unreachable => {
+                self.statement(offset, "unreachable;".to_string(), true, None);
+                self.frame().dead = true;
+            }
+            Operator::Block { blockty } => {
+                self.start_block(FrameKind::Block, blockty, offset, None)
+            }
+            Operator::Loop { blockty } => self.start_block(FrameKind::Loop, blockty, offset, None),
+            Operator::Try { blockty } => self.start_block(FrameKind::Try, blockty, offset, None),
+            Operator::If { blockty } => {
+                let cond = self.pop();
+                self.start_block(FrameKind::If, blockty, offset, Some(cond));
+            }
+            Operator::Else => self.next_arm(offset, "else".to_string(), true),
+            Operator::Catch { tag_index } => {
+                let tag = namer.item(ItemRef::new(ItemKind::Tag, tag_index));
+                self.next_arm(offset, String::new(), false);
+                let types = self.pushed_types(self.tag_params(tag_index));
+                let mut params = Vec::new();
+                for ty in types {
+                    let name = self.temp();
+                    params.push(format!("{} {}", ty, name));
+                    self.stack.push(Expr::var(name, ty, offset));
+                }
+                self.frame().head = format!("catch {}({})", tag, params.join(", "));
+            }
+            Operator::CatchAll => self.next_arm(offset, "catch".to_string(), false),
+            Operator::End if self.frames.len() == 1 => {
+                let (dead, n) = (self.frames[0].dead, self.frames[0].results.len());
+                if !dead && n > 0 {
+                    let values = self.pop_n(n);
+                    self.line(offset, format!("return {};", join(&values)));
+                }
+            }
+            Operator::End => self.end_block(offset, ""),
+            Operator::Delegate { .. } => self.end_block(offset, " delegate"),
+            Operator::Br { relative_depth } => {
+                self.spill(|_, v| v.effects);
+                self.branch(offset, relative_depth, None, String::new());
+                self.frame().dead = true;
+            }
+            Operator::BrIf { relative_depth } => {
+                let cond = self.pop();
+                // The values the branch passes also stay on the stack, so
+                // they must only be computed once.
+                let index = self.frames.len() - 1 - relative_depth as usize;
+                let arity = match self.frames[index].kind {
+                    FrameKind::Function => self.frames[index].results.len(),
+                    _ => self.frames[index].branch_vars.len(),
+                };
+                let passed = self.stack.len() - arity.min(self.stack.len());
+                self.spill(|i, v| v.effects || i >= passed);
+                self.branch(offset, relative_depth, Some(cond), String::new());
+            }
+            Operator::BrOnNull { relative_depth } => self.br_on_null(offset, relative_depth, true),
+            Operator::BrOnNonNull { relative_depth } => {
+                self.br_on_null(offset, relative_depth, false)
+            }
+            Operator::BrTable { ref targets } => {
+                let index = self.pop();
+                self.spill(|_, v| v.effects);
+                let mut cases = Vec::new();
+                let depths = targets.targets().chain(Some(Ok(targets.default())));
+                for (i, depth) in depths.enumerate() {
+                    let prefix = match i as u32 == targets.len() {
+                        true => "default: ".to_string(),
+                        false => format!("case {}: ", i),
+                    };
+                    self.branch(offset, depth.unwrap_or(0), None, prefix);
+                    cases.push(self.frame().body.pop().unwrap());
+                }
+                self.emit(Stmt::Nested {
+                    label: None,
+                    arms: vec![Arm {
+                        offset,
+                        head: format!("switch ({})", index.text),
+                        body: cases,
+                    }],
+                    end: offset,
+                    tail: String::new(),
+                });
+                self.frame().dead = true;
+            }
+            Operator::Return => {
+                self.spill(|_, v| v.effects);
+                self.branch(offset, self.frames.len() as u32 - 1, None, String::new());
+                self.frame().dead = true;
+            }
+            Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+                let name = namer.item(ItemRef::new(ItemKind::Func, function_index));
+                let (params, results) = match self.module.func_type(function_index) {
+                    Some(ty) => (ty.params().len(), ty.results().len()),
+                    None => (0, 0),
+                };
+                let args = self.pop_n(params);
+                let text = format!("{}({})", name, join(&args));
+                if let Operator::ReturnCall { .. } = op {
+                    self.statement(offset, format!("return {};", text), true, None);
+                    self.frame().dead = true;
+                } else {
+                    self.results(offset, text, &args, results);
+                }
+            }
+            Operator::CallIndirect {
+                type_index,
+                table_index,
+                ..
+            }
+            | Operator::ReturnCallIndirect {
+                type_index,
+                table_index,
+                ..
+            } => {
+                let table = namer.item(ItemRef::new(ItemKind::Table, table_index));
+                let (params, results) = match self.module.types.get(type_index as usize) {
+                    Some(ty) => (ty.value.params().len(), ty.value.results().len()),
+                    None => (0, 0),
+                };
+                let index = self.pop();
+                let args = self.pop_n(params);
+                let text = format!("{}[{}]({})", table, index.text, join(&args));
+                if let Operator::ReturnCallIndirect { .. } = op {
+                    self.statement(offset, format!("return {};", text), true, None);
+                    self.frame().dead = true;
+                } else {
+                    let mut args = args;
+                    args.push(index);
+                    self.results(offset, text, &args, results);
+                }
+            }
+            Operator::Throw { tag_index } => {
+                let tag = namer.item(ItemRef::new(ItemKind::Tag, tag_index));
+                let args = self.pop_n(self.tag_params(tag_index));
+                let text = format!("throw {}({});", tag, join(&args));
+                self.statement(offset, text, true, None);
+                self.frame().dead = true;
+            }
+            Operator::Rethrow { .. } => {
+                self.statement(offset, "rethrow;".to_string(), true, None);
+                self.frame().dead = true;
+            }
+            Operator::Drop => {
+                let value = self.pop();
+                if value.effects {
+                    self.statement(offset, format!("{};", value.text), true, None);
+                }
+            }
+            Operator::Select | Operator::TypedSelect { .. } => {
+                let args = self.pop_n(3);
+                let text = format!(
+                    "{} ? {} : {}",
+                    args[2].operand(TERNARY + 1),
+                    args[0].operand(TERNARY + 1),
+                    args[1].operand(TERNARY)
+                );
+                self.push(text, TERNARY, &args, false, offset);
+            }
+            Operator::LocalGet { local_index } => {
+                let mut value = Expr::var(namer.local(local_index), String::new(), offset);
+                value.ty = self.pushed_types(1).remove(0);
+                value.locals.push(local_index);
+                self.stack.push(value);
+            }
+            Operator::LocalSet { local_index } | Operator::LocalTee { local_index } => {
+                let name = namer.local(local_index);
+                let value = self.pop();
+                let text = format!("{} = {};", name, value.text);
+                self.statement(offset, text, value.effects, Some(local_index));
+                if let Operator::LocalTee { .. } = op {
+                    let mut value = Expr::var(name, value.ty, offset);
+                    value.locals.push(local_index);
+                    self.stack.push(value);
+                }
+            }
+            Operator::GlobalGet { global_index } => {
+                let name = namer.item(ItemRef::new(ItemKind::Global, global_index));
+                self.push(name, ATOM, &[], true, offset);
+            }
+            Operator::GlobalSet { global_index } => {
+                let name = namer.item(ItemRef::new(ItemKind::Global, global_index));
+                let value = self.pop();
+                self.statement(offset, format!("{} = {};", name, value.text), true, None);
+            }
+            Operator::I32Const { value } => {
+                self.push(value.to_string(), const_prec(value), &[], false, offset)
+            }
+            Operator::I64Const { value } => {
+                self.push(format!("{}L", value), const_prec(value), &[], false, offset)
+            }
+            Operator::F32Const { value } => {
+                let value = f32::from_bits(value.bits());
+                let prec = if value.is_sign_negative() {
+                    UNARY
+                } else {
+                    ATOM
+                };
+                self.push(format!("{:?}f", value), prec, &[], false, offset);
+            }
+            Operator::F64Const { value } => {
+                let value = f64::from_bits(value.bits());
+                let prec = if value.is_sign_negative() {
+                    UNARY
+                } else {
+                    ATOM
+                };
+                self.push(format!("{:?}", value), prec, &[], false, offset);
+            }
+            _ => self.generic(op, offset, before),
+        }
+    }
+
+    /// Decompiles an operator that has no syntax of its own, working out how
+    /// many operands it takes from the validator.
+    fn generic(&mut self, op: &Operator, offset: usize, before: usize) {
+        let (visit, memarg, immediates) = operator_info(op, &self.namer);
+        let name = visit.trim_start_matches("visit_");
+        let after = self.func.operand_stack_height() as usize;
+        let pushes = if is_void(name) { 0 } else { 1 };
+        let pops = (before + pushes).saturating_sub(after);
+        let pushes = after + pops - before;
+        let mut args = self.pop_n(pops);
+        let effects = !is_pure(name);
+
+        let (ty, short) = name.split_once('_').unwrap_or((name, ""));
+        if NUMBER_TYPES.contains(&ty) {
+            match (memarg, short, args.as_slice()) {
+                (Some(memarg), short, [addr]) if short.starts_with("load") => {
+                    let text = memory_access(&self.namer, ty, short, memarg, addr);
+                    self.push(text, ATOM, &args, true, offset);
+                    return;
+                }
+                (Some(memarg), short, [addr, value]) if short.starts_with("store") => {
+                    let access = memory_access(&self.namer, ty, short, memarg, addr);
+                    let text = format!("{} = {};", access, value.text);
+                    self.statement(offset, text, true, None);
+                    return;
+                }
+                (None, short, args) if pushes == 1 => {
+                    if let Some((text, prec, negation)) = numeric(ty, short, args) {
+                        let args = args.to_vec();
+                        self.push(text, prec, &args, effects, offset);
+                        self.stack.last_mut().unwrap().negation = negation;
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(memarg), Some(addr)) = (memarg, args.first_mut()) {
+            addr.text = address(addr, memarg.offset);
+            addr.prec = ADDITIVE;
+        }
+        let mut text = op_name(op);
+        if !immediates.is_empty() {
+            text = format!("{}<{}>", text, immediates.join(", "));
+        }
+        let text = format!("{}({})", text, join(&args));
+        match pushes {
+            1 if !effects => self.push(text, ATOM, &args, false, offset),
+            n => self.results(offset, text, &args, n),
+        }
+    }
+
+    fn render(
+        &self,
+        stmts: &[Stmt],
+        indent: u32,
+        labels: &mut HashMap<usize, String>,
+        out: &mut Vec<DecompiledLine>,
+    ) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Line { offset, text } => out.push(DecompiledLine {
+                    offset: *offset,
+                    indent,
+                    text: text.clone(),
+                }),
+                Stmt::Jump {
+                    offset,
+                    prefix,
+                    cond,
+                    target,
+                    keyword,
+                } => {
+                    let cond = match cond {
+                        Some(cond) => format!("if ({}) ", cond.text),
+                        None => String::new(),
+                    };
+                    out.push(DecompiledLine {
+                        offset: *offset,
+                        indent,
+                        text: format!("{}{}{} {};", prefix, cond, keyword, labels[target]),
+                    });
+                }
+                Stmt::Nested {
+                    label,
+                    arms,
+                    end,
+                    tail,
+                } => {
+                    let mut label_text = String::new();
+                    if let Some(id) = *label {
+                        if self.jumps[id] > 0 {
+                            let name = format!("L{}", labels.len() + 1);
+                            label_text = format!("{}: ", name);
+                            labels.insert(id, name);
+                        }
+                    }
+                    for (i, arm) in arms.iter().enumerate() {
+                        let text = match (i, arm.head.as_str()) {
+                            (0, "") => format!("{}{{", label_text),
+                            (0, head) => format!("{}{} {{", label_text, head),
+                            (_, head) => format!("}} {} {{", head),
+                        };
+                        out.push(DecompiledLine {
+                            offset: arm.offset,
+                            indent,
+                            text,
+                        });
+                        self.render(&arm.body, indent + 1, labels, out);
+                    }
+                    out.push(DecompiledLine {
+                        offset: *end,
+                        indent,
+                        text: format!("}}{}", tail),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn const_prec<T: Default + PartialOrd>(value: T) -> u8 {
+    if value < T::default() {
+        UNARY
+    } else {
+        ATOM
+    }
+}
+
+/// Decompiles the body of the given function into C-like pseudo-code, one
+/// line per statement.
+///
+/// Values are shown as nested expressions where the order of evaluation
+/// allows it, and are otherwise assigned to temporaries named `t0`, `t1` and
+/// so on. Memory accesses are shown as e.g. `mem32[p + 8]`, where loads of
+/// fewer bits than their type say how they are extended, as in `mem8u`.
+/// Blocks that branches jump out of become labelled blocks, and loops become
+/// `while` or `do`/`while` statements, with the common patterns of branches
+/// simplified into `if` statements and loop conditions.
+///
+/// Names come from the name section. Unnamed parameters are named `p0`,
+/// `p1` and so on, and unnamed locals `l2`, `l3` and so on, after their
+/// index.
+pub fn decompile(data: &[u8], func_index: u32) -> Result<Vec<DecompiledLine>, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    let (mut func, body) = func_validator(data, func_index)?;
+    let ty = module.func_type(func_index).unwrap();
+    let local_names = module.names.locals.get(&func_index);
+    let name_local = |index: u32, prefix: &str| match local_names.and_then(|l| l.get(&index)) {
+        Some(name) => name.to_string(),
+        None => format!("{}{}", prefix, index),
+    };
+
+    let mut locals = Vec::new();
+    let mut params = Vec::new();
+    for (i, &param) in ty.params().iter().enumerate() {
+        let name = name_local(i as u32, "p");
+        params.push(format!("{} {}", type_name(param), name));
+        locals.push(name);
+    }
+    let mut declarations: Vec<(ValType, Vec<String>)> = Vec::new();
+    for run in body.get_locals_reader()? {
+        let (count, ty) = run?;
+        for _ in 0..count {
+            let name = name_local(locals.len() as u32, "l");
+            match declarations.last_mut() {
+                Some((last, names)) if *last == ty => names.push(name.clone()),
+                _ => declarations.push((ty, vec![name.clone()])),
+            }
+            locals.push(name);
+        }
+    }
+
+    let ops = body
+        .get_operators_reader()?
+        .into_iter_with_offsets()
+        .collect::<Result<Vec<_>, _>>()?;
+    let targeted = branch_targets(&ops);
+    let mut function = Frame::new(None, FrameKind::Function, 0, body.range().start);
+    function.results = ty.results().iter().map(|&t| type_name(t)).collect();
+    func.read_locals(&mut body.get_binary_reader())?;
+    let mut decompiler = Decompiler {
+        module: &module,
+        namer: Namer {
+            module: &module,
+            locals,
+        },
+        func,
+        stack: Vec::new(),
+        frames: vec![function],
+        jumps: vec![0; targeted.len()],
+        targeted,
+        next_block: 0,
+        next_temp: 0,
+        skipped: 0,
+    };
+    for (op, offset) in ops.iter() {
+        let before = decompiler.func.operand_stack_height() as usize;
+        decompiler.func.op(*offset, op)?;
+        decompiler.operator(op, *offset, before);
+    }
+
+    let start = body.range().start;
+    let end = ops.last().map_or(start, |(_, offset)| *offset);
+    let results = match ty.results() {
+        [] => "void".to_string(),
+        [ty] => type_name(*ty),
+        results => {
+            let names: Vec<String> = results.iter().map(|&t| type_name(t)).collect();
+            format!("({})", names.join(", "))
+        }
+    };
+    let name = decompiler
+        .namer
+        .item(ItemRef::new(ItemKind::Func, func_index));
+    let mut out = vec![DecompiledLine {
+        offset: start,
+        indent: 0,
+        text: format!("{} {}({}) {{", results, name, params.join(", ")),
+    }];
+    for (ty, names) in declarations {
+        out.push(DecompiledLine {
+            offset: start,
+            indent: 1,
+            text: format!("{} {};", type_name(ty), names.join(", ")),
+        });
+    }
+    let body = mem::take(&mut decompiler.frames[0].body);
+    decompiler.render(&body, 1, &mut HashMap::new(), &mut out);
+    out.push(DecompiledLine {
+        offset: end,
+        indent: 0,
+        text: "}".to_string(),
+    });
+    Ok(out)
+}
@@ -0,0 +1,340 @@
+//! Structural differences between two versions of a module.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::{HashMap, VecDeque};
+use wasm_bindgen::prelude::*;
+use wasmparser::{ExternalKind, TypeRef};
+
+use crate::module::{ItemKind, ItemRef, ParsedModule};
+use crate::types::BinaryError;
+
+/// An item that differs between the old and new module.
+#[wasmtools_struct]
+pub struct ItemDiff {
+    /// `type`, `import`, `export`, `func`, `global`, `data` or `custom`.
+    pub kind: String,
+    /// `added`, `removed`, `changed`, or `renamed` if only the item's name
+    /// changed.
+    pub change: String,
+    /// The item's name in the new module, or in the old one if it was
+    /// removed.
+    pub name: String,
+    pub old_name: Option<String>,
+    pub old_index: Option<u32>,
+    pub new_index: Option<u32>,
+    /// The number of bytes the item occupies in the old module, or zero if it
+    /// was added.
+    pub old_size: usize,
+    pub new_size: usize,
+    pub size_delta: i32,
+}
+
+#[wasm_bindgen]
+pub struct ModuleDiff {
+    items: Vec<ItemDiff>,
+    old_size: usize,
+    new_size: usize,
+}
+
+/// An item to be aligned with the items of the same kind in the other module.
+struct Entry {
+    index: u32,
+    name: String,
+    /// A name that identifies the item across versions, if it has one.
+    key: Option<String>,
+    /// The item's definition, in a form that can be compared across versions.
+    /// Items with equal content are unchanged.
+    content: Vec<u8>,
+    size: usize,
+}
+
+/// Pairs up old and new entries: first by key, then by identical content,
+/// and finally by position among the entries that have no key.
+fn align(kind: &str, old: &[Entry], new: &[Entry], out: &mut Vec<ItemDiff>) {
+    let mut matches: Vec<Option<usize>> = vec![None; old.len()];
+    let mut taken = vec![false; new.len()];
+
+    let mut by_key: HashMap<&str, VecDeque<usize>> = HashMap::new();
+    for (j, n) in new.iter().enumerate() {
+        if let Some(key) = &n.key {
+            by_key.entry(key).or_default().push_back(j);
+        }
+    }
+    for (i, o) in old.iter().enumerate() {
+        let candidates = o.key.as_deref().and_then(|k| by_key.get_mut(k));
+        if let Some(j) = candidates.and_then(|c| c.pop_front()) {
+            matches[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+
+    let mut by_content: HashMap<&[u8], VecDeque<usize>> = HashMap::new();
+    for (j, n) in new.iter().enumerate() {
+        if !taken[j] {
+            by_content.entry(&n.content).or_default().push_back(j);
+        }
+    }
+    for (i, o) in old.iter().enumerate() {
+        if matches[i].is_some() {
+            continue;
+        }
+        let candidates = by_content.get_mut(o.content.as_slice());
+        if let Some(j) = candidates.and_then(|c| c.pop_front()) {
+            matches[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+
+    for (i, (o, n)) in old.iter().zip(new).enumerate() {
+        if matches[i].is_none() && !taken[i] && o.key.is_none() && n.key.is_none() {
+            matches[i] = Some(i);
+            taken[i] = true;
+        }
+    }
+
+    for (o, m) in old.iter().zip(matches) {
+        let (change, n) = match m {
+            None => ("removed", None),
+            Some(j) => {
+                let n = &new[j];
+                if o.content != n.content {
+                    ("changed", Some(n))
+                } else if o.key.is_some() && n.key.is_some() && o.key != n.key {
+                    ("renamed", Some(n))
+                } else {
+                    continue;
+                }
+            }
+        };
+        out.push(ItemDiff {
+            kind: kind.to_string(),
+            change: change.to_string(),
+            name: n.unwrap_or(o).name.clone(),
+            old_name: Some(o.name.clone()),
+            old_index: Some(o.index),
+            new_index: n.map(|n| n.index),
+            old_size: o.size,
+            new_size: n.map_or(0, |n| n.size),
+            size_delta: n.map_or(0, |n| n.size as i32) - o.size as i32,
+        });
+    }
+    for (n, taken) in new.iter().zip(taken) {
+        if taken {
+            continue;
+        }
+        out.push(ItemDiff {
+            kind: kind.to_string(),
+            change: "added".to_string(),
+            name: n.name.clone(),
+            old_name: None,
+            old_index: None,
+            new_index: Some(n.index),
+            old_size: 0,
+            new_size: n.size,
+            size_delta: n.size as i32,
+        });
+    }
+}
+
+fn bytes(module: &ParsedModule, range: std::ops::Range<usize>) -> Vec<u8> {
+    module.data[range].to_vec()
+}
+
+/// The encoding of a type, so that types are compared by structure rather
+/// than by index.
+fn type_content(module: &ParsedModule, type_index: u32) -> Vec<u8> {
+    match module.types.get(type_index as usize) {
+        Some(ty) => bytes(module, ty.range.clone()),
+        None => type_index.to_le_bytes().to_vec(),
+    }
+}
+
+fn types(module: &ParsedModule) -> Vec<Entry> {
+    (0..module.types.len() as u32)
+        .map(|i| Entry {
+            index: i,
+            name: module.display_name(ItemRef::new(ItemKind::Type, i)),
+            key: None,
+            content: type_content(module, i),
+            size: module.types[i as usize].range.len(),
+        })
+        .collect()
+}
+
+fn imports(module: &ParsedModule) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (i, import) in module.imports.iter().enumerate() {
+        let name = format!("{}.{}", import.value.module, import.value.name);
+        let content = match import.value.ty {
+            TypeRef::Func(t) => {
+                let mut content = vec![0];
+                content.extend(type_content(module, t));
+                content
+            }
+            _ => bytes(module, import.range.clone()),
+        };
+        entries.push(Entry {
+            index: i as u32,
+            key: Some(name.clone()),
+            name,
+            content,
+            size: import.range.len(),
+        });
+    }
+    entries
+}
+
+fn exports(module: &ParsedModule) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (i, export) in module.exports.iter().enumerate() {
+        // Compare what the export refers to by name rather than index, so
+        // that an export isn't considered changed when indices shift.
+        let target = ItemRef::new(export.value.kind.into(), export.value.index);
+        let mut content = vec![match export.value.kind {
+            ExternalKind::Func => 0,
+            ExternalKind::Table => 1,
+            ExternalKind::Memory => 2,
+            ExternalKind::Global => 3,
+            ExternalKind::Tag => 4,
+        }];
+        content.extend(module.display_name(target).into_bytes());
+        entries.push(Entry {
+            index: i as u32,
+            name: export.value.name.to_string(),
+            key: Some(export.value.name.to_string()),
+            content,
+            size: export.range.len(),
+        });
+    }
+    entries
+}
+
+/// Defined functions, identified by their name in the name section or their
+/// export name.
+fn funcs(module: &ParsedModule) -> Vec<Entry> {
+    let mut export_names = HashMap::new();
+    for export in module.exports.iter() {
+        if export.value.kind == ExternalKind::Func {
+            export_names
+                .entry(export.value.index)
+                .or_insert(export.value.name);
+        }
+    }
+    let names = module.func_display_names();
+    let mut entries = Vec::new();
+    for (i, body) in module.bodies.iter().enumerate() {
+        let index = module.num_imported_funcs() + i as u32;
+        let key = module
+            .names
+            .functions
+            .get(&index)
+            .or_else(|| export_names.get(&index));
+        let mut content = bytes(module, body.range());
+        if let Some(ty) = module.func_type_idx(index) {
+            content.extend(type_content(module, ty));
+        }
+        let item = ItemRef::new(ItemKind::Func, index);
+        entries.push(Entry {
+            index,
+            name: match names.get(index as usize) {
+                Some(name) => name.clone(),
+                None => module.display_name(item),
+            },
+            key: key.map(|k| k.to_string()),
+            content,
+            size: module.item_size(item),
+        });
+    }
+    entries
+}
+
+/// Defined globals or data segments, identified by their name in the name
+/// section.
+fn named_items(module: &ParsedModule, kind: ItemKind) -> Vec<Entry> {
+    let names = match kind {
+        ItemKind::Global => &module.names.globals,
+        _ => &module.names.datas,
+    };
+    let mut entries = Vec::new();
+    for index in module.num_imported(kind)..module.num_items(kind) {
+        let item = ItemRef::new(kind, index);
+        let range = module.item_range(item).unwrap_or_default();
+        entries.push(Entry {
+            index,
+            name: module.display_name(item),
+            key: names.get(&index).map(|n| n.to_string()),
+            size: range.len(),
+            content: bytes(module, range),
+        });
+    }
+    entries
+}
+
+fn customs(module: &ParsedModule) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (i, custom) in module.customs.iter().enumerate() {
+        entries.push(Entry {
+            index: i as u32,
+            name: custom.name().to_string(),
+            key: Some(custom.name().to_string()),
+            content: custom.data().to_vec(),
+            size: custom.range().len(),
+        });
+    }
+    entries
+}
+
+impl ModuleDiff {
+    /// Compares two modules item by item. Items are paired up by name where
+    /// they have one (import and export names, and names from the name
+    /// section), or else by identical content, and unnamed globals and data
+    /// segments by position. Only items that were added, removed or changed
+    /// are reported.
+    ///
+    /// Function bodies are compared byte for byte, so a function whose body
+    /// refers to an index that shifted is reported as changed.
+    pub fn build(old: &ParsedModule, new: &ParsedModule) -> ModuleDiff {
+        let mut items = Vec::new();
+        align("type", &types(old), &types(new), &mut items);
+        align("import", &imports(old), &imports(new), &mut items);
+        align("export", &exports(old), &exports(new), &mut items);
+        align("func", &funcs(old), &funcs(new), &mut items);
+        let (old_globals, new_globals) = (
+            named_items(old, ItemKind::Global),
+            named_items(new, ItemKind::Global),
+        );
+        align("global", &old_globals, &new_globals, &mut items);
+        let (old_datas, new_datas) = (
+            named_items(old, ItemKind::Data),
+            named_items(new, ItemKind::Data),
+        );
+        align("data", &old_datas, &new_datas, &mut items);
+        align("custom", &customs(old), &customs(new), &mut items);
+        ModuleDiff {
+            items,
+            old_size: old.data.len(),
+            new_size: new.data.len(),
+        }
+    }
+
+    pub fn items(&self) -> &[ItemDiff] {
+        &self.items
+    }
+}
+
+#[wasm_bindgen]
+impl ModuleDiff {
+    #[wasm_bindgen(js_name = items)]
+    pub fn js_items(&self) -> ItemDiffArray {
+        self.items.clone().into()
+    }
+
+    pub fn old_size(&self) -> usize {
+        self.old_size
+    }
+
+    pub fn new_size(&self) -> usize {
+        self.new_size
+    }
+}
@@ -0,0 +1,340 @@
+//! Detection of functions with identical or nearly identical bodies, such as
+//! monomorphized copies of the same generic function.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use wasm_bindgen::prelude::*;
+use wasmparser::FunctionBody;
+
+use crate::module::ParsedModule;
+use crate::refs::{IndexPolicy, Normalizer};
+use crate::types::BinaryError;
+
+/// A function that belongs to a group of identical or similar functions.
+#[wasmtools_struct]
+pub struct DuplicateFunction {
+    /// The id of the group the function belongs to.
+    pub group: u32,
+    pub func_index: u32,
+    pub name: String,
+    /// The size of the function's body in bytes.
+    pub size: usize,
+    /// The estimated fraction of the function's code that it shares with the
+    /// function it was clustered with, which is 1 for identical functions and
+    /// at least the threshold for similar ones.
+    pub similarity: f64,
+}
+
+/// A group of identical functions, or a cluster of similar ones.
+#[wasmtools_struct]
+pub struct DuplicateGroup {
+    pub id: u32,
+    /// `identical` or `similar`.
+    pub kind: String,
+    pub num_functions: u32,
+    /// The combined size of the bodies of the functions in the group.
+    pub total_size: usize,
+    /// The number of bytes that would be saved by keeping only the largest
+    /// function in the group. For similar functions, this is an upper bound,
+    /// since the differences would have to be handled somehow.
+    pub savings: usize,
+}
+
+/// Functions with fewer operators than this are not clustered as similar,
+/// since small functions look alike without being meaningfully duplicated.
+const MIN_SIMILAR_OPERATORS: usize = 16;
+/// The length of the operator sequences compared between similar functions.
+const SHINGLE_LEN: usize = 4;
+const NUM_HASHES: usize = 64;
+/// Signatures are split into bands, and functions that agree on a whole
+/// band are compared.
+const BAND_LEN: usize = 4;
+
+fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A cheap way to derive many hash functions from one, as in SplitMix64.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+struct Function {
+    func_index: u32,
+    name: String,
+    size: usize,
+    /// A 128-bit hash of the function's key, which is only equal for
+    /// functions whose keys are equal, barring collisions.
+    digest: (u64, u64),
+    /// Hashes of the function's operators.
+    operators: Vec<u64>,
+}
+
+/// The function's type, locals and operators, normalized, which are equal
+/// for identical functions. The hashes of the operators are pushed to
+/// `operators` along the way.
+fn key(
+    normalizer: &Normalizer,
+    func_index: u32,
+    body: &FunctionBody,
+    operators: &mut Vec<u64>,
+) -> Result<String, BinaryError> {
+    let mut key = format!("{:?}\n", normalizer.module.func_type(func_index));
+    for local in body.get_locals_reader()? {
+        let (count, ty) = local?;
+        let _ = writeln!(key, "{} {:?}", count, ty);
+    }
+    for op in body.get_operators_reader()? {
+        let op = normalizer.operator(&op?);
+        operators.push(hash(&op));
+        key.push_str(&op);
+        key.push('\n');
+    }
+    Ok(key)
+}
+
+impl Function {
+    /// The MinHash signature of the function's operator sequences, where the
+    /// fraction of equal entries in two signatures estimates how many
+    /// sequences the functions share.
+    fn signature(&self) -> [u64; NUM_HASHES] {
+        let mut signature = [u64::MAX; NUM_HASHES];
+        for shingle in self.operators.windows(SHINGLE_LEN) {
+            let h = hash(shingle);
+            for (i, min) in signature.iter_mut().enumerate() {
+                *min = (*min).min(mix(h ^ mix(i as u64)));
+            }
+        }
+        signature
+    }
+}
+
+fn similarity(a: &[u64; NUM_HASHES], b: &[u64; NUM_HASHES]) -> f64 {
+    let equal = a.iter().zip(b).filter(|(a, b)| a == b).count();
+    equal as f64 / NUM_HASHES as f64
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+#[wasm_bindgen]
+pub struct DuplicateReport {
+    groups: Vec<DuplicateGroup>,
+    functions: Vec<DuplicateFunction>,
+}
+
+impl DuplicateReport {
+    /// Groups the defined functions whose type, locals and operators are
+    /// identical, and clusters functions whose operators are at least
+    /// `threshold` similar. Each group of identical functions is represented
+    /// in the clusters by its first function.
+    ///
+    /// With `abstract_indices`, references to functions, globals, tables,
+    /// tags and segments are ignored, so e.g. two functions that only call
+    /// different functions are considered identical. Types are always
+    /// compared by structure.
+    pub fn build(
+        module: &ParsedModule,
+        abstract_indices: bool,
+        threshold: f64,
+    ) -> Result<DuplicateReport, BinaryError> {
+        let normalizer = Normalizer {
+            module,
+            indices: match abstract_indices {
+                true => IndexPolicy::Abstract,
+                false => IndexPolicy::Keep,
+            },
+        };
+        let names = module.func_display_names();
+        let mut functions = Vec::with_capacity(module.bodies.len());
+        for (i, body) in module.bodies.iter().enumerate() {
+            let func_index = module.num_imported_funcs() + i as u32;
+            let mut operators = Vec::new();
+            let key = key(&normalizer, func_index, body, &mut operators)?;
+            functions.push(Function {
+                func_index,
+                name: names[func_index as usize].clone(),
+                size: body.range().len(),
+                digest: (hash((0u8, &key)), hash((1u8, &key))),
+                operators,
+            });
+        }
+
+        let mut report = DuplicateReport {
+            groups: Vec::new(),
+            functions: Vec::new(),
+        };
+
+        let mut by_digest: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+        for (i, f) in functions.iter().enumerate() {
+            by_digest.entry(f.digest).or_default().push(i);
+        }
+        let mut identical: Vec<Vec<usize>> = Vec::new();
+        for bucket in by_digest.into_values() {
+            if bucket.len() == 1 {
+                identical.push(bucket);
+                continue;
+            }
+            // Rebuild the keys of the functions that share a digest, to
+            // split them up in case of a collision.
+            let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+            for i in bucket {
+                let func_index = functions[i].func_index;
+                let body = &module.bodies[i];
+                let key = key(&normalizer, func_index, body, &mut Vec::new())?;
+                by_key.entry(key).or_default().push(i);
+            }
+            identical.extend(by_key.into_values());
+        }
+        identical.sort_by_key(|members| members[0]);
+        // Only one function of each identical group takes part in clustering.
+        let representatives: Vec<usize> = identical
+            .iter()
+            .map(|members| members[0])
+            .filter(|&i| functions[i].operators.len() >= MIN_SIMILAR_OPERATORS)
+            .collect();
+        for members in identical {
+            if members.len() > 1 {
+                let members = members.into_iter().map(|i| (i, 1.0)).collect();
+                report.add_group("identical", &functions, members);
+            }
+        }
+
+        let signatures: Vec<[u64; NUM_HASHES]> = representatives
+            .iter()
+            .map(|&i| functions[i].signature())
+            .collect();
+        let mut parents: Vec<usize> = (0..representatives.len()).collect();
+        // The similarity of each function to the most similar function it was
+        // clustered with.
+        let mut similarities = vec![0.0; representatives.len()];
+        for band in 0..NUM_HASHES / BAND_LEN {
+            let mut buckets: HashMap<&[u64], Vec<usize>> = HashMap::new();
+            for (r, signature) in signatures.iter().enumerate() {
+                let band = &signature[band * BAND_LEN..(band + 1) * BAND_LEN];
+                buckets.entry(band).or_default().push(r);
+            }
+            for bucket in buckets.values() {
+                for (n, &a) in bucket.iter().enumerate() {
+                    for &b in &bucket[n + 1..] {
+                        if find(&mut parents, a) == find(&mut parents, b) {
+                            continue;
+                        }
+                        let s = similarity(&signatures[a], &signatures[b]);
+                        if s >= threshold {
+                            similarities[a] = f64::max(similarities[a], s);
+                            similarities[b] = f64::max(similarities[b], s);
+                            let root = find(&mut parents, a);
+                            let other = find(&mut parents, b);
+                            parents[other] = root;
+                        }
+                    }
+                }
+            }
+        }
+        let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+        for r in 0..representatives.len() {
+            let root = find(&mut parents, r);
+            clusters.entry(root).or_default().push(r);
+        }
+        let mut similar: Vec<Vec<usize>> = clusters
+            .into_values()
+            .filter(|members| members.len() > 1)
+            .collect();
+        similar.sort_by_key(|members| members[0]);
+        for members in similar {
+            let members = members
+                .iter()
+                .map(|&r| (representatives[r], similarities[r]))
+                .collect();
+            report.add_group("similar", &functions, members);
+        }
+
+        Ok(report)
+    }
+
+    fn add_group(&mut self, kind: &str, functions: &[Function], members: Vec<(usize, f64)>) {
+        let id = self.groups.len() as u32;
+        let total_size: usize = members.iter().map(|&(i, _)| functions[i].size).sum();
+        let largest = members.iter().map(|&(i, _)| functions[i].size).max();
+        self.groups.push(DuplicateGroup {
+            id,
+            kind: kind.to_string(),
+            num_functions: members.len() as u32,
+            total_size,
+            savings: total_size - largest.unwrap_or(0),
+        });
+        for (i, similarity) in members {
+            let f = &functions[i];
+            self.functions.push(DuplicateFunction {
+                group: id,
+                func_index: f.func_index,
+                name: f.name.clone(),
+                size: f.size,
+                similarity,
+            });
+        }
+    }
+
+    /// The groups of identical functions followed by the clusters of similar
+    /// ones, each in the order they first appear in the module.
+    pub fn groups(&self) -> &[DuplicateGroup] {
+        &self.groups
+    }
+
+    /// The functions in every group, in the order of `groups`.
+    pub fn functions(&self) -> &[DuplicateFunction] {
+        &self.functions
+    }
+
+    /// The number of bytes that would be saved by folding every group of
+    /// identical functions into one.
+    pub fn identical_savings(&self) -> usize {
+        self.groups
+            .iter()
+            .filter(|g| g.kind == "identical")
+            .map(|g| g.savings)
+            .sum()
+    }
+}
+
+#[wasm_bindgen]
+impl DuplicateReport {
+    #[wasm_bindgen(js_name = groups)]
+    pub fn js_groups(&self) -> DuplicateGroupArray {
+        self.groups.clone().into()
+    }
+
+    #[wasm_bindgen(js_name = functions)]
+    pub fn js_functions(&self) -> DuplicateFunctionArray {
+        self.functions.clone().into()
+    }
+
+    /// The functions in one group.
+    pub fn group_functions(&self, group: u32) -> DuplicateFunctionArray {
+        self.functions
+            .iter()
+            .filter(|f| f.group == group)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    #[wasm_bindgen(js_name = identical_savings)]
+    pub fn js_identical_savings(&self) -> usize {
+        self.identical_savings()
+    }
+}
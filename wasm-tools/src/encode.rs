@@ -0,0 +1,591 @@
+//! Encodes wasmparser's representation of a module back into the binary
+//! format, always choosing the smallest encoding.
+
+use std::ops::Range;
+use wasmparser::{
+    BinaryReader, BlockType, BrTable, ConstExpr, Data, DataKind, ElementItems, ElementKind, Export,
+    ExternalKind, FuncType, FunctionBody, GlobalType, HeapType, Ieee32, Ieee64, Import, MemArg,
+    MemoryType, Operator, OperatorsReader, RefType, Table, TableInit, TableType, TagKind, TagType,
+    TypeRef, ValType, V128,
+};
+
+use crate::module::{Item, ParsedModule, SectionInfo};
+use crate::types::BinaryError;
+
+pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+    write_u64(out, value as u64);
+}
+
+pub fn write_u64(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_bit = byte & 0x40 != 0;
+        if (value == 0 && !sign_bit) || (value == -1 && sign_bit) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Writes a section, or a subsection of the name section: an id, followed by
+/// the size of the contents and the contents themselves.
+pub fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_u32(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+fn heap_type(out: &mut Vec<u8>, ty: HeapType) {
+    match ty {
+        HeapType::TypedFunc(i) => write_i64(out, i as i64),
+        HeapType::Func => out.push(0x70),
+        HeapType::Extern => out.push(0x6f),
+        HeapType::Any => out.push(0x6e),
+        HeapType::None => out.push(0x65),
+        HeapType::NoExtern => out.push(0x69),
+        HeapType::NoFunc => out.push(0x68),
+        HeapType::Eq => out.push(0x6d),
+        HeapType::Struct => out.push(0x67),
+        HeapType::Array => out.push(0x66),
+        HeapType::I31 => out.push(0x6a),
+    }
+}
+
+fn ref_type(out: &mut Vec<u8>, ty: RefType) {
+    let heap = ty.heap_type();
+    // Nullable references to abstract heap types have a one-byte shorthand.
+    if ty.is_nullable() && !matches!(heap, HeapType::TypedFunc(_)) {
+        heap_type(out, heap);
+    } else {
+        out.push(if ty.is_nullable() { 0x6c } else { 0x6b });
+        heap_type(out, heap);
+    }
+}
+
+fn val_type(out: &mut Vec<u8>, ty: ValType) {
+    match ty {
+        ValType::I32 => out.push(0x7f),
+        ValType::I64 => out.push(0x7e),
+        ValType::F32 => out.push(0x7d),
+        ValType::F64 => out.push(0x7c),
+        ValType::V128 => out.push(0x7b),
+        ValType::Ref(r) => ref_type(out, r),
+    }
+}
+
+fn func_type(out: &mut Vec<u8>, ty: &FuncType) {
+    out.push(0x60);
+    for types in [ty.params(), ty.results()] {
+        write_u32(out, types.len() as u32);
+        for &t in types {
+            val_type(out, t);
+        }
+    }
+}
+
+fn table_type(out: &mut Vec<u8>, ty: &TableType) {
+    ref_type(out, ty.element_type);
+    match ty.maximum {
+        Some(max) => {
+            out.push(0x01);
+            write_u32(out, ty.initial);
+            write_u32(out, max);
+        }
+        None => {
+            out.push(0x00);
+            write_u32(out, ty.initial);
+        }
+    }
+}
+
+fn memory_type(out: &mut Vec<u8>, ty: &MemoryType) {
+    let flags = ty.maximum.is_some() as u8 | (ty.shared as u8) << 1 | (ty.memory64 as u8) << 2;
+    out.push(flags);
+    write_u64(out, ty.initial);
+    if let Some(max) = ty.maximum {
+        write_u64(out, max);
+    }
+}
+
+fn global_type(out: &mut Vec<u8>, ty: &GlobalType) {
+    val_type(out, ty.content_type);
+    out.push(ty.mutable as u8);
+}
+
+fn tag_type(out: &mut Vec<u8>, ty: &TagType) {
+    match ty.kind {
+        TagKind::Exception => out.push(0x00),
+    }
+    write_u32(out, ty.func_type_idx);
+}
+
+pub fn external_kind(kind: ExternalKind) -> u8 {
+    match kind {
+        ExternalKind::Func => 0,
+        ExternalKind::Table => 1,
+        ExternalKind::Memory => 2,
+        ExternalKind::Global => 3,
+        ExternalKind::Tag => 4,
+    }
+}
+
+/// An immediate argument of an operator. `name` is the argument's name in
+/// wasmparser's operator list.
+trait Immediate {
+    fn encode(&self, name: &str, out: &mut Vec<u8>) -> Result<(), BinaryError>;
+}
+
+impl Immediate for u32 {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        write_u32(out, *self);
+        Ok(())
+    }
+}
+
+impl Immediate for u8 {
+    fn encode(&self, name: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        // `table_byte` and `mem_byte` are the raw bytes of the table or memory
+        // index that precedes them, which is encoded from the index itself.
+        if !name.ends_with("_byte") {
+            out.push(*self);
+        }
+        Ok(())
+    }
+}
+
+impl Immediate for i32 {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        write_i64(out, *self as i64);
+        Ok(())
+    }
+}
+
+impl Immediate for i64 {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        write_i64(out, *self);
+        Ok(())
+    }
+}
+
+impl Immediate for Ieee32 {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        out.extend_from_slice(&self.bits().to_le_bytes());
+        Ok(())
+    }
+}
+
+impl Immediate for Ieee64 {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        out.extend_from_slice(&self.bits().to_le_bytes());
+        Ok(())
+    }
+}
+
+impl Immediate for V128 {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        out.extend_from_slice(self.bytes());
+        Ok(())
+    }
+}
+
+impl Immediate for [u8; 16] {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        out.extend_from_slice(self);
+        Ok(())
+    }
+}
+
+impl Immediate for MemArg {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        // Bit 6 of the alignment says that a memory index follows.
+        if self.memory == 0 {
+            write_u32(out, self.align as u32);
+        } else {
+            write_u32(out, self.align as u32 | 0x40);
+            write_u32(out, self.memory);
+        }
+        write_u64(out, self.offset);
+        Ok(())
+    }
+}
+
+impl Immediate for BlockType {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        match *self {
+            BlockType::Empty => out.push(0x40),
+            BlockType::Type(ty) => val_type(out, ty),
+            BlockType::FuncType(i) => write_i64(out, i as i64),
+        }
+        Ok(())
+    }
+}
+
+impl Immediate for ValType {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        // Only `select` takes a value type, as a vector of one type.
+        out.push(1);
+        val_type(out, *self);
+        Ok(())
+    }
+}
+
+impl Immediate for HeapType {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        heap_type(out, *self);
+        Ok(())
+    }
+}
+
+impl Immediate for BrTable<'_> {
+    fn encode(&self, _: &str, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+        write_u32(out, self.len());
+        for target in self.targets() {
+            write_u32(out, target?);
+        }
+        write_u32(out, self.default());
+        Ok(())
+    }
+}
+
+macro_rules! encode_immediates {
+    ($(@$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => (
+        fn encode_immediates(op: &Operator, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+            match op {
+                $(
+                    Operator::$op$({ $($arg),* })? => {
+                        $($( $arg.encode(stringify!($arg), out)?; )*)?
+                    }
+                )*
+            }
+            Ok(())
+        }
+    );
+}
+wasmparser::for_each_operator!(encode_immediates);
+
+/// Encodes an operator read from `data` at `offset`. The opcode is copied
+/// from `data`, since wasmparser doesn't expose it, but the sub-opcodes of
+/// prefixed operators are re-encoded.
+pub fn operator(
+    data: &[u8],
+    op: &Operator,
+    offset: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), BinaryError> {
+    let prefix = data[offset];
+    out.push(prefix);
+    if matches!(prefix, 0xfb..=0xfe) {
+        let mut reader = BinaryReader::new_with_offset(&data[offset + 1..], offset + 1);
+        write_u32(out, reader.read_var_u32()?);
+    }
+    encode_immediates(op, out)?;
+    // `atomic.fence` has a reserved byte that wasmparser doesn't list as an
+    // immediate.
+    if let Operator::AtomicFence = op {
+        out.push(0x00);
+    }
+    Ok(())
+}
+
+/// Encodes operators, including the final `end`.
+fn operators(data: &[u8], reader: OperatorsReader, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+    for op in reader.into_iter_with_offsets() {
+        let (op, offset) = op?;
+        operator(data, &op, offset, out)?;
+    }
+    Ok(())
+}
+
+fn const_expr(data: &[u8], expr: &ConstExpr, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+    operators(data, expr.get_operators_reader(), out)
+}
+
+/// Encodes a function's local declarations, given as runs of locals of the
+/// same type.
+pub fn locals(out: &mut Vec<u8>, runs: &[(u32, ValType)]) {
+    write_u32(out, runs.len() as u32);
+    for &(count, ty) in runs {
+        write_u32(out, count);
+        val_type(out, ty);
+    }
+}
+
+/// Encodes a function body, including its size.
+pub fn function_body(
+    data: &[u8],
+    body: &FunctionBody,
+    out: &mut Vec<u8>,
+) -> Result<(), BinaryError> {
+    let mut runs = Vec::new();
+    for run in body.get_locals_reader()? {
+        runs.push(run?);
+    }
+    let mut contents = Vec::new();
+    locals(&mut contents, &runs);
+    operators(data, body.get_operators_reader()?, &mut contents)?;
+    write_u32(out, contents.len() as u32);
+    out.extend(contents);
+    Ok(())
+}
+
+fn import(out: &mut Vec<u8>, import: &Import) {
+    write_name(out, import.module);
+    write_name(out, import.name);
+    match import.ty {
+        TypeRef::Func(ty) => {
+            out.push(0x00);
+            write_u32(out, ty);
+        }
+        TypeRef::Table(ty) => {
+            out.push(0x01);
+            table_type(out, &ty);
+        }
+        TypeRef::Memory(ty) => {
+            out.push(0x02);
+            memory_type(out, &ty);
+        }
+        TypeRef::Global(ty) => {
+            out.push(0x03);
+            global_type(out, &ty);
+        }
+        TypeRef::Tag(ty) => {
+            out.push(0x04);
+            tag_type(out, &ty);
+        }
+    }
+}
+
+fn table(data: &[u8], table: &Table, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+    match &table.init {
+        TableInit::RefNull => table_type(out, &table.ty),
+        TableInit::Expr(expr) => {
+            out.extend([0x40, 0x00]);
+            table_type(out, &table.ty);
+            const_expr(data, expr, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn export(out: &mut Vec<u8>, export: &Export) {
+    write_name(out, export.name);
+    out.push(external_kind(export.kind));
+    write_u32(out, export.index);
+}
+
+fn data_segment(data: &[u8], segment: &Data, out: &mut Vec<u8>) -> Result<(), BinaryError> {
+    match &segment.kind {
+        DataKind::Passive => out.push(0x01),
+        DataKind::Active {
+            memory_index: 0,
+            offset_expr,
+        } => {
+            out.push(0x00);
+            const_expr(data, offset_expr, out)?;
+        }
+        DataKind::Active {
+            memory_index,
+            offset_expr,
+        } => {
+            out.push(0x02);
+            write_u32(out, *memory_index);
+            const_expr(data, offset_expr, out)?;
+        }
+    }
+    write_u32(out, segment.data.len() as u32);
+    out.extend_from_slice(segment.data);
+    Ok(())
+}
+
+fn element_segment(
+    data: &[u8],
+    element: &wasmparser::Element,
+    out: &mut Vec<u8>,
+) -> Result<(), BinaryError> {
+    // Bit 0 of the flags is set for passive and declared segments, bit 1 for
+    // declared segments and active segments with an explicit table, and bit
+    // 2 for segments of expressions rather than function indices.
+    let exprs = matches!(element.items, ElementItems::Expressions(_));
+    let mut flags = if exprs { 0x04 } else { 0x00 };
+    match &element.kind {
+        ElementKind::Passive => flags |= 0x01,
+        ElementKind::Declared => flags |= 0x03,
+        ElementKind::Active {
+            table_index: Some(_),
+            ..
+        } => flags |= 0x02,
+        ElementKind::Active { .. } => {}
+    }
+    out.push(flags);
+    if let ElementKind::Active {
+        table_index,
+        offset_expr,
+    } = &element.kind
+    {
+        if let Some(table_index) = table_index {
+            write_u32(out, *table_index);
+        }
+        const_expr(data, offset_expr, out)?;
+    }
+    // Segments without a type or element kind are implicitly of funcrefs.
+    if flags & 0x03 != 0 {
+        if exprs {
+            ref_type(out, element.ty);
+        } else {
+            out.push(0x00);
+        }
+    }
+    match &element.items {
+        ElementItems::Functions(funcs) => {
+            write_u32(out, funcs.count());
+            for func in funcs.clone() {
+                write_u32(out, func?);
+            }
+        }
+        ElementItems::Expressions(exprs) => {
+            write_u32(out, exprs.count());
+            for expr in exprs.clone() {
+                const_expr(data, &expr?, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An item of a section, encoded on its own.
+pub struct EncodedItem {
+    /// The bytes the item was decoded from.
+    pub range: Range<usize>,
+    pub bytes: Vec<u8>,
+}
+
+fn encode_items<T>(
+    items: &[Item<T>],
+    mut f: impl FnMut(&T, &mut Vec<u8>) -> Result<(), BinaryError>,
+) -> Result<Vec<EncodedItem>, BinaryError> {
+    let mut encoded = Vec::with_capacity(items.len());
+    for item in items {
+        let mut bytes = Vec::new();
+        f(&item.value, &mut bytes)?;
+        encoded.push(EncodedItem {
+            range: item.range.clone(),
+            bytes,
+        });
+    }
+    Ok(encoded)
+}
+
+/// Encodes each item of a section that consists of a vector of items, which
+/// is every kind of section but the custom, start and data count sections.
+/// The ranges of function bodies include their sizes.
+pub fn section_items(
+    module: &ParsedModule,
+    section: &SectionInfo,
+) -> Result<Vec<EncodedItem>, BinaryError> {
+    let data = module.data;
+    match section.id {
+        1 => encode_items(&module.types, |ty, out| {
+            func_type(out, ty);
+            Ok(())
+        }),
+        2 => encode_items(&module.imports, |i, out| {
+            import(out, i);
+            Ok(())
+        }),
+        3 => encode_items(&module.functions, |f, out| {
+            write_u32(out, *f);
+            Ok(())
+        }),
+        4 => encode_items(&module.tables, |t, out| table(data, t, out)),
+        5 => encode_items(&module.memories, |m, out| {
+            memory_type(out, m);
+            Ok(())
+        }),
+        6 => encode_items(&module.globals, |g, out| {
+            global_type(out, &g.ty);
+            const_expr(data, &g.init_expr, out)
+        }),
+        7 => encode_items(&module.exports, |e, out| {
+            export(out, e);
+            Ok(())
+        }),
+        9 => encode_items(&module.elements, |e, out| element_segment(data, e, out)),
+        10 => {
+            // Each body starts where the previous one ends, with its size.
+            let contents = &data[section.range.clone()];
+            let mut reader = BinaryReader::new_with_offset(contents, section.range.start);
+            reader.read_var_u32()?;
+            let mut start = reader.original_position();
+            let mut encoded = Vec::with_capacity(module.bodies.len());
+            for body in module.bodies.iter() {
+                let mut bytes = Vec::new();
+                function_body(data, body, &mut bytes)?;
+                let end = body.range().end;
+                encoded.push(EncodedItem {
+                    range: start..end,
+                    bytes,
+                });
+                start = end;
+            }
+            Ok(encoded)
+        }
+        11 => encode_items(&module.datas, |d, out| data_segment(data, d, out)),
+        13 => encode_items(&module.tags, |t, out| {
+            tag_type(out, t);
+            Ok(())
+        }),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Encodes the contents of a section from what was parsed out of it, without
+/// the section id and size. Custom and unknown sections are copied as they
+/// are.
+pub fn section_contents(
+    module: &ParsedModule,
+    section: &SectionInfo,
+) -> Result<Vec<u8>, BinaryError> {
+    let data = module.data;
+    let mut out = Vec::new();
+    match section.id {
+        0 => out.extend_from_slice(&data[section.range.clone()]),
+        8 => {
+            if let Some(start) = &module.start {
+                write_u32(&mut out, start.value);
+            }
+        }
+        12 => {
+            let contents = &data[section.range.clone()];
+            let mut reader = BinaryReader::new_with_offset(contents, section.range.start);
+            write_u32(&mut out, reader.read_var_u32()?);
+        }
+        1..=7 | 9..=11 | 13 => {
+            let items = section_items(module, section)?;
+            write_u32(&mut out, items.len() as u32);
+            for item in items {
+                out.extend(item.bytes);
+            }
+        }
+        _ => out.extend_from_slice(&data[section.range.clone()]),
+    }
+    Ok(out)
+}
@@ -0,0 +1,254 @@
+//! Finds places where a module's encoding is larger than it needs to be,
+//! such as LEB128 numbers padded with extra bytes. Linkers commonly leave
+//! these behind for later relocation, and optimizers remove them.
+
+use js_sys::Array;
+use macros::*;
+use wasm_bindgen::prelude::*;
+use wasmparser::{BinaryReader, ValType};
+
+use crate::encode;
+use crate::module::{ParsedModule, SectionInfo};
+use crate::names::op_name;
+use crate::types::BinaryError;
+
+/// A part of the module whose encoding could be smaller.
+#[wasmtools_struct]
+pub struct EncodingIssue {
+    /// What is encoded inefficiently: `section_size`, `item_count`,
+    /// `body_size`, `locals`, `operator`, `custom_name`, or `item` for any
+    /// other item.
+    pub kind: String,
+    pub section: String,
+    pub offset: usize,
+    /// The number of bytes the encoding takes up.
+    pub size: usize,
+    /// The number of bytes that a minimal encoding would save.
+    pub savings: usize,
+    pub description: String,
+}
+
+fn u32_size(value: u32) -> usize {
+    let mut out = Vec::new();
+    encode::write_u32(&mut out, value);
+    out.len()
+}
+
+struct Lint<'m, 'a> {
+    module: &'m ParsedModule<'a>,
+    issues: Vec<EncodingIssue>,
+}
+
+impl Lint<'_, '_> {
+    fn check(
+        &mut self,
+        kind: &str,
+        section: &SectionInfo,
+        offset: usize,
+        size: usize,
+        minimal: usize,
+        description: impl FnOnce() -> String,
+    ) {
+        if size > minimal {
+            self.issues.push(EncodingIssue {
+                kind: kind.to_string(),
+                section: section.name().to_string(),
+                offset,
+                size,
+                savings: size - minimal,
+                description: description(),
+            });
+        }
+    }
+
+    /// Checks the number at the start of a section's contents, which is the
+    /// item count for most sections.
+    fn check_count(&mut self, kind: &str, section: &SectionInfo) -> Result<(), BinaryError> {
+        let data = &self.module.data[section.range.clone()];
+        let mut reader = BinaryReader::new_with_offset(data, section.range.start);
+        let count = reader.read_var_u32()?;
+        let size = reader.original_position() - section.range.start;
+        self.check(
+            kind,
+            section,
+            section.range.start,
+            size,
+            u32_size(count),
+            || format!("{} is encoded in {} bytes", count, size),
+        );
+        Ok(())
+    }
+
+    fn check_bodies(&mut self, section: &SectionInfo) -> Result<(), BinaryError> {
+        let module = self.module;
+        // Each body's size comes right after the previous body, or after the
+        // count for the first one.
+        let data = &module.data[section.range.clone()];
+        let mut reader = BinaryReader::new_with_offset(data, section.range.start);
+        reader.read_var_u32()?;
+        let mut item_start = reader.original_position();
+        for body in module.bodies.iter() {
+            let body_range = body.range();
+            let size = body_range.start - item_start;
+            let minimal = u32_size(body_range.len() as u32);
+            self.check("body_size", section, item_start, size, minimal, || {
+                format!(
+                    "the size of a {} byte body is encoded in {} bytes",
+                    body_range.len(),
+                    size
+                )
+            });
+
+            item_start = body_range.end;
+
+            // Adjacent runs of the same type can be merged, as long as the
+            // count still fits, and empty runs dropped.
+            let mut runs: Vec<(u32, ValType)> = Vec::new();
+            let mut num_runs = 0;
+            for run in body.get_locals_reader()? {
+                let (count, ty) = run?;
+                num_runs += 1;
+                match runs.last_mut() {
+                    _ if count == 0 => {}
+                    Some((last_count, last_ty)) if *last_ty == ty => {
+                        match last_count.checked_add(count) {
+                            Some(merged) => *last_count = merged,
+                            None => runs.push((count, ty)),
+                        }
+                    }
+                    _ => runs.push((count, ty)),
+                }
+            }
+            let operators = body.get_operators_reader()?;
+            let size = operators.original_position() - body_range.start;
+            let mut minimal = Vec::new();
+            encode::locals(&mut minimal, &runs);
+            self.check(
+                "locals",
+                section,
+                body_range.start,
+                size,
+                minimal.len(),
+                || {
+                    format!(
+                        "{} local declarations in {} bytes could be {} in {} bytes",
+                        num_runs,
+                        size,
+                        runs.len(),
+                        minimal.len()
+                    )
+                },
+            );
+
+            let mut ops = operators.into_iter_with_offsets().peekable();
+            while let Some(op) = ops.next() {
+                let (op, offset) = op?;
+                let end = match ops.peek() {
+                    Some(Ok((_, next))) => *next,
+                    _ => body_range.end,
+                };
+                let mut minimal = Vec::new();
+                encode::operator(module.data, &op, offset, &mut minimal)?;
+                self.check(
+                    "operator",
+                    section,
+                    offset,
+                    end - offset,
+                    minimal.len(),
+                    || format!("{} is encoded in {} bytes", op_name(&op), end - offset),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn check_section(
+        &mut self,
+        section: &SectionInfo,
+        header_start: usize,
+    ) -> Result<(), BinaryError> {
+        let module = self.module;
+        let size = section.range.start - header_start;
+        let minimal = 1 + u32_size(section.range.len() as u32);
+        self.check("section_size", section, header_start, size, minimal, || {
+            format!(
+                "the size of a {} byte section is encoded in {} bytes",
+                section.range.len(),
+                size - 1
+            )
+        });
+
+        match section.id {
+            0 => {
+                let data = &module.data[section.range.clone()];
+                let mut reader = BinaryReader::new_with_offset(data, section.range.start);
+                let name = reader.read_string()?;
+                let size = reader.original_position() - section.range.start;
+                let minimal = u32_size(name.len() as u32) + name.len();
+                self.check(
+                    "custom_name",
+                    section,
+                    section.range.start,
+                    size,
+                    minimal,
+                    || {
+                        format!(
+                            "the length of the name {:?} is encoded in {} bytes",
+                            name,
+                            size - name.len()
+                        )
+                    },
+                );
+            }
+            8 | 12 => self.check_count("item", section)?,
+            10 => {
+                self.check_count("item_count", section)?;
+                self.check_bodies(section)?;
+            }
+            1..=7 | 9 | 11 | 13 => {
+                self.check_count("item_count", section)?;
+                for (i, item) in encode::section_items(module, section)?
+                    .into_iter()
+                    .enumerate()
+                {
+                    self.check(
+                        "item",
+                        section,
+                        item.range.start,
+                        item.range.len(),
+                        item.bytes.len(),
+                        || {
+                            format!(
+                                "{} item {} is encoded in {} bytes instead of {}",
+                                section.name(),
+                                i,
+                                item.range.len(),
+                                item.bytes.len()
+                            )
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Finds every number, section header, local declaration and item that is
+/// encoded in more bytes than necessary, in the order they appear.
+///
+/// Function bodies are checked operator by operator. Other items are checked
+/// as a whole, since they are small.
+pub fn find_encoding_issues(module: &ParsedModule) -> Result<Vec<EncodingIssue>, BinaryError> {
+    let mut lint = Lint {
+        module,
+        issues: Vec::new(),
+    };
+    let mut header_start = 8;
+    for section in module.sections.iter() {
+        lint.check_section(section, header_start)?;
+        header_start = section.range.end;
+    }
+    Ok(lint.issues)
+}
@@ -0,0 +1,273 @@
+//! A JSON export of the whole module, for dashboards and scripts that don't
+//! go through the JS bindings.
+//!
+//! Every item has the same shape as its TypeScript definition: objects have
+//! `is_error: false`, errors are `{ is_error: true, message, offset }`, and
+//! enums have a `kind` along with a field of the same name holding the
+//! kind's value, if it has one. Beyond that, `undefined` becomes `null`,
+//! `bigint`s become plain numbers, and byte arrays become arrays of numbers.
+//!
+//! Any change to this shape must bump `SCHEMA_VERSION`.
+
+use serde::Serialize;
+use wasmparser::{CustomSectionReader, DataSectionReader, Parser, Payload};
+
+use crate::code_metadata::CODE_METADATA_PREFIX;
+use crate::sections;
+use crate::types::*;
+
+/// The version of the JSON schema, stored in `schema_version`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct SectionJson {
+    /// The section id, which is 0 for custom sections.
+    pub id: u8,
+    /// The range of the section's contents, after its id and size.
+    pub range: Range,
+}
+
+/// A data segment in the same shape as `Data`, but borrowing its contents
+/// from the module instead of copying them.
+#[derive(Serialize)]
+pub struct DataJson<'a> {
+    is_error: bool,
+    pub kind: DataKind,
+    pub data: &'a [u8],
+}
+
+/// A custom section in the same shape as `CustomSection`, but borrowing its
+/// contents from the module.
+#[derive(Serialize)]
+pub struct CustomSectionJson<'a> {
+    is_error: bool,
+    pub name: &'a str,
+    pub data: &'a [u8],
+}
+
+/// An item or the error that stopped it from being read, serialized as
+/// whichever it is.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum ItemJson<T> {
+    Ok(T),
+    Err(BinaryError),
+}
+
+/// Everything in a module. Each section's items are as returned by the
+/// corresponding `parse_*_section` function, except that function bodies
+/// include their operators.
+#[derive(Serialize)]
+pub struct ModuleJson<'a> {
+    pub schema_version: u32,
+    /// The version from the module header.
+    pub version: u16,
+    /// The sections in the order they appear in the module.
+    pub sections: Vec<SectionJson>,
+    pub types: Vec<TypeResult>,
+    pub imports: Vec<ImportResult>,
+    pub functions: Vec<FunctionResult>,
+    pub tables: Vec<TableResult>,
+    pub memories: Vec<MemoryResult>,
+    pub tags: Vec<TagTypeResult>,
+    pub globals: Vec<GlobalResult>,
+    pub exports: Vec<ExportResult>,
+    pub start: Option<u32>,
+    pub elements: Vec<ElementResult>,
+    pub data_count: Option<u32>,
+    pub bodies: Vec<FunctionBodyResult>,
+    pub data: Vec<ItemJson<DataJson<'a>>>,
+    pub customs: Vec<CustomSectionJson<'a>>,
+    pub names: Vec<NameResult>,
+    pub code_metadata: Vec<CodeMetadataSectionResult>,
+}
+
+/// The items of a section, or the error that stopped the section from being
+/// read as the only item.
+fn items<T>(result: Result<Vec<T>, BinaryError>, err: fn(BinaryError) -> T) -> Vec<T> {
+    result.unwrap_or_else(|e| vec![err(e)])
+}
+
+/// Reads the data segments, borrowing their contents from `data`.
+fn data_section(data: &[u8], offset: usize) -> Result<Vec<ItemJson<DataJson<'_>>>, BinaryError> {
+    let reader = DataSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => ItemJson::Ok(DataJson {
+                is_error: false,
+                kind: v.kind.into(),
+                data: v.data,
+            }),
+            Err(err) => ItemJson::Err(err.into()),
+        })
+        .collect();
+    Ok(results)
+}
+
+pub fn module_json(data: &[u8]) -> Result<ModuleJson<'_>, BinaryError> {
+    let mut module = ModuleJson {
+        schema_version: SCHEMA_VERSION,
+        version: 0,
+        sections: Vec::new(),
+        types: Vec::new(),
+        imports: Vec::new(),
+        functions: Vec::new(),
+        tables: Vec::new(),
+        memories: Vec::new(),
+        tags: Vec::new(),
+        globals: Vec::new(),
+        exports: Vec::new(),
+        start: None,
+        elements: Vec::new(),
+        data_count: None,
+        bodies: Vec::new(),
+        data: Vec::new(),
+        customs: Vec::new(),
+        names: Vec::new(),
+        code_metadata: Vec::new(),
+    };
+
+    for payload in Parser::new(0).parse_all(data) {
+        let (id, range) = match payload? {
+            Payload::Version { num, .. } => {
+                module.version = num;
+                continue;
+            }
+            Payload::TypeSection(reader) => (1, reader.range()),
+            Payload::ImportSection(reader) => (2, reader.range()),
+            Payload::FunctionSection(reader) => (3, reader.range()),
+            Payload::TableSection(reader) => (4, reader.range()),
+            Payload::MemorySection(reader) => (5, reader.range()),
+            Payload::GlobalSection(reader) => (6, reader.range()),
+            Payload::ExportSection(reader) => (7, reader.range()),
+            Payload::StartSection { func, range } => {
+                module.start = Some(func);
+                (8, range)
+            }
+            Payload::ElementSection(reader) => (9, reader.range()),
+            Payload::CodeSectionStart { range, .. } => (10, range),
+            Payload::DataSection(reader) => (11, reader.range()),
+            Payload::DataCountSection { count, range } => {
+                module.data_count = Some(count);
+                (12, range)
+            }
+            Payload::TagSection(reader) => (13, reader.range()),
+            Payload::CustomSection(reader) => (0, reader.range()),
+            Payload::UnknownSection { id, range, .. } => (id, range),
+            Payload::CodeSectionEntry(_) | Payload::End(_) => continue,
+            _ => {
+                return Err(BinaryError {
+                    message: "components are not supported".to_string(),
+                    offset: 0,
+                })
+            }
+        };
+
+        let contents = &data[range.clone()];
+        let offset = range.start;
+        match id {
+            0 => {
+                let reader = CustomSectionReader::new(contents, offset)?;
+                let custom = CustomSectionJson {
+                    is_error: false,
+                    name: reader.name(),
+                    data: reader.data(),
+                };
+                if custom.name == "name" {
+                    module
+                        .names
+                        .extend(sections::name_section(custom.data, reader.data_offset()));
+                } else if custom.name.starts_with(CODE_METADATA_PREFIX) {
+                    module.code_metadata.push(
+                        match sections::code_metadata_section(contents, offset) {
+                            Ok(section) => CodeMetadataSectionResult::Ok(section),
+                            Err(err) => CodeMetadataSectionResult::Err(err),
+                        },
+                    );
+                }
+                module.customs.push(custom);
+            }
+            1 => module.types = items(sections::type_section(contents, offset), TypeResult::Err),
+            2 => {
+                module.imports = items(
+                    sections::import_section(contents, offset),
+                    ImportResult::Err,
+                )
+            }
+            3 => {
+                module.functions = items(
+                    sections::function_section(contents, offset),
+                    FunctionResult::Err,
+                )
+            }
+            4 => module.tables = items(sections::table_section(contents, offset), TableResult::Err),
+            5 => {
+                module.memories = items(
+                    sections::memory_section(contents, offset),
+                    MemoryResult::Err,
+                )
+            }
+            6 => {
+                module.globals = items(
+                    sections::global_section(contents, offset),
+                    GlobalResult::Err,
+                )
+            }
+            7 => {
+                module.exports = items(
+                    sections::export_section(contents, offset),
+                    ExportResult::Err,
+                )
+            }
+            9 => {
+                module.elements = items(
+                    sections::element_section(contents, offset),
+                    ElementResult::Err,
+                )
+            }
+            10 => {
+                module.bodies = items(
+                    sections::code_section(contents, offset),
+                    FunctionBodyResult::Err,
+                );
+                for body in module.bodies.iter_mut() {
+                    if let FunctionBodyResult::Ok(body) = body {
+                        let range = body.range.start..body.range.end;
+                        body.ops = items(
+                            sections::function_body(&data[range.clone()], range.start),
+                            OperatorResult::Err,
+                        );
+                    }
+                }
+            }
+            11 => module.data = items(data_section(contents, offset), ItemJson::Err),
+            13 => module.tags = items(sections::tag_section(contents, offset), TagTypeResult::Err),
+            _ => {}
+        }
+        module.sections.push(SectionJson {
+            id,
+            range: range.into(),
+        });
+    }
+
+    Ok(module)
+}
+
+/// Serializes the whole module to JSON, following the schema described
+/// above.
+pub fn module_to_json(data: &[u8], pretty: bool) -> Result<String, BinaryError> {
+    to_json(&module_json(data)?, pretty)
+}
+
+/// Serializes anything following the schema described above.
+pub fn to_json<T: Serialize>(value: &T, pretty: bool) -> Result<String, BinaryError> {
+    let json = match pretty {
+        true => serde_json::to_string_pretty(value),
+        false => serde_json::to_string(value),
+    };
+    json.map_err(|err| BinaryError {
+        message: err.to_string(),
+        offset: 0,
+    })
+}
@@ -1,29 +1,59 @@
+use block_tree::BlockTree;
+use body_diff::{diff_bodies, OperatorDiffArray};
+use call_graph::CallGraph;
+use cfg::ControlFlowGraph;
+use dead_code::{find_dead_items, DeadItemArray};
+use decompile::{decompile, DecompiledLineArray};
+use diff::ModuleDiff;
+use duplicates::DuplicateReport;
+use encoding_lint::{find_encoding_issues, EncodingIssueArray};
+use json::module_to_json;
+use lint::{lint_rules, LintConfig, LintFindingArray, LintRuleArray};
+use module::ParsedModule;
+use module_index::ModuleIndex;
+use op_stats::ModuleOperatorStats;
+use parallel::{code_range_to_json, CodeRangeArray};
+use roundtrip::{check_round_trip, RoundTripReport};
+use size::SizeProfile;
+use stack_types::{operator_stacks, OperatorStackResultArray};
+use std::collections::HashMap;
 use types::*;
 use wasm_bindgen::prelude::*;
-use wasmparser::{
-    CodeSectionReader, CustomSectionReader, DataSectionReader, ElementSectionReader,
-    ExportSectionReader, FunctionBody as ParserFunctionBody, FunctionSectionReader,
-    GlobalSectionReader, ImportSectionReader, MemorySectionReader, NameSectionReader,
-    TableSectionReader, TypeSectionReader,
-};
+use wasmparser::{FunctionBody as ParserFunctionBody, ImportSectionReader};
+use writer::ModuleEdits;
+use xref::XRefIndex;
 
-mod names;
-mod types;
+mod block_tree;
+mod body_diff;
+mod call_graph;
+mod cfg;
+mod code_metadata;
+mod dead_code;
+mod decompile;
+mod diff;
+mod duplicates;
+mod encode;
+mod encoding_lint;
+pub mod json;
+mod lint;
+pub mod module;
+mod module_index;
+pub mod names;
+mod op_stats;
+mod parallel;
+mod refs;
+mod roundtrip;
+mod sections;
+mod size;
+mod stack_types;
+pub mod types;
+pub mod util;
+mod writer;
+mod xref;
 
 #[wasm_bindgen]
 pub fn parse_type_section(data: &[u8], offset: usize) -> Result<TypeResultArray, BinaryError> {
-    let reader = TypeSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter_with_offsets()
-        .map(|r| match r {
-            Ok((offset, v)) => TypeResult::Ok(Type {
-                t: v.into(),
-                offset: offset,
-            }),
-            Err(err) => TypeResult::Err(err.into()),
-        })
-        .collect::<Vec<TypeResult>>();
-    Ok(results.into())
+    Ok(sections::type_section(data, offset)?.into())
 }
 
 #[wasm_bindgen(getter_with_clone)]
@@ -34,17 +64,9 @@ pub struct ImportSection {
 
 #[wasm_bindgen]
 pub fn parse_import_section(data: &[u8], offset: usize) -> Result<ImportSection, BinaryError> {
-    let reader = ImportSectionReader::new(data, offset)?;
-    let range = reader.range();
-    let results = reader
-        .into_iter_with_offsets()
-        .map(|r| match r {
-            Ok((offset, v)) => ImportResult::Ok(Import::from_wasm(v, offset)),
-            Err(err) => ImportResult::Err(err.into()),
-        })
-        .collect::<Vec<ImportResult>>();
+    let range = ImportSectionReader::new(data, offset)?.range();
     Ok(ImportSection {
-        imports: results.into(),
+        imports: sections::import_section(data, offset)?.into(),
         range: range.into(),
     })
 }
@@ -54,76 +76,27 @@ pub fn parse_function_section(
     data: &[u8],
     offset: usize,
 ) -> Result<FunctionResultArray, BinaryError> {
-    let reader = FunctionSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter_with_offsets()
-        .map(|r| match r {
-            Ok((offset, v)) => FunctionResult::Ok(Function {
-                type_idx: v,
-                offset: offset,
-            }),
-            Err(err) => FunctionResult::Err(err.into()),
-        })
-        .collect::<Vec<FunctionResult>>();
-    Ok(results.into())
+    Ok(sections::function_section(data, offset)?.into())
 }
 
 #[wasm_bindgen]
 pub fn parse_table_section(data: &[u8], offset: usize) -> Result<TableResultArray, BinaryError> {
-    let reader = TableSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter_with_offsets()
-        .map(|r| match r {
-            Ok((offset, v)) => TableResult::Ok(Table::from_wasm(v, offset)),
-            Err(err) => TableResult::Err(err.into()),
-        })
-        .collect::<Vec<TableResult>>();
-    Ok(results.into())
+    Ok(sections::table_section(data, offset)?.into())
 }
 
 #[wasm_bindgen]
-pub fn parse_memory_section(
-    data: &[u8],
-    offset: usize,
-) -> Result<MemoryResultArray, BinaryError> {
-    let reader = MemorySectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter_with_offsets()
-        .map(|r| match r {
-            Ok((offset, v)) => MemoryResult::Ok(Memory {
-                t: v.into(),
-                offset: offset,
-            }),
-            Err(err) => MemoryResult::Err(err.into()),
-        })
-        .collect::<Vec<MemoryResult>>();
-    Ok(results.into())
+pub fn parse_memory_section(data: &[u8], offset: usize) -> Result<MemoryResultArray, BinaryError> {
+    Ok(sections::memory_section(data, offset)?.into())
 }
 
 #[wasm_bindgen]
 pub fn parse_global_section(data: &[u8], offset: usize) -> Result<GlobalResultArray, BinaryError> {
-    let reader = GlobalSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter_with_offsets()
-        .map(|r| match r {
-            Ok((offset, v)) => GlobalResult::Ok(Global::from_wasm(v, offset)),
-            Err(err) => GlobalResult::Err(err.into()),
-        })
-        .collect::<Vec<GlobalResult>>();
-    Ok(results.into())
+    Ok(sections::global_section(data, offset)?.into())
 }
 
 #[wasm_bindgen]
 pub fn parse_export_section(data: &[u8], offset: usize) -> Result<ExportResultArray, BinaryError> {
-    let reader = ExportSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter()
-        .map(|r| match r {
-            Ok(v) => ExportResult::Ok(v.into()),
-            Err(err) => ExportResult::Err(err.into()),
-        })
-        .collect::<Vec<ExportResult>>();
-    Ok(results.into())
+    Ok(sections::export_section(data, offset)?.into())
 }
 
 // The start section is parsed in JS.
@@ -133,15 +106,7 @@ pub fn parse_element_section(
     data: &[u8],
     offset: usize,
 ) -> Result<ElementResultArray, BinaryError> {
-    let reader = ElementSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter()
-        .map(|r| match r {
-            Ok(v) => ElementResult::Ok(v.into()),
-            Err(err) => ElementResult::Err(err.into()),
-        })
-        .collect::<Vec<ElementResult>>();
-    Ok(results.into())
+    Ok(sections::element_section(data, offset)?.into())
 }
 
 #[wasm_bindgen]
@@ -149,62 +114,366 @@ pub fn parse_code_section(
     data: &[u8],
     offset: usize,
 ) -> Result<FunctionBodyResultArray, BinaryError> {
-    let reader = CodeSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter()
-        .map(|r| match r {
-            Ok(v) => FunctionBodyResult::Ok(v.into()),
-            Err(err) => FunctionBodyResult::Err(err.into()),
-        })
-        .collect::<Vec<FunctionBodyResult>>();
-    Ok(results.into())
+    Ok(sections::code_section(data, offset)?.into())
 }
 
 #[wasm_bindgen]
 pub fn parse_function_body(data: &[u8], offset: usize) -> Result<OperatorResultArray, BinaryError> {
+    Ok(sections::function_body(data, offset)?.into())
+}
+
+/// Builds the control flow graph of a function body. Takes the same arguments
+/// as `parse_function_body`.
+#[wasm_bindgen]
+pub fn build_function_cfg(data: &[u8], offset: usize) -> Result<ControlFlowGraph, BinaryError> {
+    ControlFlowGraph::build(&ParserFunctionBody::new(offset, data))
+}
+
+/// Builds the tree of nested blocks in a function body, and resolves the
+/// target of every branch. Takes the same arguments as `parse_function_body`.
+#[wasm_bindgen]
+pub fn parse_block_tree(data: &[u8], offset: usize) -> Result<BlockTree, BinaryError> {
+    BlockTree::build(&ParserFunctionBody::new(offset, data))
+}
+
+/// Like `parse_function_body`, but also attaches the function's entries from
+/// a code metadata custom section (e.g. `metadata.code.branch_hint`) to the
+/// operators they annotate. `metadata` is the full contents of the custom
+/// section, as passed to `parse_code_metadata_section`.
+///
+/// Metadata that does not land on an instruction boundary is reported as an
+/// error at the end of the results.
+#[wasm_bindgen]
+pub fn parse_function_body_with_metadata(
+    data: &[u8],
+    offset: usize,
+    func_index: u32,
+    metadata: &[u8],
+    metadata_offset: usize,
+) -> Result<OperatorResultArray, BinaryError> {
+    let mut metadata_by_offset = HashMap::new();
+    for func in sections::code_metadata_reader(metadata, metadata_offset)? {
+        let func = func?;
+        if func.func_index != func_index {
+            continue;
+        }
+        for m in func.metadata {
+            let m = m?;
+            metadata_by_offset.insert(offset + m.offset as usize, m);
+        }
+    }
+
     let reader = ParserFunctionBody::new(offset, data);
-    let ops = reader
+    let mut ops = reader
         .get_operators_reader()?
-        .into_iter()
+        .into_iter_with_offsets()
         .map(|r| match r {
-            Ok(v) => OperatorResult::Ok(v.into()),
+            Ok((v, offset)) => {
+                let mut op = Operator::from_wasm(v, offset);
+                op.metadata = metadata_by_offset.remove(&offset).map(|m| m.into());
+                OperatorResult::Ok(op)
+            }
             Err(err) => OperatorResult::Err(err.into()),
         })
         .collect::<Vec<OperatorResult>>();
+
+    let mut unmatched = metadata_by_offset.into_values().collect::<Vec<_>>();
+    unmatched.sort_by_key(|m| m.offset);
+    ops.extend(unmatched.into_iter().map(|m| {
+        OperatorResult::Err(BinaryError {
+            message: format!(
+                "code metadata at function offset {} does not annotate an instruction",
+                m.offset
+            ),
+            offset: m.entry_offset,
+        })
+    }));
     Ok(ops.into())
 }
 
 #[wasm_bindgen]
 pub fn parse_data_section(data: &[u8], offset: usize) -> Result<DataResultArray, BinaryError> {
-    let reader = DataSectionReader::new(data, offset)?;
-    let results = reader
-        .into_iter()
-        .map(|r| match r {
-            Ok(v) => DataResult::Ok(v.into()),
-            Err(err) => DataResult::Err(err.into()),
-        })
-        .collect::<Vec<DataResult>>();
-    Ok(results.into())
+    Ok(sections::data_section(&data.into(), offset)?.into())
 }
 
 #[wasm_bindgen]
 pub fn parse_custom_section(data: &[u8], offset: usize) -> Result<CustomSection, BinaryError> {
-    let reader = CustomSectionReader::new(data, offset)?;
-    Ok(CustomSection {
-        name: reader.name().to_string(),
-        data: reader.data().to_vec(),
-    })
+    sections::custom_section(&data.into(), offset)
+}
+
+#[wasm_bindgen]
+pub fn parse_code_metadata_section(
+    data: &[u8],
+    offset: usize,
+) -> Result<CodeMetadataSection, BinaryError> {
+    sections::code_metadata_section(data, offset)
 }
 
 #[wasm_bindgen]
 pub fn parse_name_section(data: &[u8], offset: usize) -> NameResultArray {
-    let reader = NameSectionReader::new(data, offset);
-    let results = reader
-        .into_iter()
-        .map(|r| match r {
-            Ok(v) => NameResult::Ok(v.into()),
-            Err(err) => NameResult::Err(err.into()),
-        })
-        .collect::<Vec<NameResult>>();
-    results.into()
+    sections::name_section(data, offset).into()
+}
+
+// These are like the functions above, but return lists that keep the items in
+// Rust and only convert the ones JS asks for, which is much cheaper for large
+// sections where only some of the items are ever displayed.
+
+#[wasm_bindgen]
+pub fn parse_type_section_list(data: &[u8], offset: usize) -> Result<TypeResultList, BinaryError> {
+    Ok(sections::type_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_import_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<ImportResultList, BinaryError> {
+    Ok(sections::import_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_function_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<FunctionResultList, BinaryError> {
+    Ok(sections::function_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_table_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<TableResultList, BinaryError> {
+    Ok(sections::table_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_memory_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<MemoryResultList, BinaryError> {
+    Ok(sections::memory_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_global_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<GlobalResultList, BinaryError> {
+    Ok(sections::global_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_export_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<ExportResultList, BinaryError> {
+    Ok(sections::export_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_element_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<ElementResultList, BinaryError> {
+    Ok(sections::element_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_code_section_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<FunctionBodyResultList, BinaryError> {
+    Ok(sections::code_section(data, offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_function_body_list(
+    data: &[u8],
+    offset: usize,
+) -> Result<OperatorResultList, BinaryError> {
+    Ok(sections::function_body(data, offset)?.into())
+}
+
+/// The segments share one copy of the section, which `Data.data_view` gives
+/// access to without copying again.
+#[wasm_bindgen]
+pub fn parse_data_section_list(data: &[u8], offset: usize) -> Result<DataResultList, BinaryError> {
+    Ok(sections::data_section(&data.into(), offset)?.into())
+}
+
+#[wasm_bindgen]
+pub fn parse_name_section_list(data: &[u8], offset: usize) -> NameResultList {
+    sections::name_section(data, offset).into()
+}
+
+// Everything below works on the whole module at once, rather than a section at
+// a time, so `data` is the complete binary.
+
+#[wasm_bindgen]
+pub fn build_call_graph(data: &[u8]) -> Result<CallGraph, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    CallGraph::build(&module)
+}
+
+/// Finds the items that are unreachable from the module's exports and start
+/// function, along with their sizes.
+#[wasm_bindgen]
+pub fn find_dead_code(data: &[u8]) -> Result<DeadItemArray, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    Ok(find_dead_items(&module)?.into())
+}
+
+/// Profiles the size of every item in the module, including the retained
+/// size of each item through the dominator tree of the reference graph.
+#[wasm_bindgen]
+pub fn profile_size(data: &[u8]) -> Result<SizeProfile, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    SizeProfile::build(&module)
+}
+
+/// Finds the types on the operand stack before and after every operator in
+/// the body of the given function.
+#[wasm_bindgen]
+pub fn parse_operator_stacks(
+    data: &[u8],
+    func_index: u32,
+) -> Result<OperatorStackResultArray, BinaryError> {
+    Ok(operator_stacks(data, func_index)?.into())
+}
+
+/// Decompiles the body of the given function into C-like pseudo-code, with
+/// the offset of the operator each line comes from.
+#[wasm_bindgen]
+pub fn decompile_function(
+    data: &[u8],
+    func_index: u32,
+) -> Result<DecompiledLineArray, BinaryError> {
+    Ok(decompile(data, func_index)?.into())
+}
+
+/// Counts the operators in every function body, by opcode, category and
+/// proposal.
+#[wasm_bindgen]
+pub fn count_operators(data: &[u8]) -> Result<ModuleOperatorStats, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    ModuleOperatorStats::build(&module)
+}
+
+/// Compares two versions of a module, reporting the items that were added,
+/// removed or changed.
+#[wasm_bindgen]
+pub fn diff_modules(old: &[u8], new: &[u8]) -> Result<ModuleDiff, BinaryError> {
+    let old = ParsedModule::parse(old)?;
+    let new = ParsedModule::parse(new)?;
+    Ok(ModuleDiff::build(&old, &new))
+}
+
+/// Aligns the operators of a function in one module with those of a function
+/// in another, e.g. two builds of the same function.
+#[wasm_bindgen]
+pub fn diff_function_bodies(
+    old: &[u8],
+    old_func: u32,
+    new: &[u8],
+    new_func: u32,
+) -> Result<OperatorDiffArray, BinaryError> {
+    let old = ParsedModule::parse(old)?;
+    let new = ParsedModule::parse(new)?;
+    Ok(diff_bodies(&old, old_func, &new, new_func)?.into())
+}
+
+/// Indexes every use of every item in the module, for finding usages.
+#[wasm_bindgen]
+pub fn build_xref_index(data: &[u8]) -> Result<XRefIndex, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    XRefIndex::build(&module)
+}
+
+/// Resolves the function, table, memory, global and tag index spaces, so that
+/// items can be looked up by index whether they are imported or defined.
+#[wasm_bindgen]
+pub fn build_module_index(data: &[u8]) -> ModuleIndex {
+    ModuleIndex::read(data)
+}
+
+/// Finds groups of identical functions and clusters of similar ones. See
+/// `DuplicateReport::build` for what `abstract_indices` and `threshold` do.
+#[wasm_bindgen]
+pub fn find_duplicate_functions(
+    data: &[u8],
+    abstract_indices: bool,
+    threshold: f64,
+) -> Result<DuplicateReport, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    DuplicateReport::build(&module, abstract_indices, threshold)
+}
+
+/// Writes a copy of the module with the given edits applied.
+#[wasm_bindgen]
+pub fn edit_module(data: &[u8], edits: &ModuleEdits) -> Result<Vec<u8>, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    edits.apply(&module)
+}
+
+/// Re-encodes the module from its parsed representation and reports where,
+/// if anywhere, the result differs from the original.
+#[wasm_bindgen]
+pub fn round_trip_module(data: &[u8]) -> Result<RoundTripReport, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    check_round_trip(&module)
+}
+
+/// Lists every part of the module that is encoded in more bytes than
+/// necessary, such as padded LEB128 numbers.
+#[wasm_bindgen]
+pub fn lint_encoding(data: &[u8]) -> Result<EncodingIssueArray, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    Ok(find_encoding_issues(&module)?.into())
+}
+
+/// Runs the lint rules over the module. Pass a `LintConfig` to change the
+/// rules' severities or turn them off.
+#[wasm_bindgen]
+pub fn lint_module(data: &[u8], config: &LintConfig) -> Result<LintFindingArray, BinaryError> {
+    let module = ParsedModule::parse(data)?;
+    Ok(config.run(&module)?.into())
+}
+
+/// Lists the rules `lint_module` can run.
+#[wasm_bindgen]
+pub fn list_lint_rules() -> LintRuleArray {
+    lint_rules().into()
+}
+
+/// Serializes the whole module to JSON, with every section's items in the same
+/// shape as their TypeScript definitions and a `schema_version` at the top.
+#[wasm_bindgen]
+pub fn module_json(data: &[u8]) -> Result<String, BinaryError> {
+    module_to_json(data, false)
+}
+
+/// Splits the code section into at most `parts` ranges of whole function
+/// bodies with about the same number of bytes, so that each range can be
+/// decoded by `decode_code_range` in a separate web worker.
+#[wasm_bindgen]
+pub fn partition_code_section(
+    data: &[u8],
+    offset: usize,
+    parts: u32,
+) -> Result<CodeRangeArray, BinaryError> {
+    Ok(parallel::partition_code_section(data, offset, parts)?.into())
+}
+
+/// Decodes the function bodies in a range from `partition_code_section`,
+/// given just the bytes from its `start` to its `end`. The result is JSON,
+/// since objects from this module can't be posted between workers; its
+/// `bodies` each have a `body_index` to merge them by.
+#[wasm_bindgen]
+pub fn decode_code_range(
+    data: &[u8],
+    offset: usize,
+    first_body: u32,
+) -> Result<String, BinaryError> {
+    code_range_to_json(data, offset, first_body)
 }
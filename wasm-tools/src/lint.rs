@@ -0,0 +1,410 @@
+//! Lints for items that waste space or look like mistakes, such as unused
+//! imports or mutable globals that are never written.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use wasm_bindgen::prelude::*;
+use wasmparser::{DataKind, Operator};
+
+use crate::module::{ItemKind, ItemRef, ParsedModule};
+use crate::refs::{references, roots};
+use crate::types::BinaryError;
+
+/// A problem found by a lint rule.
+#[wasmtools_struct]
+pub struct LintFinding {
+    /// The id of the rule that found the problem, e.g. `unused_import`.
+    pub rule: String,
+    /// `error`, `warning` or `info`.
+    pub severity: String,
+    pub message: String,
+    /// The kind of item the problem is with, such as `func` or `custom`.
+    pub item_kind: String,
+    pub item_name: String,
+    /// The bytes to highlight for the problem.
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A lint rule that can be configured.
+#[wasmtools_struct]
+pub struct LintRule {
+    pub id: String,
+    pub description: String,
+    pub default_severity: String,
+}
+
+const SEVERITIES: [&str; 4] = ["error", "warning", "info", "off"];
+
+/// What a rule reports, before the rule's severity is applied.
+struct Problem {
+    message: String,
+    item_kind: String,
+    item_name: String,
+    range: Range<usize>,
+}
+
+impl Problem {
+    fn item(module: &ParsedModule, item: ItemRef, message: String) -> Problem {
+        Problem {
+            message,
+            item_kind: item.kind.name().to_string(),
+            item_name: module.display_name(item),
+            range: module.item_range(item).unwrap_or_default(),
+        }
+    }
+}
+
+/// What the rules need to know about the module beyond what `ParsedModule`
+/// has, computed once for all of them.
+struct Context<'m, 'a> {
+    module: &'m ParsedModule<'a>,
+    /// Every item that some other item, export or the start function refers
+    /// to.
+    used: HashSet<ItemRef>,
+    /// The globals that some `global.set` writes to.
+    written_globals: HashSet<u32>,
+}
+
+impl<'m, 'a> Context<'m, 'a> {
+    fn new(module: &'m ParsedModule<'a>) -> Result<Context<'m, 'a>, BinaryError> {
+        let mut used: HashSet<ItemRef> = roots(module).into_iter().collect();
+        let mut written_globals = HashSet::new();
+        for item in module.items() {
+            for r in references(module, item)? {
+                if r.target != item {
+                    used.insert(r.target);
+                }
+            }
+        }
+        for body in module.bodies.iter() {
+            for op in body.get_operators_reader()? {
+                if let Operator::GlobalSet { global_index } = op? {
+                    written_globals.insert(global_index);
+                }
+            }
+        }
+        Ok(Context {
+            module,
+            used,
+            written_globals,
+        })
+    }
+}
+
+type Check = fn(&Context, &mut Vec<Problem>) -> Result<(), BinaryError>;
+
+struct Rule {
+    id: &'static str,
+    description: &'static str,
+    severity: &'static str,
+    check: Check,
+}
+
+const RULES: [Rule; 8] = [
+    Rule {
+        id: "unused_type",
+        description: "A type that nothing refers to.",
+        severity: "warning",
+        check: unused_types,
+    },
+    Rule {
+        id: "duplicate_func_type",
+        description: "A function type that is identical to an earlier one.",
+        severity: "warning",
+        check: duplicate_func_types,
+    },
+    Rule {
+        id: "unused_import",
+        description: "An import that nothing refers to.",
+        severity: "warning",
+        check: unused_imports,
+    },
+    Rule {
+        id: "mutable_global_never_written",
+        description: "A mutable global that isn't exported and that no function writes to.",
+        severity: "info",
+        check: unwritten_globals,
+    },
+    Rule {
+        id: "export_traps",
+        description: "An exported function whose first instruction is `unreachable`.",
+        severity: "warning",
+        check: trapping_exports,
+    },
+    Rule {
+        id: "zero_filled_data",
+        description: "A data segment that only writes zeros to a defined, zero-filled memory.",
+        severity: "warning",
+        check: zero_filled_data,
+    },
+    Rule {
+        id: "empty_custom_section",
+        description: "A custom section with a name but no contents.",
+        severity: "info",
+        check: empty_custom_sections,
+    },
+    Rule {
+        id: "start_calls_import",
+        description: "A start function that calls into the host before the module is \
+                      fully instantiated.",
+        severity: "warning",
+        check: start_calls_import,
+    },
+];
+
+fn unused_types(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    for index in 0..cx.module.types.len() as u32 {
+        let item = ItemRef::new(ItemKind::Type, index);
+        if !cx.used.contains(&item) {
+            let message = format!("type {} is never used", cx.module.display_name(item));
+            out.push(Problem::item(cx.module, item, message));
+        }
+    }
+    Ok(())
+}
+
+fn duplicate_func_types(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    let mut first = HashMap::new();
+    for (index, ty) in cx.module.types.iter().enumerate() {
+        let index = index as u32;
+        let first = *first.entry(&ty.value).or_insert(index);
+        if first != index {
+            let item = ItemRef::new(ItemKind::Type, index);
+            let first = ItemRef::new(ItemKind::Type, first);
+            let message = format!(
+                "type {} is the same as type {}",
+                cx.module.display_name(item),
+                cx.module.display_name(first)
+            );
+            out.push(Problem::item(cx.module, item, message));
+        }
+    }
+    Ok(())
+}
+
+fn unused_imports(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    for item in cx.module.items() {
+        if let Some(import) = cx.module.import_of(item) {
+            if !cx.used.contains(&item) {
+                let message = format!(
+                    "{} {}.{} is imported but never used",
+                    item.kind.name(),
+                    import.value.module,
+                    import.value.name
+                );
+                out.push(Problem::item(cx.module, item, message));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn unwritten_globals(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    let module = cx.module;
+    let exported: HashSet<ItemRef> = roots(module).into_iter().collect();
+    for (i, global) in module.globals.iter().enumerate() {
+        let index = module.num_imported_globals() + i as u32;
+        let item = ItemRef::new(ItemKind::Global, index);
+        if global.value.ty.mutable
+            && !exported.contains(&item)
+            && !cx.written_globals.contains(&index)
+        {
+            let message = format!(
+                "global {} is mutable but never written",
+                module.display_name(item)
+            );
+            out.push(Problem::item(module, item, message));
+        }
+    }
+    Ok(())
+}
+
+fn trapping_exports(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    let module = cx.module;
+    for export in module.exports.iter() {
+        let item = ItemRef::new(export.value.kind.into(), export.value.index);
+        let body = match item.kind {
+            ItemKind::Func => module.func_body(item.index),
+            _ => None,
+        };
+        let body = match body {
+            Some(body) => body,
+            None => continue,
+        };
+        let first = body.get_operators_reader()?.into_iter_with_offsets().next();
+        if let Some((Operator::Unreachable, offset)) = first.transpose()? {
+            out.push(Problem {
+                message: format!(
+                    "exported function {} traps as soon as it is called",
+                    export.value.name
+                ),
+                item_kind: "func".to_string(),
+                item_name: module.display_name(item),
+                range: offset..offset + 1,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn zero_filled_data(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    let module = cx.module;
+    for (index, data) in module.datas.iter().enumerate() {
+        // Imported memories may already hold something else, and passive
+        // segments may be copied anywhere, so zeros can matter there.
+        match data.value.kind {
+            DataKind::Active { memory_index, .. }
+                if memory_index >= module.num_imported(ItemKind::Memory) => {}
+            _ => continue,
+        }
+        let bytes = data.value.data;
+        if !bytes.is_empty() && bytes.iter().all(|&b| b == 0) {
+            let item = ItemRef::new(ItemKind::Data, index as u32);
+            let message = format!(
+                "data segment {} only contains {} zero bytes",
+                module.display_name(item),
+                bytes.len()
+            );
+            out.push(Problem::item(module, item, message));
+        }
+    }
+    Ok(())
+}
+
+fn empty_custom_sections(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    for custom in cx.module.customs.iter() {
+        if custom.data().is_empty() {
+            out.push(Problem {
+                message: format!("custom section {:?} is empty", custom.name()),
+                item_kind: "custom".to_string(),
+                item_name: custom.name().to_string(),
+                range: custom.range(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn start_calls_import(cx: &Context, out: &mut Vec<Problem>) -> Result<(), BinaryError> {
+    let module = cx.module;
+    let start = match &module.start {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    let item = ItemRef::new(ItemKind::Func, start.value);
+    let name = module.display_name(item);
+    if start.value < module.num_imported_funcs() {
+        out.push(Problem {
+            message: format!("the start function {} is imported", name),
+            item_kind: "func".to_string(),
+            item_name: name,
+            range: start.range.clone(),
+        });
+        return Ok(());
+    }
+    let body = match module.func_body(start.value) {
+        Some(body) => body,
+        None => return Ok(()),
+    };
+    for op in body.get_operators_reader()?.into_iter_with_offsets() {
+        let (op, offset) = op?;
+        let callee = match op {
+            Operator::Call { function_index } | Operator::ReturnCall { function_index } => {
+                function_index
+            }
+            _ => continue,
+        };
+        if callee < module.num_imported_funcs() {
+            let callee = ItemRef::new(ItemKind::Func, callee);
+            out.push(Problem {
+                message: format!(
+                    "the start function {} calls the imported function {}",
+                    name,
+                    module.display_name(callee)
+                ),
+                item_kind: "func".to_string(),
+                item_name: name.clone(),
+                range: offset..offset + 1,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Which rules to run, and how severe their findings are. Every rule runs
+/// with its default severity unless configured otherwise.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct LintConfig {
+    severities: HashMap<String, String>,
+}
+
+impl LintConfig {
+    /// Runs every rule that isn't turned off, returning the findings in rule
+    /// order.
+    pub fn run(&self, module: &ParsedModule) -> Result<Vec<LintFinding>, BinaryError> {
+        let cx = Context::new(module)?;
+        let mut findings = Vec::new();
+        for rule in RULES.iter() {
+            let severity = self.severities.get(rule.id).map_or(rule.severity, |s| s);
+            if severity == "off" {
+                continue;
+            }
+            let mut problems = Vec::new();
+            (rule.check)(&cx, &mut problems)?;
+            findings.extend(problems.into_iter().map(|p| LintFinding {
+                rule: rule.id.to_string(),
+                severity: severity.to_string(),
+                message: p.message,
+                item_kind: p.item_kind,
+                item_name: p.item_name,
+                start: p.range.start,
+                end: p.range.end,
+            }));
+        }
+        Ok(findings)
+    }
+}
+
+#[wasm_bindgen]
+impl LintConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> LintConfig {
+        LintConfig::default()
+    }
+
+    /// Changes the severity of a rule's findings to `error`, `warning` or
+    /// `info`, or turns the rule off with `off`.
+    pub fn set_severity(&mut self, rule: &str, severity: &str) -> Result<(), BinaryError> {
+        if !RULES.iter().any(|r| r.id == rule) {
+            return Err(BinaryError {
+                message: format!("unknown lint rule {}", rule),
+                offset: 0,
+            });
+        }
+        if !SEVERITIES.contains(&severity) {
+            return Err(BinaryError {
+                message: format!("unknown severity {}", severity),
+                offset: 0,
+            });
+        }
+        self.severities
+            .insert(rule.to_string(), severity.to_string());
+        Ok(())
+    }
+}
+
+/// Every lint rule, with its default severity.
+pub fn lint_rules() -> Vec<LintRule> {
+    RULES
+        .iter()
+        .map(|r| LintRule {
+            id: r.id.to_string(),
+            description: r.description.to_string(),
+            default_severity: r.severity.to_string(),
+        })
+        .collect()
+}
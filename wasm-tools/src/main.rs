@@ -0,0 +1,284 @@
+//! A command-line interface to the viewer's decoding logic, for inspecting
+//! modules from scripts and on build servers.
+//!
+//! This only uses the parts of the library that don't need JS, chiefly
+//! `ParsedModule`.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use wasm_viewer::json::module_to_json;
+use wasm_viewer::module::{ItemKind, ItemRef, ParsedModule};
+use wasm_viewer::names::op_name;
+use wasm_viewer::types::BinaryError;
+use wasm_viewer::util::wasm_features;
+use wasmparser::{FuncType, HeapType, RefType, TypeRef, ValType, Validator};
+
+const USAGE: &str = "\
+usage: wasm-viewer <command> <file> [options]
+
+Reads the module from standard input if <file> is `-`.
+
+commands:
+  sections    list the sections with their offsets and sizes
+  imports     list the imports with their types
+  exports     list the exports and the items they refer to
+  funcs       list the functions with their types and bodies
+  dump        disassemble the function bodies, or just one with
+              `--func <index>`
+  validate    check that the module is valid, exiting with status 1 if not
+  json        print the whole module as JSON
+";
+
+fn error_message(err: BinaryError) -> String {
+    format!("{} (at offset {:#x})", err.message, err.offset)
+}
+
+fn ref_type(ty: RefType) -> String {
+    let heap_type = match ty.heap_type() {
+        HeapType::Func => return "funcref".to_string(),
+        HeapType::Extern => return "externref".to_string(),
+        HeapType::TypedFunc(index) => index.to_string(),
+        heap_type => format!("{:?}", heap_type).to_lowercase(),
+    };
+    let null = if ty.is_nullable() { "null " } else { "" };
+    format!("(ref {}{})", null, heap_type)
+}
+
+fn val_type(ty: ValType) -> String {
+    match ty {
+        ValType::I32 => "i32".to_string(),
+        ValType::I64 => "i64".to_string(),
+        ValType::F32 => "f32".to_string(),
+        ValType::F64 => "f64".to_string(),
+        ValType::V128 => "v128".to_string(),
+        ValType::Ref(r) => ref_type(r),
+    }
+}
+
+/// Shows a function type as e.g. `(i32, i32) -> i64`.
+fn func_type(ty: &FuncType) -> String {
+    let list = |types: &[ValType]| {
+        let types: Vec<String> = types.iter().map(|&t| val_type(t)).collect();
+        types.join(", ")
+    };
+    match ty.results() {
+        [result] => format!("({}) -> {}", list(ty.params()), val_type(*result)),
+        results => format!("({}) -> ({})", list(ty.params()), list(results)),
+    }
+}
+
+fn type_index(module: &ParsedModule, index: u32) -> String {
+    match module.types.get(index as usize) {
+        Some(ty) => func_type(&ty.value),
+        None => format!("type[{}]", index),
+    }
+}
+
+fn limits(initial: u64, maximum: Option<u64>) -> String {
+    match maximum {
+        Some(maximum) => format!("{}..{}", initial, maximum),
+        None => format!("{}..", initial),
+    }
+}
+
+fn import_type(module: &ParsedModule, ty: TypeRef) -> String {
+    match ty {
+        TypeRef::Func(index) => type_index(module, index),
+        TypeRef::Table(t) => format!(
+            "{} {}",
+            ref_type(t.element_type),
+            limits(t.initial.into(), t.maximum.map(u64::from))
+        ),
+        TypeRef::Memory(m) => {
+            let mut text = format!("{} pages", limits(m.initial, m.maximum));
+            if m.memory64 {
+                text.push_str(" i64");
+            }
+            if m.shared {
+                text.push_str(" shared");
+            }
+            text
+        }
+        TypeRef::Global(g) => match g.mutable {
+            true => format!("mut {}", val_type(g.content_type)),
+            false => val_type(g.content_type),
+        },
+        TypeRef::Tag(t) => type_index(module, t.func_type_idx),
+    }
+}
+
+fn sections(module: &ParsedModule) {
+    let mut customs = module.customs.iter();
+    for section in module.sections.iter() {
+        let range = &section.range;
+        let mut line = format!(
+            "{:>2} {:<9} {:#010x}..{:#010x} {:>9} bytes",
+            section.id,
+            section.name(),
+            range.start,
+            range.end,
+            range.len()
+        );
+        if section.id == 0 {
+            if let Some(custom) = customs.next() {
+                line.push_str(&format!(" {:?}", custom.name()));
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+fn imports(module: &ParsedModule) {
+    let mut counts = [0u32; 5];
+    for import in module.imports.iter() {
+        let (kind, count) = match import.value.ty {
+            TypeRef::Func(_) => (ItemKind::Func, &mut counts[0]),
+            TypeRef::Table(_) => (ItemKind::Table, &mut counts[1]),
+            TypeRef::Memory(_) => (ItemKind::Memory, &mut counts[2]),
+            TypeRef::Global(_) => (ItemKind::Global, &mut counts[3]),
+            TypeRef::Tag(_) => (ItemKind::Tag, &mut counts[4]),
+        };
+        println!(
+            "{}[{}] {}.{} {}",
+            kind.name(),
+            count,
+            import.value.module,
+            import.value.name,
+            import_type(module, import.value.ty)
+        );
+        *count += 1;
+    }
+}
+
+fn exports(module: &ParsedModule) {
+    for export in module.exports.iter() {
+        let item = ItemRef::new(export.value.kind.into(), export.value.index);
+        println!(
+            "{} {}[{}] {}",
+            export.value.name,
+            item.kind.name(),
+            item.index,
+            module.display_name(item)
+        );
+    }
+}
+
+fn funcs(module: &ParsedModule) {
+    for index in 0..module.num_funcs() {
+        let ty = match module.func_type(index) {
+            Some(ty) => func_type(ty),
+            None => "?".to_string(),
+        };
+        let location = match module.func_body(index) {
+            Some(body) => {
+                let range = body.range();
+                format!("body {:#010x} {} bytes", range.start, range.len())
+            }
+            None => "import".to_string(),
+        };
+        println!(
+            "func[{}] {} {} {}",
+            index,
+            module.func_display_name(index),
+            ty,
+            location
+        );
+    }
+}
+
+fn dump(module: &ParsedModule, func: Option<u32>) -> Result<(), String> {
+    let first = module.num_imported_funcs();
+    let indices = match func {
+        Some(index) if module.func_body(index).is_none() => {
+            return Err(format!("function {} has no body", index));
+        }
+        Some(index) => index..index + 1,
+        None => first..module.num_funcs(),
+    };
+    for index in indices {
+        let body = module.func_body(index).unwrap();
+        println!("func[{}] {}:", index, module.func_display_name(index));
+        let reader = body
+            .get_operators_reader()
+            .map_err(|e| error_message(e.into()))?;
+        for op in reader.into_iter_with_offsets() {
+            let (op, offset) = op.map_err(|e| error_message(e.into()))?;
+            println!(" {:#010x}: {}", offset, op_name(&op));
+        }
+    }
+    Ok(())
+}
+
+fn validate(data: &[u8]) -> ExitCode {
+    let mut validator = Validator::new_with_features(wasm_features());
+    match validator.validate_all(data) {
+        Ok(_) => {
+            println!("valid");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            println!("invalid: {}", error_message(err.into()));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let result = match path {
+        "-" => io::stdin().read_to_end(&mut data).map(|_| ()),
+        path => fs::read(path).map(|d| data = d),
+    };
+    result.map_err(|e| format!("can't read {}: {}", path, e))?;
+    Ok(data)
+}
+
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    let (command, path, options) = match args {
+        [command, path, options @ ..] => (command.as_str(), path.as_str(), options),
+        _ => return Err(USAGE.to_string()),
+    };
+    let func = match options {
+        [] => None,
+        [flag, index] if command == "dump" && flag == "--func" => {
+            let index = index
+                .parse()
+                .map_err(|_| format!("invalid function index {}", index))?;
+            Some(index)
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let data = read_input(path)?;
+    if command == "validate" {
+        return Ok(validate(&data));
+    }
+    if command == "json" {
+        println!("{}", module_to_json(&data, true).map_err(error_message)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+    let module = ParsedModule::parse(&data).map_err(error_message)?;
+    match command {
+        "sections" => sections(&module),
+        "imports" => imports(&module),
+        "exports" => exports(&module),
+        "funcs" => funcs(&module),
+        "dump" => dump(&module, func)?,
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("{}", message.trim_end());
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,628 @@
+//! A whole-module view over a wasm binary, for analyses that need to look
+//! across sections (call graphs, reachability, etc.).
+//!
+//! Unlike the `parse_*_section` functions, which convert each section into JS
+//! objects as the viewer streams the file in, this keeps wasmparser's types
+//! and borrows from the module bytes, so it works the same outside of JS.
+
+use crate::types::{BinaryError, NAME_SUBSECTION_FIELD, NAME_SUBSECTION_TAG};
+use std::collections::HashMap;
+use std::ops::Range;
+use wasmparser::{
+    CustomSectionReader, Data, Element, Export, ExternalKind, FuncType, FunctionBody, Global,
+    Import, MemoryType, Name, NameSectionReader, Parser, Payload, Result, SectionLimited, Table,
+    TagType, Type, TypeRef,
+};
+
+/// An item from one of the module's sections, along with the range of bytes
+/// it was decoded from.
+pub struct Item<T> {
+    pub value: T,
+    pub range: Range<usize>,
+}
+
+/// A section of the module. `range` covers the section's contents, not
+/// including the section id and size.
+#[derive(Debug, Clone)]
+pub struct SectionInfo {
+    pub id: u8,
+    pub range: Range<usize>,
+}
+
+impl SectionInfo {
+    /// The section's name, e.g. `type` or `datacount`, or `unknown` for ids
+    /// that aren't defined by the spec or a supported proposal.
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            0 => "custom",
+            1 => "type",
+            2 => "import",
+            3 => "function",
+            4 => "table",
+            5 => "memory",
+            6 => "global",
+            7 => "export",
+            8 => "start",
+            9 => "element",
+            10 => "code",
+            11 => "data",
+            12 => "datacount",
+            13 => "tag",
+            _ => "unknown",
+        }
+    }
+}
+
+/// The kinds of things a module can refer to by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ItemKind {
+    Type,
+    Func,
+    Table,
+    Memory,
+    Global,
+    Tag,
+    Element,
+    Data,
+    /// A local (or parameter) of a function. Local indices are relative to the
+    /// function they appear in.
+    Local,
+}
+
+impl ItemKind {
+    /// Every kind with a module-wide index space, i.e. all but `Local`.
+    pub const MODULE_LEVEL: [ItemKind; 8] = [
+        ItemKind::Type,
+        ItemKind::Func,
+        ItemKind::Table,
+        ItemKind::Memory,
+        ItemKind::Global,
+        ItemKind::Tag,
+        ItemKind::Element,
+        ItemKind::Data,
+    ];
+
+    /// The name used for this kind in JS, matching `ExternalKind`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemKind::Type => "type",
+            ItemKind::Func => "func",
+            ItemKind::Table => "table",
+            ItemKind::Memory => "memory",
+            ItemKind::Global => "global",
+            ItemKind::Tag => "tag",
+            ItemKind::Element => "element",
+            ItemKind::Data => "data",
+            ItemKind::Local => "local",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ItemKind> {
+        Some(match name {
+            "type" => ItemKind::Type,
+            "func" => ItemKind::Func,
+            "table" => ItemKind::Table,
+            "memory" => ItemKind::Memory,
+            "global" => ItemKind::Global,
+            "tag" => ItemKind::Tag,
+            "element" => ItemKind::Element,
+            "data" => ItemKind::Data,
+            "local" => ItemKind::Local,
+            _ => return None,
+        })
+    }
+}
+
+impl From<ExternalKind> for ItemKind {
+    fn from(value: ExternalKind) -> Self {
+        match value {
+            ExternalKind::Func => ItemKind::Func,
+            ExternalKind::Table => ItemKind::Table,
+            ExternalKind::Memory => ItemKind::Memory,
+            ExternalKind::Global => ItemKind::Global,
+            ExternalKind::Tag => ItemKind::Tag,
+        }
+    }
+}
+
+/// A reference to an item in one of the module's index spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ItemRef {
+    pub kind: ItemKind,
+    pub index: u32,
+}
+
+impl ItemRef {
+    pub fn new(kind: ItemKind, index: u32) -> Self {
+        ItemRef { kind, index }
+    }
+}
+
+/// The names from the name section, indexed the same way as the module.
+#[derive(Debug, Default)]
+pub struct Names<'a> {
+    pub module: Option<&'a str>,
+    pub functions: HashMap<u32, &'a str>,
+    pub locals: HashMap<u32, HashMap<u32, &'a str>>,
+    pub types: HashMap<u32, &'a str>,
+    pub tables: HashMap<u32, &'a str>,
+    pub memories: HashMap<u32, &'a str>,
+    pub globals: HashMap<u32, &'a str>,
+    pub elements: HashMap<u32, &'a str>,
+    pub datas: HashMap<u32, &'a str>,
+    /// Field names, by type index and then field index.
+    pub fields: HashMap<u32, HashMap<u32, &'a str>>,
+    pub tags: HashMap<u32, &'a str>,
+}
+
+impl<'a> Names<'a> {
+    /// Reads the names from a name section, keeping the ones read before an
+    /// error if there is one.
+    pub fn read(&mut self, reader: &CustomSectionReader<'a>) -> Result<()> {
+        fn name_map<'a>(
+            map: &mut HashMap<u32, &'a str>,
+            names: SectionLimited<'a, wasmparser::Naming<'a>>,
+        ) -> Result<()> {
+            for naming in names {
+                let naming = naming?;
+                map.insert(naming.index, naming.name);
+            }
+            Ok(())
+        }
+
+        fn indirect_name_map<'a>(
+            map: &mut HashMap<u32, HashMap<u32, &'a str>>,
+            names: SectionLimited<'a, wasmparser::IndirectNaming<'a>>,
+        ) -> Result<()> {
+            for indirect in names {
+                let indirect = indirect?;
+                name_map(map.entry(indirect.index).or_default(), indirect.names)?;
+            }
+            Ok(())
+        }
+
+        for name in NameSectionReader::new(reader.data(), reader.data_offset()) {
+            match name? {
+                Name::Module { name, .. } => self.module = Some(name),
+                Name::Function(m) => name_map(&mut self.functions, m)?,
+                Name::Local(m) => indirect_name_map(&mut self.locals, m)?,
+                Name::Type(m) => name_map(&mut self.types, m)?,
+                Name::Table(m) => name_map(&mut self.tables, m)?,
+                Name::Memory(m) => name_map(&mut self.memories, m)?,
+                Name::Global(m) => name_map(&mut self.globals, m)?,
+                Name::Element(m) => name_map(&mut self.elements, m)?,
+                Name::Data(m) => name_map(&mut self.datas, m)?,
+                // See `Name` in types.rs for why these come through as unknown.
+                Name::Unknown {
+                    ty: NAME_SUBSECTION_FIELD,
+                    data,
+                    range,
+                } => indirect_name_map(&mut self.fields, SectionLimited::new(data, range.start)?)?,
+                Name::Unknown {
+                    ty: NAME_SUBSECTION_TAG,
+                    data,
+                    range,
+                } => name_map(&mut self.tags, SectionLimited::new(data, range.start)?)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ParsedModule<'a> {
+    /// The complete module binary.
+    pub data: &'a [u8],
+    pub sections: Vec<SectionInfo>,
+    pub types: Vec<Item<FuncType>>,
+    pub imports: Vec<Item<Import<'a>>>,
+    /// The type index of each function defined in this module.
+    pub functions: Vec<Item<u32>>,
+    pub tables: Vec<Item<Table<'a>>>,
+    pub memories: Vec<Item<MemoryType>>,
+    pub tags: Vec<Item<TagType>>,
+    pub globals: Vec<Item<Global<'a>>>,
+    pub exports: Vec<Item<Export<'a>>>,
+    pub start: Option<Item<u32>>,
+    pub elements: Vec<Item<Element<'a>>>,
+    pub bodies: Vec<FunctionBody<'a>>,
+    pub datas: Vec<Item<Data<'a>>>,
+    pub customs: Vec<CustomSectionReader<'a>>,
+    pub names: Names<'a>,
+    /// The error that stopped the name section from being read completely,
+    /// if any. `names` still holds the names read before it.
+    pub names_error: Option<BinaryError>,
+
+    /// Indices into `imports` of the imports of each kind, in order.
+    imported_funcs: Vec<usize>,
+    imported_tables: Vec<usize>,
+    imported_memories: Vec<usize>,
+    imported_globals: Vec<usize>,
+    imported_tags: Vec<usize>,
+}
+
+/// Reads every item out of a section, recording the byte range each one came
+/// from. Items end where the next one begins, or at the end of the section.
+fn read_items<'a, T, U>(
+    reader: SectionLimited<'a, T>,
+    mut f: impl FnMut(T) -> U,
+) -> Result<Vec<Item<U>>>
+where
+    T: wasmparser::FromReader<'a>,
+{
+    let end = reader.range().end;
+    let mut items: Vec<Item<U>> = Vec::new();
+    for r in reader.into_iter_with_offsets() {
+        let (offset, value) = r?;
+        if let Some(prev) = items.last_mut() {
+            prev.range.end = offset;
+        }
+        items.push(Item {
+            value: f(value),
+            range: offset..end,
+        });
+    }
+    Ok(items)
+}
+
+impl<'a> ParsedModule<'a> {
+    /// Decodes an entire module. Unlike the section parsers, this stops at the
+    /// first error, since the analyses built on top of it need a complete
+    /// picture of the module.
+    pub fn parse(data: &'a [u8]) -> Result<ParsedModule<'a>, BinaryError> {
+        let mut module = ParsedModule {
+            data,
+            sections: Vec::new(),
+            types: Vec::new(),
+            imports: Vec::new(),
+            functions: Vec::new(),
+            tables: Vec::new(),
+            memories: Vec::new(),
+            tags: Vec::new(),
+            globals: Vec::new(),
+            exports: Vec::new(),
+            start: None,
+            elements: Vec::new(),
+            bodies: Vec::new(),
+            datas: Vec::new(),
+            customs: Vec::new(),
+            names: Names::default(),
+            names_error: None,
+            imported_funcs: Vec::new(),
+            imported_tables: Vec::new(),
+            imported_memories: Vec::new(),
+            imported_globals: Vec::new(),
+            imported_tags: Vec::new(),
+        };
+
+        for payload in Parser::new(0).parse_all(data) {
+            let (id, range) = match payload? {
+                Payload::TypeSection(reader) => {
+                    let range = reader.range();
+                    module.types = read_items(reader, |t| match t {
+                        Type::Func(f) => f,
+                    })?;
+                    (1, range)
+                }
+                Payload::ImportSection(reader) => {
+                    let range = reader.range();
+                    module.imports = read_items(reader, |i| i)?;
+                    for (i, import) in module.imports.iter().enumerate() {
+                        match import.value.ty {
+                            TypeRef::Func(_) => module.imported_funcs.push(i),
+                            TypeRef::Table(_) => module.imported_tables.push(i),
+                            TypeRef::Memory(_) => module.imported_memories.push(i),
+                            TypeRef::Global(_) => module.imported_globals.push(i),
+                            TypeRef::Tag(_) => module.imported_tags.push(i),
+                        }
+                    }
+                    (2, range)
+                }
+                Payload::FunctionSection(reader) => {
+                    let range = reader.range();
+                    module.functions = read_items(reader, |f| f)?;
+                    (3, range)
+                }
+                Payload::TableSection(reader) => {
+                    let range = reader.range();
+                    module.tables = read_items(reader, |t| t)?;
+                    (4, range)
+                }
+                Payload::MemorySection(reader) => {
+                    let range = reader.range();
+                    module.memories = read_items(reader, |m| m)?;
+                    (5, range)
+                }
+                Payload::GlobalSection(reader) => {
+                    let range = reader.range();
+                    module.globals = read_items(reader, |g| g)?;
+                    (6, range)
+                }
+                Payload::ExportSection(reader) => {
+                    let range = reader.range();
+                    module.exports = read_items(reader, |e| e)?;
+                    (7, range)
+                }
+                Payload::StartSection { func, range } => {
+                    module.start = Some(Item {
+                        value: func,
+                        range: range.clone(),
+                    });
+                    (8, range)
+                }
+                Payload::ElementSection(reader) => {
+                    let range = reader.range();
+                    for element in reader {
+                        let element = element?;
+                        module.elements.push(Item {
+                            range: element.range.clone(),
+                            value: element,
+                        });
+                    }
+                    (9, range)
+                }
+                Payload::CodeSectionStart { range, .. } => (10, range),
+                Payload::CodeSectionEntry(body) => {
+                    module.bodies.push(body);
+                    continue;
+                }
+                Payload::DataSection(reader) => {
+                    let range = reader.range();
+                    for d in reader {
+                        let d = d?;
+                        module.datas.push(Item {
+                            range: d.range.clone(),
+                            value: d,
+                        });
+                    }
+                    (11, range)
+                }
+                Payload::DataCountSection { range, .. } => (12, range),
+                Payload::TagSection(reader) => {
+                    let range = reader.range();
+                    module.tags = read_items(reader, |t| t)?;
+                    (13, range)
+                }
+                Payload::CustomSection(reader) => {
+                    let range = reader.range();
+                    if reader.name() == "name" {
+                        // A broken name section shouldn't prevent analysis.
+                        if let Err(err) = module.names.read(&reader) {
+                            module.names_error = Some(err.into());
+                        }
+                    }
+                    module.customs.push(reader);
+                    (0, range)
+                }
+                Payload::UnknownSection { id, range, .. } => (id, range),
+                Payload::Version { .. } | Payload::End(_) => continue,
+                _ => {
+                    return Err(BinaryError {
+                        message: "components are not supported".to_string(),
+                        offset: 0,
+                    })
+                }
+            };
+            module.sections.push(SectionInfo { id, range });
+        }
+
+        Ok(module)
+    }
+
+    pub fn num_imported_funcs(&self) -> u32 {
+        self.imported_funcs.len() as u32
+    }
+
+    pub fn num_imported_tables(&self) -> u32 {
+        self.imported_tables.len() as u32
+    }
+
+    pub fn num_imported_memories(&self) -> u32 {
+        self.imported_memories.len() as u32
+    }
+
+    pub fn num_imported_globals(&self) -> u32 {
+        self.imported_globals.len() as u32
+    }
+
+    pub fn num_imported_tags(&self) -> u32 {
+        self.imported_tags.len() as u32
+    }
+
+    /// The total number of functions, including imports.
+    pub fn num_funcs(&self) -> u32 {
+        self.num_imported_funcs() + self.functions.len() as u32
+    }
+
+    pub fn num_tables(&self) -> u32 {
+        self.num_imported_tables() + self.tables.len() as u32
+    }
+
+    pub fn num_memories(&self) -> u32 {
+        self.num_imported_memories() + self.memories.len() as u32
+    }
+
+    pub fn num_globals(&self) -> u32 {
+        self.num_imported_globals() + self.globals.len() as u32
+    }
+
+    pub fn num_tags(&self) -> u32 {
+        self.num_imported_tags() + self.tags.len() as u32
+    }
+
+    /// Indices into `imports` of the imports of the given kind.
+    fn imported(&self, kind: ItemKind) -> &[usize] {
+        match kind {
+            ItemKind::Func => &self.imported_funcs,
+            ItemKind::Table => &self.imported_tables,
+            ItemKind::Memory => &self.imported_memories,
+            ItemKind::Global => &self.imported_globals,
+            ItemKind::Tag => &self.imported_tags,
+            _ => &[],
+        }
+    }
+
+    /// The number of imports of the given kind.
+    pub fn num_imported(&self, kind: ItemKind) -> u32 {
+        self.imported(kind).len() as u32
+    }
+
+    /// The total number of items of the given kind, including imports.
+    /// Always zero for locals, which are counted per function.
+    pub fn num_items(&self, kind: ItemKind) -> u32 {
+        match kind {
+            ItemKind::Type => self.types.len() as u32,
+            ItemKind::Func => self.num_funcs(),
+            ItemKind::Table => self.num_tables(),
+            ItemKind::Memory => self.num_memories(),
+            ItemKind::Global => self.num_globals(),
+            ItemKind::Tag => self.num_tags(),
+            ItemKind::Element => self.elements.len() as u32,
+            ItemKind::Data => self.datas.len() as u32,
+            ItemKind::Local => 0,
+        }
+    }
+
+    /// Every item in the module, ordered by kind and then index.
+    pub fn items(&self) -> impl Iterator<Item = ItemRef> + '_ {
+        ItemKind::MODULE_LEVEL
+            .into_iter()
+            .flat_map(|kind| (0..self.num_items(kind)).map(move |i| ItemRef::new(kind, i)))
+    }
+
+    /// Finds the import for the given item, if it is imported.
+    pub fn import_of(&self, item: ItemRef) -> Option<&Item<Import<'a>>> {
+        let import = *self.imported(item.kind).get(item.index as usize)?;
+        Some(&self.imports[import])
+    }
+
+    /// Finds the import for the given function index, if it is imported.
+    pub fn func_import(&self, func_idx: u32) -> Option<&Import<'a>> {
+        self.import_of(ItemRef::new(ItemKind::Func, func_idx))
+            .map(|i| &i.value)
+    }
+
+    /// The bytes that make up an item: its import entry if it is imported,
+    /// or else its entry in its section. For defined functions this is the
+    /// function body; the function section entry is not included.
+    pub fn item_range(&self, item: ItemRef) -> Option<Range<usize>> {
+        if let Some(import) = self.import_of(item) {
+            return Some(import.range.clone());
+        }
+        let defined = (item.index - self.num_imported(item.kind)) as usize;
+        match item.kind {
+            ItemKind::Type => self.types.get(defined).map(|i| i.range.clone()),
+            ItemKind::Func => self.bodies.get(defined).map(|b| b.range()),
+            ItemKind::Table => self.tables.get(defined).map(|i| i.range.clone()),
+            ItemKind::Memory => self.memories.get(defined).map(|i| i.range.clone()),
+            ItemKind::Global => self.globals.get(defined).map(|i| i.range.clone()),
+            ItemKind::Tag => self.tags.get(defined).map(|i| i.range.clone()),
+            ItemKind::Element => self.elements.get(defined).map(|i| i.range.clone()),
+            ItemKind::Data => self.datas.get(defined).map(|i| i.range.clone()),
+            ItemKind::Local => None,
+        }
+    }
+
+    /// The number of bytes an item occupies in the binary. For defined
+    /// functions this includes the function section entry as well as the
+    /// body.
+    pub fn item_size(&self, item: ItemRef) -> usize {
+        let size = self.item_range(item).map_or(0, |r| r.len());
+        if item.kind == ItemKind::Func && item.index >= self.num_imported_funcs() {
+            let defined = (item.index - self.num_imported_funcs()) as usize;
+            size + self.functions.get(defined).map_or(0, |f| f.range.len())
+        } else {
+            size
+        }
+    }
+
+    /// A human-readable name for the item: its name from the name section if
+    /// it has one, else something like `global[3]`. Functions also fall back
+    /// to their import or export names.
+    pub fn display_name(&self, item: ItemRef) -> String {
+        let names = match item.kind {
+            ItemKind::Func => return self.func_display_name(item.index),
+            ItemKind::Type => &self.names.types,
+            ItemKind::Table => &self.names.tables,
+            ItemKind::Memory => &self.names.memories,
+            ItemKind::Global => &self.names.globals,
+            ItemKind::Element => &self.names.elements,
+            ItemKind::Data => &self.names.datas,
+            ItemKind::Tag => &self.names.tags,
+            ItemKind::Local => return format!("{}[{}]", item.kind.name(), item.index),
+        };
+        match names.get(&item.index) {
+            Some(name) => name.to_string(),
+            None => format!("{}[{}]", item.kind.name(), item.index),
+        }
+    }
+
+    /// The type index of the given function, including imported functions.
+    pub fn func_type_idx(&self, func_idx: u32) -> Option<u32> {
+        if func_idx < self.num_imported_funcs() {
+            match self.func_import(func_idx)?.ty {
+                TypeRef::Func(ty) => Some(ty),
+                _ => None,
+            }
+        } else {
+            let defined = (func_idx - self.num_imported_funcs()) as usize;
+            self.functions.get(defined).map(|f| f.value)
+        }
+    }
+
+    pub fn func_type(&self, func_idx: u32) -> Option<&FuncType> {
+        let ty = self.func_type_idx(func_idx)?;
+        self.types.get(ty as usize).map(|t| &t.value)
+    }
+
+    /// The body of the given function, or `None` if it is imported.
+    pub fn func_body(&self, func_idx: u32) -> Option<&FunctionBody<'a>> {
+        let defined = func_idx.checked_sub(self.num_imported_funcs())?;
+        self.bodies.get(defined as usize)
+    }
+
+    /// The `func_display_name` of every function, looking up the export
+    /// names once for the whole module rather than once per function.
+    pub fn func_display_names(&self) -> Vec<String> {
+        let mut exports = HashMap::new();
+        for export in self.exports.iter() {
+            if export.value.kind == ExternalKind::Func {
+                exports
+                    .entry(export.value.index)
+                    .or_insert(export.value.name);
+            }
+        }
+        (0..self.num_funcs())
+            .map(|f| {
+                if let Some(name) = self.names.functions.get(&f) {
+                    name.to_string()
+                } else if let Some(import) = self.func_import(f) {
+                    format!("{}.{}", import.module, import.name)
+                } else if let Some(name) = exports.get(&f) {
+                    name.to_string()
+                } else {
+                    format!("func[{}]", f)
+                }
+            })
+            .collect()
+    }
+
+    /// A human-readable name for the function: its name from the name
+    /// section, else its import or export name, else its index.
+    pub fn func_display_name(&self, func_idx: u32) -> String {
+        if let Some(name) = self.names.functions.get(&func_idx) {
+            return name.to_string();
+        }
+        if let Some(import) = self.func_import(func_idx) {
+            return format!("{}.{}", import.module, import.name);
+        }
+        let export = self
+            .exports
+            .iter()
+            .find(|e| e.value.kind == wasmparser::ExternalKind::Func && e.value.index == func_idx);
+        match export {
+            Some(export) => export.value.name.to_string(),
+            None => format!("func[{}]", func_idx),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Resolves the index spaces that imports and definitions share, so that
+//! callers don't have to offset indices by the number of imports.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use wasmparser::{
+    CodeSectionReader, CustomSectionReader, FromReader, Parser, Payload, SectionLimited,
+    TypeRef as ParserTypeRef,
+};
+
+use crate::module::{ItemKind, ItemRef, Names};
+use crate::types::{BinaryError, TypeRef};
+
+/// A function, table, memory, global or tag, whether imported or defined.
+#[wasmtools_struct]
+pub struct IndexEntry {
+    /// `func`, `table`, `memory`, `global` or `tag`.
+    pub kind: String,
+    /// The item's index in its index space, counting imports.
+    pub index: u32,
+    pub imported: bool,
+    /// The position of the item's entry in the import section if it is
+    /// imported, or else in its own section.
+    pub section_index: u32,
+    /// The byte offset of the item's entry in the import section or its own
+    /// section. For defined functions, this is the function section entry.
+    pub offset: usize,
+    /// The byte offset of a defined function's body.
+    pub body_offset: Option<usize>,
+    pub import_module: Option<String>,
+    pub import_name: Option<String>,
+    /// The item's type. For functions and tags, this refers to an entry in
+    /// the type section.
+    pub ty: TypeRef,
+    /// The item's name from the name section, or else its first export name.
+    pub name: Option<String>,
+    /// The item's name if it has one, or else e.g. `func[3]`.
+    pub display_name: String,
+}
+
+/// An import or definition as read from its section, before its index and
+/// name are known.
+struct Declared {
+    section_index: u32,
+    offset: usize,
+    import: Option<(String, String)>,
+    ty: TypeRef,
+}
+
+/// The index spaces of a module, built a section at a time so that it holds
+/// whatever could be decoded even if part of the module is broken.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct ModuleIndex {
+    imported: HashMap<ItemKind, Vec<Declared>>,
+    defined: HashMap<ItemKind, Vec<Declared>>,
+    body_offsets: Vec<usize>,
+    export_names: HashMap<ItemRef, String>,
+    names: HashMap<ItemRef, String>,
+    error: Option<BinaryError>,
+}
+
+const KINDS: [ItemKind; 5] = [
+    ItemKind::Func,
+    ItemKind::Table,
+    ItemKind::Memory,
+    ItemKind::Global,
+    ItemKind::Tag,
+];
+
+/// Reads the items of a section up to the first one that fails to decode,
+/// keeping the error in `error` unless there already is one.
+fn read_items<'a, T: FromReader<'a>>(
+    data: &'a [u8],
+    offset: usize,
+    error: &mut Option<BinaryError>,
+) -> Vec<(usize, T)> {
+    let mut items = Vec::new();
+    let reader = match SectionLimited::new(data, offset) {
+        Ok(reader) => reader,
+        Err(err) => {
+            error.get_or_insert(err.into());
+            return items;
+        }
+    };
+    for item in reader.into_iter_with_offsets() {
+        match item {
+            Ok(item) => items.push(item),
+            Err(err) => {
+                error.get_or_insert(err.into());
+                break;
+            }
+        }
+    }
+    items
+}
+
+impl ModuleIndex {
+    /// Builds the index of a complete module binary. Decoding stops at the
+    /// first error, which is kept in `error`.
+    pub fn read(data: &[u8]) -> ModuleIndex {
+        let mut index = ModuleIndex::default();
+        for payload in Parser::new(0).parse_all(data) {
+            let (id, range) = match payload {
+                Ok(Payload::ImportSection(reader)) => (2, reader.range()),
+                Ok(Payload::FunctionSection(reader)) => (3, reader.range()),
+                Ok(Payload::TableSection(reader)) => (4, reader.range()),
+                Ok(Payload::MemorySection(reader)) => (5, reader.range()),
+                Ok(Payload::GlobalSection(reader)) => (6, reader.range()),
+                Ok(Payload::ExportSection(reader)) => (7, reader.range()),
+                Ok(Payload::CodeSectionStart { range, .. }) => (10, range),
+                Ok(Payload::TagSection(reader)) => (13, reader.range()),
+                Ok(Payload::CustomSection(reader)) => (0, reader.range()),
+                Ok(_) => continue,
+                Err(err) => {
+                    index.error.get_or_insert(err.into());
+                    break;
+                }
+            };
+            index.add_section(id, &data[range.clone()], range.start);
+        }
+        index
+    }
+
+    fn declare(&mut self, kind: ItemKind, declared: Declared) {
+        let list = match declared.import {
+            Some(_) => self.imported.entry(kind).or_default(),
+            None => self.defined.entry(kind).or_default(),
+        };
+        list.push(declared);
+    }
+
+    fn define(&mut self, kind: ItemKind, defs: Vec<(usize, TypeRef)>) {
+        for (i, (offset, ty)) in defs.into_iter().enumerate() {
+            let declared = Declared {
+                section_index: i as u32,
+                offset,
+                import: None,
+                ty,
+            };
+            self.declare(kind, declared);
+        }
+    }
+
+    fn read_names(&mut self, data: &[u8], offset: usize) {
+        let reader = match CustomSectionReader::new(data, offset) {
+            Ok(reader) => reader,
+            Err(err) => {
+                self.error.get_or_insert(err.into());
+                return;
+            }
+        };
+        if reader.name() != "name" {
+            return;
+        }
+        let mut names = Names::default();
+        if let Err(err) = names.read(&reader) {
+            self.error.get_or_insert(err.into());
+        }
+        let maps = [
+            (ItemKind::Func, &names.functions),
+            (ItemKind::Table, &names.tables),
+            (ItemKind::Memory, &names.memories),
+            (ItemKind::Global, &names.globals),
+            (ItemKind::Tag, &names.tags),
+        ];
+        for (kind, map) in maps {
+            for (&index, name) in map {
+                self.names
+                    .insert(ItemRef::new(kind, index), name.to_string());
+            }
+        }
+    }
+
+    /// Every item of the given kind, imports first. Empty for kinds that
+    /// can't be imported.
+    pub fn entries(&self, kind: ItemKind) -> Vec<IndexEntry> {
+        (0..self.len(kind))
+            .filter_map(|i| self.get(ItemRef::new(kind, i)))
+            .collect()
+    }
+
+    pub fn get(&self, item: ItemRef) -> Option<IndexEntry> {
+        let imported = self.imported.get(&item.kind).map_or(&[][..], |i| i);
+        let (declared, defined) = match imported.get(item.index as usize) {
+            Some(declared) => (declared, None),
+            None => {
+                let defined = item.index as usize - imported.len();
+                (self.defined.get(&item.kind)?.get(defined)?, Some(defined))
+            }
+        };
+        let name = self
+            .names
+            .get(&item)
+            .or_else(|| self.export_names.get(&item))
+            .cloned();
+        let body_offset = match item.kind {
+            ItemKind::Func => defined.and_then(|i| self.body_offsets.get(i).copied()),
+            _ => None,
+        };
+        Some(IndexEntry {
+            kind: item.kind.name().to_string(),
+            index: item.index,
+            imported: declared.import.is_some(),
+            section_index: declared.section_index,
+            offset: declared.offset,
+            body_offset,
+            import_module: declared.import.as_ref().map(|i| i.0.clone()),
+            import_name: declared.import.as_ref().map(|i| i.1.clone()),
+            ty: declared.ty.clone(),
+            display_name: name
+                .clone()
+                .unwrap_or_else(|| format!("{}[{}]", item.kind.name(), item.index)),
+            name,
+        })
+    }
+
+    /// The number of items of the given kind, including imports.
+    pub fn len(&self, kind: ItemKind) -> u32 {
+        (self.imported.get(&kind).map_or(0, |i| i.len())
+            + self.defined.get(&kind).map_or(0, |d| d.len())) as u32
+    }
+
+    pub fn num_imported(&self, kind: ItemKind) -> u32 {
+        self.imported.get(&kind).map_or(0, |i| i.len()) as u32
+    }
+
+    /// The item defined by the entry at `section_index` in its own section,
+    /// e.g. the third entry of the function section.
+    pub fn defined(&self, kind: ItemKind, section_index: u32) -> Option<IndexEntry> {
+        let index = self.num_imported(kind).checked_add(section_index)?;
+        self.get(ItemRef::new(kind, index))
+    }
+}
+
+fn importable_kind(kind: &str) -> Result<ItemKind, BinaryError> {
+    match ItemKind::from_name(kind) {
+        Some(kind) if KINDS.contains(&kind) => Ok(kind),
+        _ => Err(BinaryError {
+            message: format!("{} is not an importable kind of item", kind),
+            offset: 0,
+        }),
+    }
+}
+
+#[wasm_bindgen]
+impl ModuleIndex {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ModuleIndex {
+        ModuleIndex::default()
+    }
+
+    /// Adds the items of a section, given its contents and their offset in
+    /// the module, as for the `parse_*_section` functions. Sections that
+    /// don't declare or name any items are ignored.
+    pub fn add_section(&mut self, id: u8, data: &[u8], offset: usize) {
+        let mut error = self.error.take();
+        match id {
+            0 => self.read_names(data, offset),
+            2 => {
+                let imports = read_items::<wasmparser::Import>(data, offset, &mut error);
+                for (i, (offset, import)) in imports.into_iter().enumerate() {
+                    let kind = match import.ty {
+                        ParserTypeRef::Func(_) => ItemKind::Func,
+                        ParserTypeRef::Table(_) => ItemKind::Table,
+                        ParserTypeRef::Memory(_) => ItemKind::Memory,
+                        ParserTypeRef::Global(_) => ItemKind::Global,
+                        ParserTypeRef::Tag(_) => ItemKind::Tag,
+                    };
+                    let declared = Declared {
+                        section_index: i as u32,
+                        offset,
+                        import: Some((import.module.to_string(), import.name.to_string())),
+                        ty: import.ty.into(),
+                    };
+                    self.declare(kind, declared);
+                }
+            }
+            3 => {
+                let funcs = read_items::<u32>(data, offset, &mut error);
+                let defs = funcs
+                    .into_iter()
+                    .map(|(offset, ty)| (offset, TypeRef::new_func(ty)))
+                    .collect();
+                self.define(ItemKind::Func, defs);
+            }
+            4 => {
+                let tables = read_items::<wasmparser::Table>(data, offset, &mut error);
+                let defs = tables
+                    .into_iter()
+                    .map(|(offset, t)| (offset, TypeRef::new_table(t.ty.into())))
+                    .collect();
+                self.define(ItemKind::Table, defs);
+            }
+            5 => {
+                let memories = read_items::<wasmparser::MemoryType>(data, offset, &mut error);
+                let defs = memories
+                    .into_iter()
+                    .map(|(offset, m)| (offset, TypeRef::new_memory(m.into())))
+                    .collect();
+                self.define(ItemKind::Memory, defs);
+            }
+            6 => {
+                let globals = read_items::<wasmparser::Global>(data, offset, &mut error);
+                let defs = globals
+                    .into_iter()
+                    .map(|(offset, g)| (offset, TypeRef::new_global(g.ty.into())))
+                    .collect();
+                self.define(ItemKind::Global, defs);
+            }
+            7 => {
+                let exports = read_items::<wasmparser::Export>(data, offset, &mut error);
+                for (_, export) in exports {
+                    let item = ItemRef::new(export.kind.into(), export.index);
+                    self.export_names
+                        .entry(item)
+                        .or_insert_with(|| export.name.to_string());
+                }
+            }
+            10 => match CodeSectionReader::new(data, offset) {
+                Ok(reader) => {
+                    for body in reader {
+                        match body {
+                            Ok(body) => self.body_offsets.push(body.range().start),
+                            Err(err) => {
+                                error.get_or_insert(err.into());
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    error.get_or_insert(err.into());
+                }
+            },
+            13 => {
+                let tags = read_items::<wasmparser::TagType>(data, offset, &mut error);
+                let defs = tags
+                    .into_iter()
+                    .map(|(offset, t)| (offset, TypeRef::new_tag(t.into())))
+                    .collect();
+                self.define(ItemKind::Tag, defs);
+            }
+            _ => {}
+        }
+        if let Some(err) = error {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    /// The first error that stopped part of the module from being indexed,
+    /// if any. The items decoded before it are still in the index.
+    #[wasm_bindgen(getter)]
+    pub fn error(&self) -> Option<BinaryError> {
+        self.error.clone()
+    }
+
+    /// Looks up an item by its index, counting imports. `kind` is one of
+    /// `func`, `table`, `memory`, `global` or `tag`.
+    #[wasm_bindgen(js_name = get)]
+    pub fn js_get(&self, kind: &str, index: u32) -> Result<Option<IndexEntry>, BinaryError> {
+        Ok(self.get(ItemRef::new(importable_kind(kind)?, index)))
+    }
+
+    /// Looks up an item by the position of its entry in its own section,
+    /// rather than in the index space.
+    #[wasm_bindgen(js_name = defined)]
+    pub fn js_defined(
+        &self,
+        kind: &str,
+        section_index: u32,
+    ) -> Result<Option<IndexEntry>, BinaryError> {
+        Ok(self.defined(importable_kind(kind)?, section_index))
+    }
+
+    /// The number of items of the given kind, including imports. Together
+    /// with `get`, this visits the items without converting them all at once.
+    #[wasm_bindgen(js_name = length)]
+    pub fn js_len(&self, kind: &str) -> Result<u32, BinaryError> {
+        Ok(self.len(importable_kind(kind)?))
+    }
+
+    #[wasm_bindgen(js_name = num_imported)]
+    pub fn js_num_imported(&self, kind: &str) -> Result<u32, BinaryError> {
+        Ok(self.num_imported(importable_kind(kind)?))
+    }
+}
@@ -0,0 +1,203 @@
+//! Counts of the operators used in function bodies.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use wasmparser::{FunctionBody, Operator};
+
+use crate::module::ParsedModule;
+use crate::types::BinaryError;
+
+/// The kind of work an operator does, roughly following the instruction
+/// categories in the spec.
+fn category(proposal: &str, op: &str) -> &'static str {
+    match proposal {
+        "simd" | "relaxed_simd" => return "simd",
+        "threads" => return "atomics",
+        "gc" => return "gc",
+        _ => {}
+    }
+    match op {
+        "Unreachable" | "Nop" | "Block" | "Loop" | "If" | "Else" | "Try" | "Catch" | "Throw"
+        | "Rethrow" | "End" | "Br" | "BrIf" | "BrTable" | "Return" | "Call" | "CallIndirect"
+        | "ReturnCall" | "ReturnCallIndirect" | "Delegate" | "CatchAll" | "CallRef"
+        | "ReturnCallRef" | "BrOnNull" | "BrOnNonNull" => "control",
+        "Drop" | "Select" | "TypedSelect" => "parametric",
+        "LocalGet" | "LocalSet" | "LocalTee" | "GlobalGet" | "GlobalSet" => "variable",
+        "ElemDrop" => "table",
+        "DataDrop" => "memory",
+        _ if op.starts_with("Table") => "table",
+        _ if op.starts_with("Ref") => "reference",
+        _ if op.starts_with("Memory") || op.contains("Load") || op.contains("Store") => "memory",
+        _ => "numeric",
+    }
+}
+
+macro_rules! classify {
+    ($(@$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => (
+        /// Returns an operator's name, the proposal that introduced it (`mvp`
+        /// for the original spec), and its category.
+        fn classify(op: &Operator) -> (&'static str, &'static str, &'static str) {
+            match op {
+                $(
+                    Operator::$op$({ $($arg: _,)* })? => (
+                        wasmprinter::operator_name!($op),
+                        stringify!($proposal),
+                        category(stringify!($proposal), stringify!($op)),
+                    ),
+                )*
+            }
+        }
+    );
+}
+wasmparser::for_each_operator!(classify);
+
+/// The number of operators that share an opcode, category or proposal.
+#[wasmtools_struct]
+pub struct OperatorCount {
+    pub key: String,
+    pub count: u32,
+}
+
+/// Operator counts for a function body, or for a whole module.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct OperatorStats {
+    total: u32,
+    by_opcode: HashMap<&'static str, u32>,
+    by_category: HashMap<&'static str, u32>,
+    by_proposal: HashMap<&'static str, u32>,
+}
+
+fn sorted_counts(counts: &HashMap<&'static str, u32>) -> Vec<OperatorCount> {
+    let mut counts: Vec<OperatorCount> = counts
+        .iter()
+        .map(|(key, &count)| OperatorCount {
+            key: key.to_string(),
+            count,
+        })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    counts
+}
+
+impl OperatorStats {
+    pub fn from_body(body: &FunctionBody) -> Result<OperatorStats, BinaryError> {
+        let mut stats = OperatorStats::default();
+        for op in body.get_operators_reader()? {
+            stats.add(&op?);
+        }
+        Ok(stats)
+    }
+
+    fn add(&mut self, op: &Operator) {
+        let (name, proposal, category) = classify(op);
+        self.total += 1;
+        *self.by_opcode.entry(name).or_default() += 1;
+        *self.by_category.entry(category).or_default() += 1;
+        *self.by_proposal.entry(proposal).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &OperatorStats) {
+        self.total += other.total;
+        for (mine, theirs) in [
+            (&mut self.by_opcode, &other.by_opcode),
+            (&mut self.by_category, &other.by_category),
+            (&mut self.by_proposal, &other.by_proposal),
+        ] {
+            for (key, count) in theirs.iter() {
+                *mine.entry(key).or_default() += count;
+            }
+        }
+    }
+
+    /// The counts for each opcode, most common first.
+    pub fn opcode_counts(&self) -> Vec<OperatorCount> {
+        sorted_counts(&self.by_opcode)
+    }
+
+    /// The counts for each category: `control`, `parametric`, `variable`,
+    /// `table`, `memory`, `reference`, `numeric`, `simd`, `atomics` or `gc`.
+    pub fn category_counts(&self) -> Vec<OperatorCount> {
+        sorted_counts(&self.by_category)
+    }
+
+    /// The counts for each proposal, named as in wasmparser, e.g.
+    /// `sign_extension`. Operators from the original spec are under `mvp`.
+    pub fn proposal_counts(&self) -> Vec<OperatorCount> {
+        sorted_counts(&self.by_proposal)
+    }
+}
+
+#[wasm_bindgen]
+impl OperatorStats {
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    pub fn count(&self, opcode: &str) -> u32 {
+        self.by_opcode.get(opcode).copied().unwrap_or(0)
+    }
+
+    #[wasm_bindgen(js_name = opcode_counts)]
+    pub fn js_opcode_counts(&self) -> OperatorCountArray {
+        self.opcode_counts().into()
+    }
+
+    #[wasm_bindgen(js_name = category_counts)]
+    pub fn js_category_counts(&self) -> OperatorCountArray {
+        self.category_counts().into()
+    }
+
+    #[wasm_bindgen(js_name = proposal_counts)]
+    pub fn js_proposal_counts(&self) -> OperatorCountArray {
+        self.proposal_counts().into()
+    }
+}
+
+/// Operator counts for every function body in a module. Constant expressions
+/// outside of function bodies are not counted.
+#[wasm_bindgen]
+pub struct ModuleOperatorStats {
+    module: OperatorStats,
+    functions: Vec<OperatorStats>,
+    num_imported_funcs: u32,
+}
+
+impl ModuleOperatorStats {
+    pub fn build(module: &ParsedModule) -> Result<ModuleOperatorStats, BinaryError> {
+        let mut stats = ModuleOperatorStats {
+            module: OperatorStats::default(),
+            functions: Vec::with_capacity(module.bodies.len()),
+            num_imported_funcs: module.num_imported_funcs(),
+        };
+        for body in module.bodies.iter() {
+            let func = OperatorStats::from_body(body)?;
+            stats.module.merge(&func);
+            stats.functions.push(func);
+        }
+        Ok(stats)
+    }
+
+    pub fn module_stats(&self) -> &OperatorStats {
+        &self.module
+    }
+
+    /// The counts for the given function, or `None` if it is imported.
+    pub fn function_stats(&self, func_index: u32) -> Option<&OperatorStats> {
+        let defined = func_index.checked_sub(self.num_imported_funcs)?;
+        self.functions.get(defined as usize)
+    }
+}
+
+#[wasm_bindgen]
+impl ModuleOperatorStats {
+    pub fn module(&self) -> OperatorStats {
+        self.module.clone()
+    }
+
+    pub fn function(&self, func_index: u32) -> Option<OperatorStats> {
+        self.function_stats(func_index).cloned()
+    }
+}
@@ -0,0 +1,173 @@
+//! Decoding the code section in independent parts, so that huge code sections
+//! can be spread over several web workers instead of blocking the UI thread.
+//!
+//! The main thread calls `partition_code_section` and hands each range's bytes
+//! to a worker with its own instance of this module. The worker calls
+//! `decode_code_range`, which needs nothing but those bytes, and posts back
+//! the JSON it returns. Every decoded body carries its `body_index`, so the
+//! results can be merged in any order.
+
+use js_sys::Array;
+use macros::*;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use wasmparser::{BinaryReader, FunctionBody as ParserFunctionBody};
+
+use crate::json::to_json;
+use crate::sections;
+use crate::types::*;
+
+/// A run of consecutive function bodies in the code section.
+#[wasmtools_struct]
+pub struct CodeRange {
+    /// The index in the code section of the first body in the range. Add the
+    /// number of imported functions to get its function index.
+    pub first_body: u32,
+    pub num_bodies: u32,
+    /// The start of the first body's size, as an offset into the module.
+    pub start: usize,
+    /// The end of the last body, as an offset into the module.
+    pub end: usize,
+}
+
+/// A function body decoded on its own, with the information needed to put
+/// it back in place.
+#[wasmtools_struct]
+pub struct DecodedBody {
+    /// The index of the body in the code section.
+    pub body_index: u32,
+    pub range: Range,
+    /// The number of locals, not counting the parameters.
+    pub num_locals: u32,
+    pub ops: Vec<OperatorResult>,
+}
+
+/// The version of `DecodedRangeJson`'s shape, stored in its `schema_version`.
+/// Any change to that shape must bump this, independently of the module
+/// export's `SCHEMA_VERSION`.
+pub const DECODED_RANGE_VERSION: u32 = 1;
+
+/// What `decode_code_range` returns, as JSON.
+#[derive(Serialize)]
+pub struct DecodedRangeJson {
+    pub schema_version: u32,
+    pub first_body: u32,
+    pub bodies: Vec<DecodedBodyResult>,
+}
+
+/// Splits the bodies of the code section into at most `parts` ranges that
+/// hold about the same number of bytes. Every range holds at least one body,
+/// so there are fewer ranges than `parts` if there are fewer bodies.
+pub fn partition_code_section(
+    data: &[u8],
+    offset: usize,
+    parts: u32,
+) -> Result<Vec<CodeRange>, BinaryError> {
+    let mut reader = BinaryReader::new_with_offset(data, offset);
+    let count = reader.read_var_u32()?;
+    let mut bodies = Vec::new();
+    for _ in 0..count {
+        let start = reader.original_position();
+        let size = reader.read_var_u32()?;
+        reader.read_bytes(size as usize)?;
+        bodies.push(start..reader.original_position());
+    }
+
+    let (first, last) = match (bodies.first(), bodies.last()) {
+        (Some(first), Some(last)) => (first.start, last.end),
+        _ => return Ok(Vec::new()),
+    };
+    let parts = (parts.max(1) as usize).min(bodies.len());
+    let target = (last - first + parts - 1) / parts;
+
+    let mut ranges: Vec<CodeRange> = Vec::with_capacity(parts);
+    for (i, body) in bodies.iter().enumerate() {
+        let full = match ranges.last() {
+            Some(range) => range.end - range.start >= target,
+            None => true,
+        };
+        // Leave at least one body for each of the remaining parts.
+        let remaining = bodies.len() - i;
+        let needed = parts - ranges.len();
+        if (full && ranges.len() < parts) || remaining == needed {
+            ranges.push(CodeRange {
+                first_body: i as u32,
+                num_bodies: 0,
+                start: body.start,
+                end: body.start,
+            });
+        }
+        let range = ranges.last_mut().unwrap();
+        range.num_bodies += 1;
+        range.end = body.end;
+    }
+    Ok(ranges)
+}
+
+/// Decodes every function body in `data`, which holds exactly the bytes of a
+/// range from `partition_code_section`, starting at `offset` in the module.
+/// Decoding stops at the first body whose size can't be read, leaving an
+/// error as the last item.
+pub fn decode_code_range(data: &[u8], offset: usize, first_body: u32) -> Vec<DecodedBodyResult> {
+    let mut reader = BinaryReader::new_with_offset(data, offset);
+    let mut results = Vec::new();
+    let mut body_index = first_body;
+    while !reader.eof() {
+        let (body_offset, body) = match read_body(&mut reader) {
+            Ok(body) => body,
+            Err(err) => {
+                results.push(DecodedBodyResult::Err(err));
+                break;
+            }
+        };
+        results.push(match decode_body(body_index, body_offset, body) {
+            Ok(body) => DecodedBodyResult::Ok(body),
+            Err(err) => DecodedBodyResult::Err(err),
+        });
+        body_index += 1;
+    }
+    results
+}
+
+/// Reads a body's size and contents, returning the offset of the contents.
+fn read_body<'a>(reader: &mut BinaryReader<'a>) -> Result<(usize, &'a [u8]), BinaryError> {
+    let size = reader.read_var_u32()?;
+    let offset = reader.original_position();
+    Ok((offset, reader.read_bytes(size as usize)?))
+}
+
+fn decode_body(body_index: u32, offset: usize, data: &[u8]) -> Result<DecodedBody, BinaryError> {
+    let body = ParserFunctionBody::new(offset, data);
+    let mut num_locals = 0u32;
+    for local in body.get_locals_reader()? {
+        let (count, _) = local?;
+        num_locals = num_locals.saturating_add(count);
+    }
+    let ops = match sections::function_body(data, offset) {
+        Ok(ops) => ops,
+        Err(err) => vec![OperatorResult::Err(err)],
+    };
+    Ok(DecodedBody {
+        body_index,
+        range: body.range().into(),
+        num_locals,
+        ops,
+    })
+}
+
+/// Decodes a range as `decode_code_range` does and serializes the result, so
+/// that it can be posted from a worker to the main thread.
+pub fn code_range_to_json(
+    data: &[u8],
+    offset: usize,
+    first_body: u32,
+) -> Result<String, BinaryError> {
+    to_json(
+        &DecodedRangeJson {
+            schema_version: DECODED_RANGE_VERSION,
+            first_body,
+            bodies: decode_code_range(data, offset, first_body),
+        },
+        false,
+    )
+}
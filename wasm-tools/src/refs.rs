@@ -0,0 +1,383 @@
+//! Finding the other items that an item refers to by index.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use wasmparser::{
+    BlockType, BrTable, ConstExpr, DataKind, ElementItems, ElementKind, HeapType, Ieee32, Ieee64,
+    MemArg, Operator, RefType, TableInit, TypeRef, ValType, V128,
+};
+
+use crate::module::{ItemKind, ItemRef, ParsedModule};
+use crate::types::BinaryError;
+
+/// A use of an item, at the given byte offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reference {
+    pub target: ItemRef,
+    pub offset: usize,
+}
+
+fn heap_type_ref(hty: HeapType) -> Option<u32> {
+    match hty {
+        HeapType::TypedFunc(t) => Some(t),
+        _ => None,
+    }
+}
+
+fn val_type_ref(ty: ValType) -> Option<ItemRef> {
+    match ty {
+        ValType::Ref(r) => heap_type_ref(r.heap_type()).map(|t| ItemRef::new(ItemKind::Type, t)),
+        _ => None,
+    }
+}
+
+/// An immediate argument of an operator, which may refer to an item.
+pub trait OperatorArg {
+    fn item_ref(&self, name: &str) -> Option<ItemRef>;
+}
+
+impl OperatorArg for u32 {
+    fn item_ref(&self, name: &str) -> Option<ItemRef> {
+        // Operators name their immediates consistently, so the name tells us
+        // which index space an immediate belongs to.
+        let kind = match name {
+            "function_index" => ItemKind::Func,
+            "type_index" => ItemKind::Type,
+            "table_index" | "table" | "dst_table" | "src_table" => ItemKind::Table,
+            "mem" | "dst_mem" | "src_mem" => ItemKind::Memory,
+            "global_index" => ItemKind::Global,
+            "tag_index" => ItemKind::Tag,
+            "elem_index" => ItemKind::Element,
+            "data_index" => ItemKind::Data,
+            "local_index" => ItemKind::Local,
+            _ => return None,
+        };
+        Some(ItemRef::new(kind, *self))
+    }
+}
+
+impl OperatorArg for MemArg {
+    fn item_ref(&self, _: &str) -> Option<ItemRef> {
+        Some(ItemRef::new(ItemKind::Memory, self.memory))
+    }
+}
+
+impl OperatorArg for BlockType {
+    fn item_ref(&self, _: &str) -> Option<ItemRef> {
+        match *self {
+            BlockType::FuncType(t) => Some(ItemRef::new(ItemKind::Type, t)),
+            BlockType::Type(ty) => val_type_ref(ty),
+            BlockType::Empty => None,
+        }
+    }
+}
+
+impl OperatorArg for HeapType {
+    fn item_ref(&self, _: &str) -> Option<ItemRef> {
+        heap_type_ref(*self).map(|t| ItemRef::new(ItemKind::Type, t))
+    }
+}
+
+impl OperatorArg for ValType {
+    fn item_ref(&self, _: &str) -> Option<ItemRef> {
+        val_type_ref(*self)
+    }
+}
+
+impl OperatorArg for RefType {
+    fn item_ref(&self, _: &str) -> Option<ItemRef> {
+        val_type_ref(ValType::Ref(*self))
+    }
+}
+
+macro_rules! no_refs {
+    ($($ty:ty)*) => ($(
+        impl OperatorArg for $ty {
+            fn item_ref(&self, _: &str) -> Option<ItemRef> {
+                None
+            }
+        }
+    )*);
+}
+no_refs!(u8 i32 i64 Ieee32 Ieee64 V128 BrTable<'_> [u8; 16]);
+
+macro_rules! operator_refs {
+    ($(@$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => (
+        /// Collects the items referred to by an operator's immediates. Locals
+        /// are included, relative to the function containing the operator.
+        pub fn operator_refs(op: &Operator, out: &mut Vec<ItemRef>) {
+            match op {
+                $(
+                    Operator::$op$({ $($arg),* })? => {
+                        $($(
+                            out.extend($arg.item_ref(stringify!($arg)));
+                        )*)?
+                    }
+                )*
+            }
+        }
+    );
+}
+wasmparser::for_each_operator!(operator_refs);
+
+/// How `Normalizer` describes indices of items other than types, which are
+/// always described by their structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexPolicy {
+    /// Keep indices as they are.
+    Keep,
+    /// Replace function and global indices with their names, so that
+    /// operators don't change just because an index shifted.
+    Names,
+    /// Replace every index except those of locals and memories with `_`.
+    Abstract,
+}
+
+/// Describes operators in a way that doesn't depend on the index space
+/// layout of the module, as far as the `IndexPolicy` allows, so that
+/// operators can be compared across functions and modules.
+pub struct Normalizer<'m, 'a> {
+    pub module: &'m ParsedModule<'a>,
+    pub indices: IndexPolicy,
+}
+
+impl Normalizer<'_, '_> {
+    fn arg(&self, arg: &dyn OperatorArg, name: &str, debug: String) -> String {
+        let item = match arg.item_ref(name) {
+            Some(item) => item,
+            None => return debug,
+        };
+        match (item.kind, self.indices) {
+            (ItemKind::Type, _) => match self.module.types.get(item.index as usize) {
+                Some(ty) => format!("{:?}", ty.value),
+                None => debug,
+            },
+            (ItemKind::Func | ItemKind::Global, IndexPolicy::Names) => {
+                format!("${}", self.module.display_name(item))
+            }
+            (ItemKind::Local | ItemKind::Memory, _) => debug,
+            (_, IndexPolicy::Abstract) => "_".to_string(),
+            _ => debug,
+        }
+    }
+}
+
+macro_rules! normalize {
+    ($(@$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => (
+        impl Normalizer<'_, '_> {
+            /// The operator's name followed by its normalized immediates.
+            pub fn operator(&self, op: &Operator) -> String {
+                match op {
+                    $(
+                        Operator::$op$({ $($arg),* })? => {
+                            #[allow(unused_mut)]
+                            let mut out = stringify!($op).to_string();
+                            $($(
+                                let arg = self.arg($arg, stringify!($arg), format!("{:?}", $arg));
+                                let _ = write!(out, " {}", arg);
+                            )*)?
+                            out
+                        }
+                    )*
+                }
+            }
+        }
+    );
+}
+wasmparser::for_each_operator!(normalize);
+
+fn const_expr_refs(expr: &ConstExpr, out: &mut Vec<Reference>) -> Result<(), BinaryError> {
+    let mut targets = Vec::new();
+    for op in expr.get_operators_reader().into_iter_with_offsets() {
+        let (op, offset) = op?;
+        operator_refs(&op, &mut targets);
+        out.extend(targets.drain(..).map(|target| Reference { target, offset }));
+    }
+    Ok(())
+}
+
+fn at(target: Option<ItemRef>, offset: usize) -> Option<Reference> {
+    target.map(|target| Reference { target, offset })
+}
+
+/// Finds every use of another item within the definition of `item`: in its
+/// type, its initializer, or its function body.
+///
+/// Imported items only refer to their types. Function bodies also report
+/// their uses of locals.
+pub fn references(module: &ParsedModule, item: ItemRef) -> Result<Vec<Reference>, BinaryError> {
+    let mut out = Vec::new();
+
+    if let Some(import) = module.import_of(item) {
+        let target = match import.value.ty {
+            TypeRef::Func(t) => Some(ItemRef::new(ItemKind::Type, t)),
+            TypeRef::Table(t) => val_type_ref(ValType::Ref(t.element_type)),
+            TypeRef::Global(g) => val_type_ref(g.content_type),
+            TypeRef::Tag(t) => Some(ItemRef::new(ItemKind::Type, t.func_type_idx)),
+            TypeRef::Memory(_) => None,
+        };
+        out.extend(at(target, import.range.start));
+        return Ok(out);
+    }
+
+    let defined = (item.index - module.num_imported(item.kind)) as usize;
+    match item.kind {
+        ItemKind::Type => {
+            if let Some(ty) = module.types.get(defined) {
+                for vt in ty.value.params().iter().chain(ty.value.results()) {
+                    out.extend(at(val_type_ref(*vt), ty.range.start));
+                }
+            }
+        }
+        ItemKind::Func => {
+            if let Some(f) = module.functions.get(defined) {
+                let ty = ItemRef::new(ItemKind::Type, f.value);
+                out.extend(at(Some(ty), f.range.start));
+            }
+            if let Some(body) = module.bodies.get(defined) {
+                let mut locals = body.get_locals_reader()?;
+                for _ in 0..locals.get_count() {
+                    let offset = locals.original_position();
+                    let (_, ty) = locals.read()?;
+                    out.extend(at(val_type_ref(ty), offset));
+                }
+
+                let mut targets = Vec::new();
+                for op in body.get_operators_reader()?.into_iter_with_offsets() {
+                    let (op, offset) = op?;
+                    operator_refs(&op, &mut targets);
+                    out.extend(targets.drain(..).map(|target| Reference { target, offset }));
+                }
+            }
+        }
+        ItemKind::Table => {
+            if let Some(table) = module.tables.get(defined) {
+                let ty = ValType::Ref(table.value.ty.element_type);
+                out.extend(at(val_type_ref(ty), table.range.start));
+                if let TableInit::Expr(expr) = &table.value.init {
+                    const_expr_refs(expr, &mut out)?;
+                }
+            }
+        }
+        ItemKind::Global => {
+            if let Some(global) = module.globals.get(defined) {
+                let ty = global.value.ty.content_type;
+                out.extend(at(val_type_ref(ty), global.range.start));
+                const_expr_refs(&global.value.init_expr, &mut out)?;
+            }
+        }
+        ItemKind::Tag => {
+            if let Some(tag) = module.tags.get(defined) {
+                let ty = ItemRef::new(ItemKind::Type, tag.value.func_type_idx);
+                out.extend(at(Some(ty), tag.range.start));
+            }
+        }
+        ItemKind::Element => {
+            if let Some(element) = module.elements.get(defined) {
+                let offset = element.range.start;
+                out.extend(at(val_type_ref(ValType::Ref(element.value.ty)), offset));
+                if let ElementKind::Active {
+                    table_index,
+                    offset_expr,
+                } = &element.value.kind
+                {
+                    let table = ItemRef::new(ItemKind::Table, table_index.unwrap_or(0));
+                    out.extend(at(Some(table), offset));
+                    const_expr_refs(offset_expr, &mut out)?;
+                }
+                match &element.value.items {
+                    ElementItems::Functions(funcs) => {
+                        for f in funcs.clone().into_iter_with_offsets() {
+                            let (offset, f) = f?;
+                            out.extend(at(Some(ItemRef::new(ItemKind::Func, f)), offset));
+                        }
+                    }
+                    ElementItems::Expressions(exprs) => {
+                        for expr in exprs.clone() {
+                            const_expr_refs(&expr?, &mut out)?;
+                        }
+                    }
+                }
+            }
+        }
+        ItemKind::Data => {
+            if let Some(data) = module.datas.get(defined) {
+                if let DataKind::Active {
+                    memory_index,
+                    offset_expr,
+                } = &data.value.kind
+                {
+                    let memory = ItemRef::new(ItemKind::Memory, *memory_index);
+                    out.extend(at(Some(memory), data.range.start));
+                    const_expr_refs(offset_expr, &mut out)?;
+                }
+            }
+        }
+        ItemKind::Memory | ItemKind::Local => {}
+    }
+    Ok(out)
+}
+
+/// The items the host can use directly: the module's exports and its start
+/// function.
+pub fn roots(module: &ParsedModule) -> Vec<ItemRef> {
+    let mut roots: Vec<ItemRef> = module
+        .exports
+        .iter()
+        .map(|e| ItemRef::new(e.value.kind.into(), e.value.index))
+        .collect();
+    if let Some(start) = &module.start {
+        roots.push(ItemRef::new(ItemKind::Func, start.value));
+    }
+    roots
+}
+
+/// Dependencies between items that aren't written as an index in the
+/// dependent item's definition, keyed by the dependent item.
+///
+/// A table depends on the active element segments that initialize it, and a
+/// memory on its active data segments, since those are applied whenever the
+/// table or memory is instantiated. A function depends on the declarative
+/// element segments that declare it, which `ref.func` requires.
+pub fn implicit_references(
+    module: &ParsedModule,
+) -> Result<HashMap<ItemRef, Vec<ItemRef>>, BinaryError> {
+    let mut out: HashMap<ItemRef, Vec<ItemRef>> = HashMap::new();
+    for (i, element) in module.elements.iter().enumerate() {
+        let segment = ItemRef::new(ItemKind::Element, i as u32);
+        match &element.value.kind {
+            ElementKind::Active { table_index, .. } => {
+                let table = ItemRef::new(ItemKind::Table, table_index.unwrap_or(0));
+                out.entry(table).or_default().push(segment);
+            }
+            ElementKind::Declared => match &element.value.items {
+                ElementItems::Functions(funcs) => {
+                    for f in funcs.clone() {
+                        let func = ItemRef::new(ItemKind::Func, f?);
+                        out.entry(func).or_default().push(segment);
+                    }
+                }
+                ElementItems::Expressions(exprs) => {
+                    let mut refs = Vec::new();
+                    for expr in exprs.clone() {
+                        const_expr_refs(&expr?, &mut refs)?;
+                    }
+                    for r in refs {
+                        if r.target.kind == ItemKind::Func {
+                            out.entry(r.target).or_default().push(segment);
+                        }
+                    }
+                }
+            },
+            ElementKind::Passive => {}
+        }
+    }
+    for (i, data) in module.datas.iter().enumerate() {
+        if let DataKind::Active { memory_index, .. } = data.value.kind {
+            let memory = ItemRef::new(ItemKind::Memory, memory_index);
+            let segment = ItemRef::new(ItemKind::Data, i as u32);
+            out.entry(memory).or_default().push(segment);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,233 @@
+//! Checks that a module can be re-encoded from its parsed representation
+//! without changing a byte.
+
+use js_sys::Array;
+use macros::*;
+use std::ops::Range;
+use wasm_bindgen::prelude::*;
+use wasmparser::BinaryReader;
+
+use crate::encode;
+use crate::module::{ItemKind, ItemRef, ParsedModule};
+use crate::types::BinaryError;
+
+/// Where the re-encoded module first differs from the original.
+#[wasmtools_struct]
+pub struct RoundTripMismatch {
+    /// The offset of the first byte that differs.
+    pub offset: usize,
+    /// The section the byte belongs to, e.g. `code`.
+    pub section: String,
+    /// The kind of item the byte belongs to, such as `func` or `export`, if
+    /// it isn't part of a section header or item count.
+    pub item_kind: Option<String>,
+    pub item_name: Option<String>,
+    pub item_start: Option<usize>,
+    pub item_end: Option<usize>,
+    /// The byte in the original module, unless the original section or
+    /// item ended first.
+    pub expected: Option<u8>,
+    /// The byte in the re-encoded module, unless the re-encoded section or
+    /// item ended first.
+    pub actual: Option<u8>,
+}
+
+#[wasmtools_struct]
+pub struct RoundTripReport {
+    /// Whether re-encoding produced exactly the original bytes.
+    pub identical: bool,
+    pub original_size: usize,
+    pub encoded_size: usize,
+    pub mismatch: Option<RoundTripMismatch>,
+}
+
+/// Finds the item that a byte of the module belongs to.
+fn locate(
+    module: &ParsedModule,
+    offset: usize,
+) -> (String, Option<(String, String, Range<usize>)>) {
+    let position = match module.sections.iter().position(|s| offset < s.range.end) {
+        Some(position) => position,
+        None => return ("end".to_string(), None),
+    };
+    let section = &module.sections[position];
+    if offset < section.range.start {
+        return (section.name().to_string(), None);
+    }
+
+    let item = |kind: ItemKind, index: usize| {
+        let item = ItemRef::new(kind, (index as u32) + module.num_imported(kind));
+        let range = module.item_range(item)?;
+        Some((kind.name().to_string(), module.display_name(item), range))
+    };
+    let find = |kind: ItemKind, ranges: Vec<Range<usize>>| {
+        let index = ranges.iter().position(|r| r.contains(&offset))?;
+        item(kind, index)
+    };
+    let found = match section.id {
+        1 => find(
+            ItemKind::Type,
+            module.types.iter().map(|i| i.range.clone()).collect(),
+        ),
+        2 => module
+            .imports
+            .iter()
+            .find(|i| i.range.contains(&offset))
+            .map(|i| {
+                let name = format!("{}.{}", i.value.module, i.value.name);
+                ("import".to_string(), name, i.range.clone())
+            }),
+        3 => {
+            let index = module
+                .functions
+                .iter()
+                .position(|f| f.range.contains(&offset));
+            index.map(|i| {
+                let func = ItemRef::new(ItemKind::Func, module.num_imported_funcs() + i as u32);
+                let range = module.functions[i].range.clone();
+                ("func".to_string(), module.display_name(func), range)
+            })
+        }
+        4 => find(
+            ItemKind::Table,
+            module.tables.iter().map(|i| i.range.clone()).collect(),
+        ),
+        5 => find(
+            ItemKind::Memory,
+            module.memories.iter().map(|i| i.range.clone()).collect(),
+        ),
+        6 => find(
+            ItemKind::Global,
+            module.globals.iter().map(|i| i.range.clone()).collect(),
+        ),
+        7 => module
+            .exports
+            .iter()
+            .find(|e| e.range.contains(&offset))
+            .map(|e| {
+                (
+                    "export".to_string(),
+                    e.value.name.to_string(),
+                    e.range.clone(),
+                )
+            }),
+        8 => module
+            .start
+            .as_ref()
+            .map(|s| ("start".to_string(), "start".to_string(), s.range.clone())),
+        9 => find(
+            ItemKind::Element,
+            module.elements.iter().map(|i| i.range.clone()).collect(),
+        ),
+        // A body's range doesn't include its size, which belongs to it too,
+        // so each body starts where the previous one ends.
+        10 => {
+            let data = &module.data[section.range.clone()];
+            let mut reader = BinaryReader::new_with_offset(data, section.range.start);
+            let first_body = reader.read_var_u32().map(|_| reader.original_position());
+            let i = module.bodies.iter().position(|b| offset < b.range().end);
+            let start = match i {
+                Some(0) => first_body.ok(),
+                Some(i) => Some(module.bodies[i - 1].range().end),
+                None => None,
+            };
+            match (i, start) {
+                (Some(i), Some(start)) if offset >= start => {
+                    let func = ItemRef::new(ItemKind::Func, module.num_imported_funcs() + i as u32);
+                    let range = start..module.bodies[i].range().end;
+                    Some(("func".to_string(), module.display_name(func), range))
+                }
+                _ => None,
+            }
+        }
+        11 => find(
+            ItemKind::Data,
+            module.datas.iter().map(|i| i.range.clone()).collect(),
+        ),
+        13 => find(
+            ItemKind::Tag,
+            module.tags.iter().map(|i| i.range.clone()).collect(),
+        ),
+        0 => {
+            let sections = module.sections[..position].iter();
+            let custom_index = sections.filter(|s| s.id == 0).count();
+            module
+                .customs
+                .get(custom_index)
+                .map(|c| ("custom".to_string(), c.name().to_string(), c.range()))
+        }
+        _ => None,
+    };
+    (section.name().to_string(), found)
+}
+
+/// The first position at which two byte strings differ, if they do.
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    let common = a.iter().zip(b).position(|(a, b)| a != b);
+    common.or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+}
+
+/// Re-encodes the module from the values wasmparser decoded, choosing the
+/// smallest encoding for everything, and compares the result with the
+/// original. A difference means either that the module uses a non-minimal
+/// encoding somewhere, or that something was lost in decoding.
+///
+/// Section contents are compared before section headers, so that a
+/// difference inside a section is reported there rather than at the section
+/// size that changes because of it.
+pub fn check_round_trip(module: &ParsedModule) -> Result<RoundTripReport, BinaryError> {
+    let original = module.data;
+    let mut encoded = original[..8].to_vec();
+    // The offset of the first difference in the original, with the bytes
+    // found there in each version.
+    let mut difference: Option<(usize, Option<u8>, Option<u8>)> = None;
+    let mut header_start = 8;
+    for section in module.sections.iter() {
+        let contents = encode::section_contents(module, section)?;
+        if difference.is_none() {
+            let old = &original[section.range.clone()];
+            let old_header = &original[header_start..section.range.start];
+            let mut header = vec![section.id];
+            encode::write_u32(&mut header, contents.len() as u32);
+            if let Some(i) = first_difference(old, &contents) {
+                difference = Some((
+                    section.range.start + i,
+                    old.get(i).copied(),
+                    contents.get(i).copied(),
+                ));
+            } else if let Some(i) = first_difference(old_header, &header) {
+                difference = Some((
+                    header_start + i,
+                    old_header.get(i).copied(),
+                    header.get(i).copied(),
+                ));
+            }
+        }
+        encode::write_section(&mut encoded, section.id, &contents);
+        header_start = section.range.end;
+    }
+
+    let mismatch = difference.map(|(offset, expected, actual)| {
+        let (section, item) = locate(module, offset);
+        let (item_kind, item_name, item_range) = match item {
+            Some((kind, name, range)) => (Some(kind), Some(name), Some(range)),
+            None => (None, None, None),
+        };
+        RoundTripMismatch {
+            offset,
+            section,
+            item_kind,
+            item_name,
+            item_start: item_range.as_ref().map(|r| r.start),
+            item_end: item_range.map(|r| r.end),
+            expected,
+            actual,
+        }
+    });
+    Ok(RoundTripReport {
+        identical: mismatch.is_none(),
+        original_size: original.len(),
+        encoded_size: encoded.len(),
+        mismatch,
+    })
+}
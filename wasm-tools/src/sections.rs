@@ -0,0 +1,226 @@
+//! Readers for each section of a module, producing the types from `types.rs`.
+//!
+//! These are the native halves of the `parse_*_section` functions exported to
+//! JS, which convert the results to JS arrays.
+
+use wasmparser::{
+    CodeSectionReader, CustomSectionReader, DataSectionReader, ElementSectionReader,
+    ExportSectionReader, FunctionBody as ParserFunctionBody, FunctionSectionReader,
+    GlobalSectionReader, ImportSectionReader, MemorySectionReader, NameSectionReader,
+    TableSectionReader, TagSectionReader, TypeSectionReader,
+};
+
+use crate::code_metadata::{CodeMetadataSectionReader, CODE_METADATA_PREFIX};
+use crate::types::*;
+
+pub fn type_section(data: &[u8], offset: usize) -> Result<Vec<TypeResult>, BinaryError> {
+    let reader = TypeSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((offset, v)) => TypeResult::Ok(Type {
+                t: v.into(),
+                offset: offset,
+            }),
+            Err(err) => TypeResult::Err(err.into()),
+        })
+        .collect::<Vec<TypeResult>>();
+    Ok(results)
+}
+
+pub fn import_section(data: &[u8], offset: usize) -> Result<Vec<ImportResult>, BinaryError> {
+    let reader = ImportSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((offset, v)) => ImportResult::Ok(Import::from_wasm(v, offset)),
+            Err(err) => ImportResult::Err(err.into()),
+        })
+        .collect::<Vec<ImportResult>>();
+    Ok(results)
+}
+
+pub fn function_section(data: &[u8], offset: usize) -> Result<Vec<FunctionResult>, BinaryError> {
+    let reader = FunctionSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((offset, v)) => FunctionResult::Ok(Function {
+                type_idx: v,
+                offset: offset,
+            }),
+            Err(err) => FunctionResult::Err(err.into()),
+        })
+        .collect::<Vec<FunctionResult>>();
+    Ok(results)
+}
+
+pub fn table_section(data: &[u8], offset: usize) -> Result<Vec<TableResult>, BinaryError> {
+    let reader = TableSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((offset, v)) => TableResult::Ok(Table::from_wasm(v, offset)),
+            Err(err) => TableResult::Err(err.into()),
+        })
+        .collect::<Vec<TableResult>>();
+    Ok(results)
+}
+
+pub fn memory_section(data: &[u8], offset: usize) -> Result<Vec<MemoryResult>, BinaryError> {
+    let reader = MemorySectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((offset, v)) => MemoryResult::Ok(Memory {
+                t: v.into(),
+                offset: offset,
+            }),
+            Err(err) => MemoryResult::Err(err.into()),
+        })
+        .collect::<Vec<MemoryResult>>();
+    Ok(results)
+}
+
+pub fn tag_section(data: &[u8], offset: usize) -> Result<Vec<TagTypeResult>, BinaryError> {
+    let reader = TagSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => TagTypeResult::Ok(v.into()),
+            Err(err) => TagTypeResult::Err(err.into()),
+        })
+        .collect::<Vec<TagTypeResult>>();
+    Ok(results)
+}
+
+pub fn global_section(data: &[u8], offset: usize) -> Result<Vec<GlobalResult>, BinaryError> {
+    let reader = GlobalSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((offset, v)) => GlobalResult::Ok(Global::from_wasm(v, offset)),
+            Err(err) => GlobalResult::Err(err.into()),
+        })
+        .collect::<Vec<GlobalResult>>();
+    Ok(results)
+}
+
+pub fn export_section(data: &[u8], offset: usize) -> Result<Vec<ExportResult>, BinaryError> {
+    let reader = ExportSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => ExportResult::Ok(v.into()),
+            Err(err) => ExportResult::Err(err.into()),
+        })
+        .collect::<Vec<ExportResult>>();
+    Ok(results)
+}
+
+pub fn element_section(data: &[u8], offset: usize) -> Result<Vec<ElementResult>, BinaryError> {
+    let reader = ElementSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => ElementResult::Ok(v.into()),
+            Err(err) => ElementResult::Err(err.into()),
+        })
+        .collect::<Vec<ElementResult>>();
+    Ok(results)
+}
+
+/// Reads the function bodies in the code section, without their operators.
+pub fn code_section(data: &[u8], offset: usize) -> Result<Vec<FunctionBodyResult>, BinaryError> {
+    let reader = CodeSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => FunctionBodyResult::Ok(v.into()),
+            Err(err) => FunctionBodyResult::Err(err.into()),
+        })
+        .collect::<Vec<FunctionBodyResult>>();
+    Ok(results)
+}
+
+pub fn function_body(data: &[u8], offset: usize) -> Result<Vec<OperatorResult>, BinaryError> {
+    let reader = ParserFunctionBody::new(offset, data);
+    let ops = reader
+        .get_operators_reader()?
+        .into_iter_with_offsets()
+        .map(|r| match r {
+            Ok((v, offset)) => OperatorResult::Ok(Operator::from_wasm(v, offset)),
+            Err(err) => OperatorResult::Err(err.into()),
+        })
+        .collect::<Vec<OperatorResult>>();
+    Ok(ops)
+}
+
+/// Reads the data segments, which share their contents with `data`.
+pub fn data_section(data: &Bytes, offset: usize) -> Result<Vec<DataResult>, BinaryError> {
+    let reader = DataSectionReader::new(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => DataResult::Ok(Data::from_wasm(v, data)),
+            Err(err) => DataResult::Err(err.into()),
+        })
+        .collect::<Vec<DataResult>>();
+    Ok(results)
+}
+
+/// Reads a custom section, which shares its contents with `data`.
+pub fn custom_section(data: &Bytes, offset: usize) -> Result<CustomSection, BinaryError> {
+    let reader = CustomSectionReader::new(data, offset)?;
+    Ok(CustomSection {
+        name: reader.name().to_string(),
+        data: data.part(reader.data()),
+    })
+}
+
+pub fn code_metadata_reader(
+    data: &[u8],
+    offset: usize,
+) -> Result<CodeMetadataSectionReader<'_>, BinaryError> {
+    let reader = CustomSectionReader::new(data, offset)?;
+    if !reader.name().starts_with(CODE_METADATA_PREFIX) {
+        return Err(BinaryError {
+            message: format!("\"{}\" is not a code metadata section", reader.name()),
+            offset: offset,
+        });
+    }
+    Ok(CodeMetadataSectionReader::new(
+        reader.data(),
+        reader.data_offset(),
+    )?)
+}
+
+pub fn code_metadata_section(
+    data: &[u8],
+    offset: usize,
+) -> Result<CodeMetadataSection, BinaryError> {
+    let name = CustomSectionReader::new(data, offset)?.name().to_string();
+    let reader = code_metadata_reader(data, offset)?;
+    let results = reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => FunctionCodeMetadataResult::Ok(v.into()),
+            Err(err) => FunctionCodeMetadataResult::Err(err.into()),
+        })
+        .collect::<Vec<FunctionCodeMetadataResult>>();
+    Ok(CodeMetadataSection {
+        kind: name[CODE_METADATA_PREFIX.len()..].to_string(),
+        funcs: results,
+    })
+}
+
+pub fn name_section(data: &[u8], offset: usize) -> Vec<NameResult> {
+    let reader = NameSectionReader::new(data, offset);
+    reader
+        .into_iter()
+        .map(|r| match r {
+            Ok(v) => NameResult::Ok(v.into()),
+            Err(err) => NameResult::Err(err.into()),
+        })
+        .collect::<Vec<NameResult>>()
+}
@@ -0,0 +1,311 @@
+//! Where a module's bytes go: the size of each item on its own, and the size
+//! of everything that would go away along with it.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+use crate::module::{ItemRef, ParsedModule};
+use crate::refs::{implicit_references, references, roots};
+use crate::types::BinaryError;
+
+/// An item in the size profile. Custom sections are included with the kind
+/// `custom`, numbered in the order they appear.
+#[wasmtools_struct]
+pub struct SizeEntry {
+    /// This entry's position in the profile, used to refer to it from
+    /// `dominator` and `dominated_by`.
+    pub id: usize,
+    pub kind: String,
+    pub index: u32,
+    pub name: String,
+    /// The number of bytes the item itself occupies.
+    pub shallow_size: usize,
+    /// The number of bytes that would be removed along with this item: its
+    /// own size plus that of every item only reachable through it.
+    pub retained_size: usize,
+    /// The entry that all paths from the module's roots to this one go
+    /// through, or `None` if it is referenced directly by a root.
+    pub dominator: Option<usize>,
+}
+
+#[wasm_bindgen]
+pub struct SizeProfile {
+    entries: Vec<SizeEntry>,
+    /// The ids of the entries immediately dominated by each entry.
+    dominated: Vec<Vec<usize>>,
+    total_size: usize,
+}
+
+/// The reference graph used for the profile. Node 0 is a synthetic root;
+/// node `i` is entry `i - 1`.
+struct Graph {
+    succs: Vec<Vec<usize>>,
+}
+
+impl Graph {
+    /// Orders the nodes reachable from the root so that each node comes
+    /// before its successors, except along back edges.
+    fn reverse_postorder(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.succs.len()];
+        let mut postorder = Vec::with_capacity(self.succs.len());
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((node, next)) = stack.last_mut() {
+            if let Some(&succ) = self.succs[*node].get(*next) {
+                *next += 1;
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                postorder.push(*node);
+                stack.pop();
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// Computes the immediate dominator of every node, using the iterative
+    /// algorithm from Cooper, Harvey and Kennedy's "A Simple, Fast Dominance
+    /// Algorithm". Unreachable nodes, and the root, are left as `None`.
+    fn immediate_dominators(&self, rpo: &[usize]) -> Vec<Option<usize>> {
+        let mut order = vec![usize::MAX; self.succs.len()];
+        for (i, &node) in rpo.iter().enumerate() {
+            order[node] = i;
+        }
+        let mut preds = vec![Vec::new(); self.succs.len()];
+        for &node in rpo {
+            for &succ in self.succs[node].iter() {
+                preds[succ].push(node);
+            }
+        }
+
+        let mut idom = vec![None; self.succs.len()];
+        idom[0] = Some(0);
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while order[a] > order[b] {
+                    a = idom[a].unwrap();
+                }
+                while order[b] > order[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &node in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in preds[node].iter() {
+                    if idom[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idom[node] != new_idom {
+                    idom[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[0] = None;
+        idom
+    }
+}
+
+impl SizeProfile {
+    /// Profiles every item and custom section in the module.
+    ///
+    /// Items are connected by the references in their definitions, and the
+    /// module's exports, start function and custom sections hang off a
+    /// synthetic root. Items that are unreachable from the root are attached
+    /// to it directly, so that every byte is accounted for somewhere.
+    pub fn build(module: &ParsedModule) -> Result<SizeProfile, BinaryError> {
+        let items: Vec<ItemRef> = module.items().collect();
+        let node_of: HashMap<ItemRef, usize> = items
+            .iter()
+            .enumerate()
+            .map(|(i, &item)| (item, i + 1))
+            .collect();
+        let num_nodes = 1 + items.len() + module.customs.len();
+
+        let mut entries = Vec::with_capacity(num_nodes - 1);
+        for (i, &item) in items.iter().enumerate() {
+            entries.push(SizeEntry {
+                id: i,
+                kind: item.kind.name().to_string(),
+                index: item.index,
+                name: module.display_name(item),
+                shallow_size: module.item_size(item),
+                retained_size: 0,
+                dominator: None,
+            });
+        }
+        for (i, custom) in module.customs.iter().enumerate() {
+            entries.push(SizeEntry {
+                id: entries.len(),
+                kind: "custom".to_string(),
+                index: i as u32,
+                name: custom.name().to_string(),
+                shallow_size: custom.range().len(),
+                retained_size: 0,
+                dominator: None,
+            });
+        }
+
+        let mut graph = Graph {
+            succs: vec![Vec::new(); num_nodes],
+        };
+        let implicit = implicit_references(module)?;
+        for (i, &item) in items.iter().enumerate() {
+            let succs = &mut graph.succs[i + 1];
+            for r in references(module, item)? {
+                succs.extend(node_of.get(&r.target));
+            }
+            for target in implicit.get(&item).into_iter().flatten() {
+                succs.extend(node_of.get(target));
+            }
+            succs.sort_unstable();
+            succs.dedup();
+        }
+        let root = &mut graph.succs[0];
+        root.extend(roots(module).iter().filter_map(|r| node_of.get(r)));
+        root.extend(1 + items.len()..num_nodes);
+
+        let mut rpo = graph.reverse_postorder();
+        if rpo.len() < num_nodes {
+            let mut reachable = vec![false; num_nodes];
+            for &node in rpo.iter() {
+                reachable[node] = true;
+            }
+            // Attaching one unreachable item may make others reachable, so
+            // only attach those that are still unreachable afterwards.
+            for node in 1..num_nodes {
+                if reachable[node] {
+                    continue;
+                }
+                graph.succs[0].push(node);
+                let mut stack = vec![node];
+                while let Some(n) = stack.pop() {
+                    if !reachable[n] {
+                        reachable[n] = true;
+                        stack.extend(graph.succs[n].iter().copied());
+                    }
+                }
+            }
+            rpo = graph.reverse_postorder();
+        }
+
+        let idom = graph.immediate_dominators(&rpo);
+        let mut retained: Vec<usize> = std::iter::once(0)
+            .chain(entries.iter().map(|e| e.shallow_size))
+            .collect();
+        for &node in rpo.iter().rev() {
+            if let Some(parent) = idom[node] {
+                retained[parent] += retained[node];
+            }
+        }
+
+        let mut dominated = vec![Vec::new(); entries.len()];
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.retained_size = retained[i + 1];
+            entry.dominator = match idom[i + 1] {
+                Some(0) | None => None,
+                Some(parent) => {
+                    dominated[parent - 1].push(i);
+                    Some(parent - 1)
+                }
+            };
+        }
+
+        Ok(SizeProfile {
+            entries,
+            dominated,
+            total_size: module.data.len(),
+        })
+    }
+
+    pub fn entries(&self) -> &[SizeEntry] {
+        &self.entries
+    }
+
+    /// The `n` largest entries by retained size, largest first.
+    pub fn top_retained(&self, n: usize) -> Vec<&SizeEntry> {
+        self.top_by(n, |e| (e.retained_size, e.shallow_size))
+    }
+
+    /// The `n` largest entries by shallow size, largest first.
+    pub fn top_shallow(&self, n: usize) -> Vec<&SizeEntry> {
+        self.top_by(n, |e| (e.shallow_size, e.retained_size))
+    }
+
+    fn top_by(&self, n: usize, key: impl Fn(&SizeEntry) -> (usize, usize)) -> Vec<&SizeEntry> {
+        let mut entries: Vec<&SizeEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| key(b).cmp(&key(a)).then(a.id.cmp(&b.id)));
+        entries.truncate(n);
+        entries
+    }
+
+    /// The entries immediately dominated by the given one, or by the root if
+    /// `id` is `None`, largest retained size first.
+    pub fn dominated_by(&self, id: Option<usize>) -> Vec<&SizeEntry> {
+        let mut entries: Vec<&SizeEntry> = match id {
+            Some(id) => self
+                .dominated
+                .get(id)
+                .into_iter()
+                .flatten()
+                .map(|&i| &self.entries[i])
+                .collect(),
+            None => self
+                .entries
+                .iter()
+                .filter(|e| e.dominator.is_none())
+                .collect(),
+        };
+        entries.sort_by(|a, b| b.retained_size.cmp(&a.retained_size).then(a.id.cmp(&b.id)));
+        entries
+    }
+}
+
+#[wasm_bindgen]
+impl SizeProfile {
+    #[wasm_bindgen(js_name = entries)]
+    pub fn js_entries(&self) -> SizeEntryArray {
+        self.entries.clone().into()
+    }
+
+    #[wasm_bindgen(js_name = top_retained)]
+    pub fn js_top_retained(&self, n: usize) -> SizeEntryArray {
+        let entries: Vec<SizeEntry> = self.top_retained(n).into_iter().cloned().collect();
+        entries.into()
+    }
+
+    #[wasm_bindgen(js_name = top_shallow)]
+    pub fn js_top_shallow(&self, n: usize) -> SizeEntryArray {
+        let entries: Vec<SizeEntry> = self.top_shallow(n).into_iter().cloned().collect();
+        entries.into()
+    }
+
+    /// The children of an entry in the dominator tree. Pass `undefined` to
+    /// get the top level of the tree.
+    #[wasm_bindgen(js_name = dominated_by)]
+    pub fn js_dominated_by(&self, id: Option<usize>) -> SizeEntryArray {
+        let entries: Vec<SizeEntry> = self.dominated_by(id).into_iter().cloned().collect();
+        entries.into()
+    }
+
+    /// The size of the whole binary, including section headers and other
+    /// bytes that don't belong to any one item.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+}
@@ -0,0 +1,140 @@
+//! The types on the operand stack at every instruction in a function.
+
+use js_sys::Array;
+use macros::*;
+use wasm_bindgen::prelude::*;
+use wasmparser::{
+    FuncValidator, FunctionBody, Parser, ValidPayload, Validator, ValidatorResources,
+    WasmModuleResources,
+};
+
+use crate::types::{BinaryError, Operator, ValType};
+use crate::util::wasm_features;
+
+#[wasm_bindgen]
+extern "C" {
+    /// The types on an operand stack, bottom first. A type is `undefined`
+    /// if it can't be known because the code is unreachable.
+    #[derive(Debug, Clone)]
+    #[wasm_bindgen(typescript_type = "Array<ValType | undefined>")]
+    pub type StackTypeArray;
+}
+
+/// The operand stack on either side of an operator.
+#[wasmtools_struct]
+pub struct OperatorStack {
+    pub operator: Operator,
+    /// The stack before the operator runs.
+    #[wasm_bindgen(skip)]
+    pub before: Vec<Option<ValType>>,
+    /// The stack after the operator runs.
+    #[wasm_bindgen(skip)]
+    pub after: Vec<Option<ValType>>,
+    /// Whether the operator is unreachable. The stack is then polymorphic:
+    /// below the values shown, it can be treated as holding any values the
+    /// operator needs.
+    pub unreachable: bool,
+}
+
+fn js_stack(types: &[Option<ValType>]) -> StackTypeArray {
+    let types: Array = types
+        .iter()
+        .map(|ty| match ty {
+            Some(ty) => JsValue::from(ty.clone()),
+            None => JsValue::UNDEFINED,
+        })
+        .collect();
+    types.unchecked_into()
+}
+
+#[wasm_bindgen]
+impl OperatorStack {
+    #[wasm_bindgen(getter = before)]
+    pub fn js_before(&self) -> StackTypeArray {
+        js_stack(&self.before)
+    }
+
+    #[wasm_bindgen(getter = after)]
+    pub fn js_after(&self) -> StackTypeArray {
+        js_stack(&self.after)
+    }
+}
+
+fn stack<T: WasmModuleResources>(func: &FuncValidator<T>) -> Vec<Option<ValType>> {
+    let height = func.operand_stack_height() as usize;
+    (0..height)
+        .rev()
+        .map(|depth| func.get_operand_type(depth).flatten().map(ValType::from))
+        .collect()
+}
+
+fn trace<T: WasmModuleResources>(
+    mut func: FuncValidator<T>,
+    body: &FunctionBody,
+) -> Vec<OperatorStackResult> {
+    let mut out = Vec::new();
+    let mut reader = body.get_binary_reader();
+    if let Err(err) = func.read_locals(&mut reader) {
+        out.push(OperatorStackResult::Err(err.into()));
+        return out;
+    }
+    while !reader.eof() {
+        let offset = reader.original_position();
+        let op = match reader.read_operator() {
+            Ok(op) => op,
+            Err(err) => {
+                out.push(OperatorStackResult::Err(err.into()));
+                return out;
+            }
+        };
+        let before = stack(&func);
+        let unreachable = func.get_control_frame(0).map_or(false, |f| f.unreachable);
+        if let Err(err) = func.op(offset, &op) {
+            out.push(OperatorStackResult::Err(err.into()));
+            return out;
+        }
+        out.push(OperatorStackResult::Ok(OperatorStack {
+            operator: Operator::from_wasm(op, offset),
+            before,
+            after: stack(&func),
+            unreachable,
+        }));
+    }
+    if let Err(err) = func.finish(reader.original_position()) {
+        out.push(OperatorStackResult::Err(err.into()));
+    }
+    out
+}
+
+/// Validates the module up to the given function, and returns a validator
+/// for the function along with its body.
+pub fn func_validator(
+    data: &[u8],
+    func_index: u32,
+) -> Result<(FuncValidator<ValidatorResources>, FunctionBody<'_>), BinaryError> {
+    let mut validator = Validator::new_with_features(wasm_features());
+    for payload in Parser::new(0).parse_all(data) {
+        if let ValidPayload::Func(func, body) = validator.payload(&payload?)? {
+            let func = func.into_validator(Default::default());
+            if func.index() == func_index {
+                return Ok((func, body));
+            }
+        }
+    }
+    Err(BinaryError {
+        message: format!("function {} has no body", func_index),
+        offset: 0,
+    })
+}
+
+/// Validates the module up to the given function, then follows the operand
+/// stack through the function's body, in the same way the validator checks
+/// it. Tracing stops at the first invalid operator, which is reported as an
+/// error at the end of the results.
+pub fn operator_stacks(
+    data: &[u8],
+    func_index: u32,
+) -> Result<Vec<OperatorStackResult>, BinaryError> {
+    let (func, body) = func_validator(data, func_index)?;
+    Ok(trace(func, &body))
+}
@@ -1,22 +1,30 @@
 use js_sys::Array;
 use macros::*;
-use std::ops::Range as StdRange;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+use std::ops::{Deref, Range as StdRange};
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasmparser::{
-    BinaryReaderError, ConstExpr as ParserConstExpr, Data as ParserData,
-    DataKind as ParserDataKind, Element as ParserElement, ElementItems as ParserElementItems,
-    ElementKind as ParserElementKind, Export as ParserExport, ExternalKind as ParserExternalKind,
-    FuncType as ParserFuncType, FunctionBody as ParserFunctionBody, Global as ParserGlobal,
-    GlobalType as ParserGlobalType, HeapType as ParserHeapType, Import as ParserImport,
-    IndirectNaming as ParserIndirectNaming, MemoryType as ParserMemoryType, Name as ParserName,
-    Naming as ParserNaming, Operator as ParserOperator, RefType as ParserRefType, SectionLimited,
-    Table as ParserTable, TableInit as ParserTableInit, TableType as ParserTableType,
-    TagKind as ParserTagKind, TagType as ParserTagType, Type as ParserType,
-    TypeRef as ParserTypeRef, ValType as ParserValType,
+    BinaryReaderError, BlockType as ParserBlockType, ConstExpr as ParserConstExpr,
+    Data as ParserData, DataKind as ParserDataKind, Element as ParserElement,
+    ElementItems as ParserElementItems, ElementKind as ParserElementKind, Export as ParserExport,
+    ExternalKind as ParserExternalKind, FuncType as ParserFuncType,
+    FunctionBody as ParserFunctionBody, Global as ParserGlobal, GlobalType as ParserGlobalType,
+    HeapType as ParserHeapType, Import as ParserImport, IndirectNaming as ParserIndirectNaming,
+    MemoryType as ParserMemoryType, Name as ParserName, Naming as ParserNaming,
+    Operator as ParserOperator, RefType as ParserRefType, SectionLimited, Table as ParserTable,
+    TableInit as ParserTableInit, TableType as ParserTableType, TagKind as ParserTagKind,
+    TagType as ParserTagType, Type as ParserType, TypeRef as ParserTypeRef,
+    ValType as ParserValType,
 };
 
+use crate::code_metadata::{
+    CodeMetadata as ParserCodeMetadata, FunctionCodeMetadata as ParserFunctionCodeMetadata,
+};
 use crate::names::op_name;
 
+#[derive(Debug, Clone)]
 #[wasm_bindgen(getter_with_clone)]
 pub struct BinaryError {
     pub message: String,
@@ -38,6 +46,16 @@ interface BinaryError {
 }
 "#;
 
+impl Serialize for BinaryError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("BinaryError", 3)?;
+        state.serialize_field("is_error", &true)?;
+        state.serialize_field("message", &self.message)?;
+        state.serialize_field("offset", &self.offset)?;
+        state.end()
+    }
+}
+
 impl From<BinaryReaderError> for BinaryError {
     fn from(value: BinaryReaderError) -> Self {
         BinaryError {
@@ -47,6 +65,72 @@ impl From<BinaryReaderError> for BinaryError {
     }
 }
 
+/// Bytes from a section or module that every item read from it shares, so
+/// that items like data segments don't each need a copy of their contents.
+#[derive(Clone)]
+pub struct Bytes {
+    buf: Rc<[u8]>,
+    range: StdRange<usize>,
+}
+
+impl Bytes {
+    /// The bytes in `range`, relative to the start of these ones.
+    pub fn slice(&self, range: StdRange<usize>) -> Bytes {
+        assert!(range.start <= range.end && range.end <= self.len());
+        Bytes {
+            buf: self.buf.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+
+    /// The same bytes as `part`, which must have been borrowed from these.
+    pub fn part(&self, part: &[u8]) -> Bytes {
+        let start = (part.as_ptr() as usize)
+            .checked_sub(self.as_ptr() as usize)
+            .expect("bytes are not part of this buffer");
+        self.slice(start..start + part.len())
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.range.clone()]
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(value: &[u8]) -> Self {
+        Bytes {
+            buf: value.into(),
+            range: 0..value.len(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(value: Vec<u8>) -> Self {
+        let len = value.len();
+        Bytes {
+            buf: value.into(),
+            range: 0..len,
+        }
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
 // Stuff in here is copy-pasted from wasm-tools and set up with macros to
 // alleviate the enormous amount of repetition it takes to send these types
 // to JS.
@@ -140,11 +224,32 @@ impl From<ParserValType> for ValType {
     }
 }
 
+/// The type of a `block`, `loop`, `if` or `try`.
+#[wasmtools_enum]
+pub enum BlockType {
+    /// The block produces no values.
+    empty,
+    /// The block produces a single value of the given type.
+    type_(ValType),
+    /// The block's parameters and results are given by a function type.
+    func_type(u32),
+}
+
+impl From<ParserBlockType> for BlockType {
+    fn from(value: ParserBlockType) -> Self {
+        match value {
+            ParserBlockType::Empty => BlockType::new_empty(),
+            ParserBlockType::Type(ty) => BlockType::new_type_(ty.into()),
+            ParserBlockType::FuncType(idx) => BlockType::new_func_type(idx),
+        }
+    }
+}
+
 /// Represents a type of a function in a WebAssembly module.
 #[wasmtools_struct]
 pub struct FuncType {
     /// The combined parameters and result types.
-    pub params_results: ValTypeArray,
+    pub params_results: Vec<ValType>,
     /// The number of parameter types.
     pub len_params: usize,
 }
@@ -154,14 +259,14 @@ impl From<ParserFuncType> for FuncType {
         let params_results = value.params().iter().chain(value.results().iter());
         let params_results: Vec<ValType> = params_results.map(|vt| (*vt).into()).collect();
         FuncType {
-            params_results: params_results.into(),
+            params_results,
             len_params: value.params().len(),
         }
     }
 }
 
 /// Represents a type in a WebAssembly module.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Type {
     pub t: TypeNoOffset,
     pub offset: usize,
@@ -203,7 +308,7 @@ impl From<ParserTypeRef> for TypeRef {
 }
 
 /// Represents an import in a WebAssembly module.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Import {
     /// The module being imported from.
     pub module: String,
@@ -280,7 +385,7 @@ impl From<ParserTagType> for TagType {
     }
 }
 
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Function {
     pub type_idx: u32,
     pub offset: usize,
@@ -307,7 +412,7 @@ impl From<ParserTableInit<'_>> for TableInit {
 
 /// Type information about a table defined in the table section of a WebAssembly
 /// module.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Table {
     /// The type of this table, including its element type and its limits.
     pub ty: TableType,
@@ -326,7 +431,7 @@ impl Table {
     }
 }
 
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Memory {
     pub t: MemoryType,
     pub offset: usize,
@@ -408,7 +513,7 @@ impl From<ParserConstExpr<'_>> for ConstExpr {
 }
 
 /// Represents a core WebAssembly global.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Global {
     /// The global's type.
     pub ty: GlobalType,
@@ -457,7 +562,7 @@ impl From<ParserExternalKind> for ExternalKind {
 }
 
 /// Represents an export in a WebAssembly module.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Export {
     /// The name of the exported item.
     pub name: String,
@@ -477,21 +582,18 @@ impl From<ParserExport<'_>> for Export {
     }
 }
 
-#[wasmtools_struct]
-pub struct ElementKindActive {
-    /// The index of the table being initialized.
-    pub table_index: u32,
-    /// The initial expression of the element segment.
-    pub offset_expr: ConstExpr,
-}
-
 /// The kind of element segment.
 #[wasmtools_enum]
 pub enum ElementKind {
     /// The element segment is passive.
     passive,
     /// The element segment is active.
-    active(ElementKindActive),
+    active {
+        /// The index of the table being initialized.
+        table_index: u32,
+        /// The initial expression of the element segment.
+        offset_expr: ConstExpr,
+    },
     /// The element segment is declared.
     declared,
 }
@@ -503,10 +605,7 @@ impl From<ParserElementKind<'_>> for ElementKind {
             ParserElementKind::Active {
                 table_index,
                 offset_expr,
-            } => ElementKind::new_active(ElementKindActive {
-                table_index: table_index.unwrap_or(0),
-                offset_expr: offset_expr.into(),
-            }),
+            } => ElementKind::new_active(table_index.unwrap_or(0), offset_expr.into()),
             ParserElementKind::Declared => ElementKind::new_declared(),
         }
     }
@@ -515,9 +614,10 @@ impl From<ParserElementKind<'_>> for ElementKind {
 /// Exists to hack around macro issues
 pub type U32Array = Vec<u32>;
 
+// wasm-bindgen passes a `Vec<u32>` to JS as a `Uint32Array`.
 #[wasm_bindgen(typescript_custom_section)]
 const _: &'static str = r#"
-export type U32Array = Array<number>;
+export type U32Array = Uint32Array;
 "#;
 
 /// Represents the items of an element segment.
@@ -526,7 +626,7 @@ pub enum ElementItems {
     /// This element contains function indices.
     functions(U32Array),
     /// This element contains constant expressions used to initialize the table.
-    expressions(ConstExprArray),
+    expressions(Vec<ConstExpr>),
 }
 
 impl From<ParserElementItems<'_>> for ElementItems {
@@ -538,14 +638,14 @@ impl From<ParserElementItems<'_>> for ElementItems {
             }
             ParserElementItems::Expressions(exprs) => {
                 let es: Vec<ConstExpr> = exprs.into_iter().map(|e| e.unwrap().into()).collect();
-                ElementItems::new_expressions(es.into())
+                ElementItems::new_expressions(es)
             }
         }
     }
 }
 
 /// Represents a core WebAssembly element segment.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Element {
     /// The kind of the element segment.
     pub kind: ElementKind,
@@ -582,40 +682,106 @@ impl From<StdRange<usize>> for Range {
     }
 }
 
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct FunctionBody {
     pub range: Range,
-    pub ops: OperatorResultArray,
+    pub ops: Vec<OperatorResult>,
 }
 
 impl From<ParserFunctionBody<'_>> for FunctionBody {
     fn from(value: ParserFunctionBody) -> Self {
         FunctionBody {
             range: value.range().into(),
-            ops: Vec::new().into(),
+            ops: Vec::new(),
         }
     }
 }
 
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Operator {
     pub name: String,
+    /// The byte offset of the operator within the module.
+    pub offset: usize,
+    /// The code metadata attached to this operator, if any.
+    pub metadata: Option<CodeMetadata>,
 }
 
-impl From<ParserOperator<'_>> for Operator {
-    fn from(value: ParserOperator) -> Self {
+impl Operator {
+    pub fn from_wasm(value: ParserOperator, offset: usize) -> Self {
         Operator {
             name: op_name(&value),
+            offset: offset,
+            metadata: None,
+        }
+    }
+}
+
+/// A single piece of metadata attached to an instruction by a
+/// `metadata.code.*` custom section.
+#[wasmtools_struct]
+pub struct CodeMetadata {
+    /// The byte offset of the annotated instruction, relative to the start of
+    /// the function body.
+    pub offset: u32,
+    /// The metadata payload.
+    pub data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl CodeMetadata {
+    /// The payload decoded as a branch hint: `true` if the branch is likely
+    /// taken, `false` if unlikely, and `undefined` if this isn't a valid hint.
+    #[wasm_bindgen(getter)]
+    pub fn branch_hint(&self) -> Option<bool> {
+        match self.data.as_slice() {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParserCodeMetadata<'_>> for CodeMetadata {
+    fn from(value: ParserCodeMetadata) -> Self {
+        CodeMetadata {
+            offset: value.offset,
+            data: value.data.to_vec(),
         }
     }
 }
 
+/// The code metadata for all annotated instructions in a single function.
 #[wasmtools_struct]
-pub struct DataKindActive {
-    /// The memory index for the data segment.
-    pub memory_index: u32,
-    /// The initialization expression for the data segment.
-    pub offset_expr: ConstExpr,
+pub struct FunctionCodeMetadata {
+    /// The index of the function, including imported functions.
+    pub func_index: u32,
+    pub metadata: Vec<CodeMetadataResult>,
+}
+
+impl From<ParserFunctionCodeMetadata<'_>> for FunctionCodeMetadata {
+    fn from(value: ParserFunctionCodeMetadata) -> Self {
+        let metadata = value
+            .metadata
+            .into_iter()
+            .map(|v| match v {
+                Ok(m) => CodeMetadataResult::Ok(m.into()),
+                Err(err) => CodeMetadataResult::Err(err.into()),
+            })
+            .collect::<Vec<CodeMetadataResult>>();
+        FunctionCodeMetadata {
+            func_index: value.func_index,
+            metadata,
+        }
+    }
+}
+
+/// A `metadata.code.*` custom section.
+#[wasmtools_struct]
+pub struct CodeMetadataSection {
+    /// The kind of metadata, i.e. the section name without the
+    /// `metadata.code.` prefix (e.g. `branch_hint`).
+    pub kind: String,
+    pub funcs: Vec<FunctionCodeMetadataResult>,
 }
 
 /// The kind of data segment.
@@ -624,7 +790,12 @@ pub enum DataKind {
     /// The data segment is passive.
     passive,
     /// The data segment is active.
-    active(DataKindActive),
+    active {
+        /// The memory index for the data segment.
+        memory_index: u32,
+        /// The initialization expression for the data segment.
+        offset_expr: ConstExpr,
+    },
 }
 
 impl From<ParserDataKind<'_>> for DataKind {
@@ -634,30 +805,28 @@ impl From<ParserDataKind<'_>> for DataKind {
             ParserDataKind::Active {
                 memory_index,
                 offset_expr,
-            } => DataKind::new_active(DataKindActive {
-                memory_index: memory_index,
-                offset_expr: offset_expr.into(),
-            }),
+            } => DataKind::new_active(memory_index, offset_expr.into()),
         }
     }
 }
 
 /// Represents a data segment in a core WebAssembly module.
-#[wasmtools_struct]
+#[wasmtools_struct(list)]
 pub struct Data {
     /// The kind of data segment.
     pub kind: DataKind,
     /// The data of the data segment.
-    pub data: Vec<u8>,
+    pub data: Bytes,
     // /// The range of the data segment.
     // pub range: Range<usize>,
 }
 
-impl From<ParserData<'_>> for Data {
-    fn from(value: ParserData) -> Self {
+impl Data {
+    /// Reads a data segment from `section`, sharing its bytes.
+    pub fn from_wasm(value: ParserData, section: &Bytes) -> Self {
         Data {
             kind: value.kind.into(),
-            data: value.data.to_vec(),
+            data: section.part(value.data),
         }
     }
 }
@@ -665,7 +834,7 @@ impl From<ParserData<'_>> for Data {
 #[wasmtools_struct]
 pub struct CustomSection {
     pub name: String,
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 /// Represents a name for an index from the names section.
@@ -686,17 +855,14 @@ impl From<ParserNaming<'_>> for Naming {
     }
 }
 
-impl From<SectionLimited<'_, ParserNaming<'_>>> for NamingResultArray {
-    fn from(value: SectionLimited<'_, ParserNaming>) -> Self {
-        let res = value
-            .into_iter()
-            .map(|v| match v {
-                Ok(naming) => NamingResult::Ok(naming.into()),
-                Err(err) => NamingResult::Err(err.into()),
-            })
-            .collect::<Vec<NamingResult>>();
-        res.into()
-    }
+fn naming_results(value: SectionLimited<'_, ParserNaming>) -> Vec<NamingResult> {
+    value
+        .into_iter()
+        .map(|v| match v {
+            Ok(naming) => NamingResult::Ok(naming.into()),
+            Err(err) => NamingResult::Err(err.into()),
+        })
+        .collect()
 }
 
 /// Represents an indirect name in the names custom section.
@@ -705,29 +871,28 @@ pub struct IndirectNaming {
     /// The indirect index of the name.
     pub index: u32,
     /// The map of names within the `index` prior.
-    pub names: NamingResultArray,
+    pub names: Vec<NamingResult>,
 }
 
 impl From<ParserIndirectNaming<'_>> for IndirectNaming {
     fn from(value: ParserIndirectNaming<'_>) -> Self {
         IndirectNaming {
             index: value.index,
-            names: value.names.into(),
+            names: naming_results(value.names),
         }
     }
 }
 
-impl From<SectionLimited<'_, ParserIndirectNaming<'_>>> for IndirectNamingResultArray {
-    fn from(value: SectionLimited<'_, ParserIndirectNaming>) -> Self {
-        let res = value
-            .into_iter()
-            .map(|v| match v {
-                Ok(naming) => IndirectNamingResult::Ok(naming.into()),
-                Err(err) => IndirectNamingResult::Err(err.into()),
-            })
-            .collect::<Vec<IndirectNamingResult>>();
-        res.into()
-    }
+fn indirect_naming_results(
+    value: SectionLimited<'_, ParserIndirectNaming>,
+) -> Vec<IndirectNamingResult> {
+    value
+        .into_iter()
+        .map(|v| match v {
+            Ok(naming) => IndirectNamingResult::Ok(naming.into()),
+            Err(err) => IndirectNamingResult::Err(err.into()),
+        })
+        .collect()
 }
 
 #[wasmtools_struct]
@@ -741,29 +906,38 @@ pub struct NameUnknown {
     // pub range: Range<usize>,
 }
 
+// Subsection IDs from the extended name section proposal:
+// https://github.com/WebAssembly/extended-name-section
+pub(crate) const NAME_SUBSECTION_FIELD: u8 = 10;
+pub(crate) const NAME_SUBSECTION_TAG: u8 = 11;
+
 /// Represents a name read from the names custom section.
-#[wasmtools_enum]
+#[wasmtools_enum(list)]
 pub enum Name {
     /// The name is for the module.
     module(String),
     /// The name is for the functions.
-    function(NamingResultArray),
+    function(Vec<NamingResult>),
     /// The name is for the function locals.
-    local(IndirectNamingResultArray),
+    local(Vec<IndirectNamingResult>),
     /// The name is for the function labels.
-    label(IndirectNamingResultArray),
+    label(Vec<IndirectNamingResult>),
     /// The name is for the types.
-    type_(NamingResultArray),
+    type_(Vec<NamingResult>),
     /// The name is for the tables.
-    table(NamingResultArray),
+    table(Vec<NamingResult>),
     /// The name is for the memories.
-    memory(NamingResultArray),
+    memory(Vec<NamingResult>),
     /// The name is for the globals.
-    global(NamingResultArray),
+    global(Vec<NamingResult>),
     /// The name is for the element segments.
-    element(NamingResultArray),
+    element(Vec<NamingResult>),
     /// The name is for the data segments.
-    data(NamingResultArray),
+    data(Vec<NamingResult>),
+    /// The name is for the fields of struct types.
+    field(Vec<IndirectNamingResult>),
+    /// The name is for the tags.
+    tag(Vec<NamingResult>),
     /// An unknown [name subsection](https://webassembly.github.io/spec/core/appendix/custom.html#subsections).
     unknown(NameUnknown),
 }
@@ -772,15 +946,33 @@ impl From<ParserName<'_>> for Name {
     fn from(value: ParserName) -> Self {
         match value {
             ParserName::Module { name, .. } => Name::new_module(name.to_string()),
-            ParserName::Function(m) => Name::new_function(m.into()),
-            ParserName::Local(m) => Name::new_local(m.into()),
-            ParserName::Label(m) => Name::new_label(m.into()),
-            ParserName::Type(m) => Name::new_type_(m.into()),
-            ParserName::Table(m) => Name::new_table(m.into()),
-            ParserName::Memory(m) => Name::new_memory(m.into()),
-            ParserName::Global(m) => Name::new_global(m.into()),
-            ParserName::Element(m) => Name::new_element(m.into()),
-            ParserName::Data(m) => Name::new_data(m.into()),
+            ParserName::Function(m) => Name::new_function(naming_results(m)),
+            ParserName::Local(m) => Name::new_local(indirect_naming_results(m)),
+            ParserName::Label(m) => Name::new_label(indirect_naming_results(m)),
+            ParserName::Type(m) => Name::new_type_(naming_results(m)),
+            ParserName::Table(m) => Name::new_table(naming_results(m)),
+            ParserName::Memory(m) => Name::new_memory(naming_results(m)),
+            ParserName::Global(m) => Name::new_global(naming_results(m)),
+            ParserName::Element(m) => Name::new_element(naming_results(m)),
+            ParserName::Data(m) => Name::new_data(naming_results(m)),
+            // wasmparser does not yet decode the subsections from the extended
+            // name section proposal, so we pick those out of the unknown ones.
+            ParserName::Unknown {
+                ty: NAME_SUBSECTION_FIELD,
+                data,
+                range,
+            } => Name::new_field(match SectionLimited::new(data, range.start) {
+                Ok(m) => indirect_naming_results(m),
+                Err(err) => vec![IndirectNamingResult::Err(err.into())],
+            }),
+            ParserName::Unknown {
+                ty: NAME_SUBSECTION_TAG,
+                data,
+                range,
+            } => Name::new_tag(match SectionLimited::new(data, range.start) {
+                Ok(m) => naming_results(m),
+                Err(err) => vec![NamingResult::Err(err.into())],
+            }),
             ParserName::Unknown { ty, data, .. } => Name::new_unknown(NameUnknown {
                 ty: ty,
                 data: data.to_vec(),
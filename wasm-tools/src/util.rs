@@ -0,0 +1,32 @@
+use wasmparser::WasmFeatures;
+
+/// Quotes and escapes a string for use as a Graphviz DOT ID or label.
+pub fn dot_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The proposals the viewer understands, for validating modules. This is
+/// wasmparser's defaults plus every proposal whose operators we can display.
+pub fn wasm_features() -> WasmFeatures {
+    WasmFeatures {
+        tail_call: true,
+        multi_memory: true,
+        exceptions: true,
+        memory64: true,
+        extended_const: true,
+        function_references: true,
+        memory_control: true,
+        ..Default::default()
+    }
+}
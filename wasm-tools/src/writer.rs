@@ -0,0 +1,340 @@
+//! Writes a new module binary with edits applied, such as stripping debug
+//! info or renaming exports.
+//!
+//! Sections that aren't edited are copied over byte for byte, so the output
+//! only differs from the input where the edits require it.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+use wasmparser::{BinaryReader, ExternalKind};
+
+use crate::encode::{external_kind, write_name, write_section, write_u32};
+use crate::module::{ItemKind, ParsedModule};
+use crate::types::{BinaryError, NAME_SUBSECTION_TAG};
+
+fn write_name_map(out: &mut Vec<u8>, names: &BTreeMap<u32, String>) {
+    write_u32(out, names.len() as u32);
+    for (index, name) in names {
+        write_u32(out, *index);
+        write_name(out, name);
+    }
+}
+
+/// A set of edits to make to a module. Edits are recorded first and then
+/// applied all at once by `apply`.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct ModuleEdits {
+    removed_customs: Vec<String>,
+    strip_debug_info: bool,
+    strip_names: bool,
+    dropped_exports: Vec<String>,
+    renamed_exports: Vec<(String, String)>,
+    /// `Some(None)` removes the module name.
+    module_name: Option<Option<String>>,
+    /// Names to set in the name section. `None` removes an item's name.
+    names: Vec<(ItemKind, u32, Option<String>)>,
+    local_names: Vec<(u32, u32, Option<String>)>,
+}
+
+/// A name section's names, in a form that can be edited and re-encoded.
+#[derive(Default)]
+struct NameMaps {
+    module: Option<String>,
+    maps: BTreeMap<u8, BTreeMap<u32, String>>,
+    locals: BTreeMap<u32, BTreeMap<u32, String>>,
+    /// Subsections that aren't modeled, such as label names, by id.
+    other: BTreeMap<u8, Vec<u8>>,
+}
+
+/// The id of the name subsection for each kind of item.
+fn name_subsection(kind: ItemKind) -> Option<u8> {
+    match kind {
+        ItemKind::Func => Some(1),
+        ItemKind::Type => Some(4),
+        ItemKind::Table => Some(5),
+        ItemKind::Memory => Some(6),
+        ItemKind::Global => Some(7),
+        ItemKind::Element => Some(8),
+        ItemKind::Data => Some(9),
+        ItemKind::Tag => Some(NAME_SUBSECTION_TAG),
+        ItemKind::Local => None,
+    }
+}
+
+impl NameMaps {
+    fn from_module(module: &ParsedModule) -> Result<NameMaps, BinaryError> {
+        fn owned(names: &HashMap<u32, &str>) -> BTreeMap<u32, String> {
+            names.iter().map(|(&i, &n)| (i, n.to_string())).collect()
+        }
+
+        // Rebuilding the section from what could be read would silently drop
+        // the rest of it.
+        if let Some(err) = &module.names_error {
+            return Err(BinaryError {
+                message: format!(
+                    "can't edit names, since the name section is broken: {}",
+                    err.message
+                ),
+                offset: err.offset,
+            });
+        }
+
+        let names = &module.names;
+        let mut maps = NameMaps {
+            module: names.module.map(|n| n.to_string()),
+            ..Default::default()
+        };
+        for (id, map) in [
+            (1, &names.functions),
+            (4, &names.types),
+            (5, &names.tables),
+            (6, &names.memories),
+            (7, &names.globals),
+            (8, &names.elements),
+            (9, &names.datas),
+            (NAME_SUBSECTION_TAG, &names.tags),
+        ] {
+            maps.maps.insert(id, owned(map));
+        }
+        for (&func, locals) in names.locals.iter() {
+            maps.locals.insert(func, owned(locals));
+        }
+
+        // Keep the subsections that `Names` doesn't cover as they are.
+        if let Some(section) = module.customs.iter().find(|c| c.name() == "name") {
+            let mut reader = BinaryReader::new_with_offset(section.data(), section.data_offset());
+            while !reader.eof() {
+                let id = reader.read_u8()?;
+                let size = reader.read_var_u32()?;
+                let contents = reader.read_bytes(size as usize)?;
+                if !matches!(id, 0 | 1 | 2 | 4..=9 | NAME_SUBSECTION_TAG) {
+                    maps.other.insert(id, contents.to_vec());
+                }
+            }
+        }
+        Ok(maps)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut subsections: BTreeMap<u8, Vec<u8>> = self.other.clone();
+        if let Some(name) = &self.module {
+            let mut contents = Vec::new();
+            write_name(&mut contents, name);
+            subsections.insert(0, contents);
+        }
+        let locals: BTreeMap<_, _> = self.locals.iter().filter(|(_, l)| !l.is_empty()).collect();
+        if !locals.is_empty() {
+            let mut contents = Vec::new();
+            write_u32(&mut contents, locals.len() as u32);
+            for (func, names) in locals {
+                write_u32(&mut contents, *func);
+                write_name_map(&mut contents, names);
+            }
+            subsections.insert(2, contents);
+        }
+        for (&id, names) in self.maps.iter() {
+            if !names.is_empty() {
+                let mut contents = Vec::new();
+                write_name_map(&mut contents, names);
+                subsections.insert(id, contents);
+            }
+        }
+
+        let mut out = Vec::new();
+        write_name(&mut out, "name");
+        for (id, contents) in subsections {
+            write_section(&mut out, id, &contents);
+        }
+        out
+    }
+}
+
+fn error(message: String) -> BinaryError {
+    BinaryError { message, offset: 0 }
+}
+
+impl ModuleEdits {
+    fn edits_names(&self) -> bool {
+        self.module_name.is_some() || !self.names.is_empty() || !self.local_names.is_empty()
+    }
+
+    fn edits_exports(&self) -> bool {
+        !self.dropped_exports.is_empty() || !self.renamed_exports.is_empty()
+    }
+
+    fn keeps_custom(&self, name: &str) -> bool {
+        if self.removed_customs.iter().any(|n| n == name) {
+            return false;
+        }
+        if self.strip_debug_info && name.starts_with(".debug_") {
+            return false;
+        }
+        if name == "name" {
+            return !self.strip_names;
+        }
+        true
+    }
+
+    fn encode_exports(&self, module: &ParsedModule) -> Result<Vec<u8>, BinaryError> {
+        for name in self.dropped_exports.iter() {
+            if !module.exports.iter().any(|e| e.value.name == name) {
+                return Err(error(format!("no export named {}", name)));
+            }
+        }
+        let mut exports: Vec<(&str, ExternalKind, u32)> = module
+            .exports
+            .iter()
+            .filter(|e| !self.dropped_exports.iter().any(|n| n == e.value.name))
+            .map(|e| (e.value.name, e.value.kind, e.value.index))
+            .collect();
+        // Renames all refer to the original names, so that e.g. two exports
+        // can swap names, and only the final names have to be unique.
+        let original: Vec<&str> = exports.iter().map(|e| e.0).collect();
+        let mut renamed = vec![false; exports.len()];
+        for (name, new_name) in self.renamed_exports.iter() {
+            match original.iter().position(|n| n == name) {
+                Some(i) if !renamed[i] => {
+                    exports[i].0 = new_name;
+                    renamed[i] = true;
+                }
+                Some(_) => return Err(error(format!("export {} is renamed twice", name))),
+                None => return Err(error(format!("no export named {}", name))),
+            }
+        }
+        let mut seen = HashSet::new();
+        for (name, _, _) in exports.iter() {
+            if !seen.insert(*name) {
+                return Err(error(format!("there is already an export named {}", name)));
+            }
+        }
+
+        let mut out = Vec::new();
+        write_u32(&mut out, exports.len() as u32);
+        for (name, kind, index) in exports {
+            write_name(&mut out, name);
+            out.push(external_kind(kind));
+            write_u32(&mut out, index);
+        }
+        Ok(out)
+    }
+
+    fn encode_names(&self, module: &ParsedModule) -> Result<Vec<u8>, BinaryError> {
+        let mut maps = NameMaps::from_module(module)?;
+        if let Some(name) = &self.module_name {
+            maps.module = name.clone();
+        }
+        for (kind, index, name) in self.names.iter() {
+            let id = name_subsection(*kind).unwrap();
+            let map = maps.maps.entry(id).or_default();
+            match name {
+                Some(name) => map.insert(*index, name.clone()),
+                None => map.remove(index),
+            };
+        }
+        for (func, local, name) in self.local_names.iter() {
+            let map = maps.locals.entry(*func).or_default();
+            match name {
+                Some(name) => map.insert(*local, name.clone()),
+                None => map.remove(local),
+            };
+        }
+        Ok(maps.encode())
+    }
+
+    /// Writes a copy of the module with the edits applied. If names are set
+    /// and the module has no name section, one is added at the end.
+    pub fn apply(&self, module: &ParsedModule) -> Result<Vec<u8>, BinaryError> {
+        let mut out = module.data[..8].to_vec();
+        let mut customs = module.customs.iter();
+        let mut wrote_names = false;
+        for section in module.sections.iter() {
+            let contents = &module.data[section.range.clone()];
+            match section.id {
+                0 => {
+                    let custom = customs.next().unwrap();
+                    if !self.keeps_custom(custom.name()) {
+                        continue;
+                    }
+                    if custom.name() == "name" && self.edits_names() && !wrote_names {
+                        write_section(&mut out, 0, &self.encode_names(module)?);
+                        wrote_names = true;
+                    } else {
+                        write_section(&mut out, 0, contents);
+                    }
+                }
+                7 if self.edits_exports() => {
+                    let exports = self.encode_exports(module)?;
+                    write_section(&mut out, 7, &exports);
+                }
+                id => write_section(&mut out, id, contents),
+            }
+        }
+        if self.edits_names() && !wrote_names && !self.strip_names {
+            write_section(&mut out, 0, &self.encode_names(module)?);
+        }
+        Ok(out)
+    }
+}
+
+#[wasm_bindgen]
+impl ModuleEdits {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ModuleEdits {
+        ModuleEdits::default()
+    }
+
+    /// Removes every custom section with the given name.
+    pub fn remove_custom_section(&mut self, name: String) {
+        self.removed_customs.push(name);
+    }
+
+    /// Removes the DWARF sections, i.e. the custom sections whose names start
+    /// with `.debug_`. The name section is kept.
+    pub fn strip_debug_info(&mut self) {
+        self.strip_debug_info = true;
+    }
+
+    /// Removes the name section. Any names set with `set_name` are ignored.
+    pub fn strip_names(&mut self) {
+        self.strip_names = true;
+    }
+
+    pub fn drop_export(&mut self, name: String) {
+        self.dropped_exports.push(name);
+    }
+
+    pub fn rename_export(&mut self, name: String, new_name: String) {
+        self.renamed_exports.push((name, new_name));
+    }
+
+    /// Sets the module's name in the name section, or removes it if `name`
+    /// is undefined.
+    pub fn set_module_name(&mut self, name: Option<String>) {
+        self.module_name = Some(name);
+    }
+
+    /// Sets an item's name in the name section, or removes it if `name` is
+    /// undefined. `kind` is one of `type`, `func`, `table`, `memory`,
+    /// `global`, `element`, `data` or `tag`.
+    pub fn set_name(
+        &mut self,
+        kind: &str,
+        index: u32,
+        name: Option<String>,
+    ) -> Result<(), BinaryError> {
+        match ItemKind::from_name(kind) {
+            Some(item_kind) if name_subsection(item_kind).is_some() => {
+                self.names.push((item_kind, index, name));
+                Ok(())
+            }
+            _ => Err(error(format!("{} items can't be named", kind))),
+        }
+    }
+
+    /// Sets the name of a local in the name section, or removes it if `name`
+    /// is undefined.
+    pub fn set_local_name(&mut self, func_index: u32, local_index: u32, name: Option<String>) {
+        self.local_names.push((func_index, local_index, name));
+    }
+}
@@ -0,0 +1,137 @@
+//! A cross-reference index recording every place each item is used.
+
+use js_sys::Array;
+use macros::*;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+use crate::module::{ItemKind, ItemRef, ParsedModule};
+use crate::refs::references;
+use crate::types::BinaryError;
+
+/// A use of an item.
+#[wasmtools_struct]
+pub struct XRef {
+    /// The kind of item the use appears in, such as `func` or `global`, or
+    /// `export` or `start` for uses outside of any item.
+    pub user_kind: String,
+    /// The index of the item the use appears in, or of the export.
+    pub user_index: u32,
+    pub user_name: String,
+    /// The byte offset of the use, e.g. of the instruction or the export
+    /// entry.
+    pub offset: usize,
+}
+
+#[wasm_bindgen]
+pub struct XRefIndex {
+    uses: HashMap<ItemRef, Vec<XRef>>,
+    /// Uses of locals, keyed by function index and local index.
+    local_uses: HashMap<(u32, u32), Vec<XRef>>,
+}
+
+impl XRefIndex {
+    pub fn build(module: &ParsedModule) -> Result<XRefIndex, BinaryError> {
+        let mut index = XRefIndex {
+            uses: HashMap::new(),
+            local_uses: HashMap::new(),
+        };
+
+        for (i, export) in module.exports.iter().enumerate() {
+            let target = ItemRef::new(export.value.kind.into(), export.value.index);
+            index.uses.entry(target).or_default().push(XRef {
+                user_kind: "export".to_string(),
+                user_index: i as u32,
+                user_name: export.value.name.to_string(),
+                offset: export.range.start,
+            });
+        }
+        if let Some(start) = &module.start {
+            let target = ItemRef::new(ItemKind::Func, start.value);
+            index.uses.entry(target).or_default().push(XRef {
+                user_kind: "start".to_string(),
+                user_index: 0,
+                user_name: "start".to_string(),
+                offset: start.range.start,
+            });
+        }
+
+        let func_names = module.func_display_names();
+        for user in module.items() {
+            let refs = references(module, user)?;
+            if refs.is_empty() {
+                continue;
+            }
+            let user_name = match func_names.get(user.index as usize) {
+                Some(name) if user.kind == ItemKind::Func => name.clone(),
+                _ => module.display_name(user),
+            };
+            for r in refs {
+                let xref = XRef {
+                    user_kind: user.kind.name().to_string(),
+                    user_index: user.index,
+                    user_name: user_name.clone(),
+                    offset: r.offset,
+                };
+                let uses = match r.target.kind {
+                    ItemKind::Local => index
+                        .local_uses
+                        .entry((user.index, r.target.index))
+                        .or_default(),
+                    _ => index.uses.entry(r.target).or_default(),
+                };
+                uses.push(xref);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Every use of the given item, in the order they appear in the module,
+    /// except that exports and the start function come first. Use
+    /// `local_references_to` for locals.
+    pub fn references_to(&self, item: ItemRef) -> &[XRef] {
+        self.uses.get(&item).map_or(&[], |uses| uses.as_slice())
+    }
+
+    /// Every use of a local within the function that declares it.
+    pub fn local_references_to(&self, func_index: u32, local_index: u32) -> &[XRef] {
+        self.local_uses
+            .get(&(func_index, local_index))
+            .map_or(&[], |uses| uses.as_slice())
+    }
+}
+
+#[wasm_bindgen]
+impl XRefIndex {
+    /// Finds every use of an item. `kind` is one of `type`, `func`, `table`,
+    /// `memory`, `global`, `tag`, `element` or `data`.
+    #[wasm_bindgen(js_name = references_to)]
+    pub fn js_references_to(&self, kind: &str, index: u32) -> Result<XRefArray, BinaryError> {
+        let kind = match ItemKind::from_name(kind) {
+            Some(ItemKind::Local) => {
+                return Err(BinaryError {
+                    message: "use local_references_to for locals".to_string(),
+                    offset: 0,
+                })
+            }
+            Some(kind) => kind,
+            None => {
+                return Err(BinaryError {
+                    message: format!("unknown item kind {}", kind),
+                    offset: 0,
+                })
+            }
+        };
+        Ok(self
+            .references_to(ItemRef::new(kind, index))
+            .to_vec()
+            .into())
+    }
+
+    #[wasm_bindgen(js_name = local_references_to)]
+    pub fn js_local_references_to(&self, func_index: u32, local_index: u32) -> XRefArray {
+        self.local_references_to(func_index, local_index)
+            .to_vec()
+            .into()
+    }
+}